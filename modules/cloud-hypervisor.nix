@@ -28,6 +28,8 @@ in
     environment.systemPackages = with pkgs; [
       # VMMs
       cloud-hypervisor #v0.50.2
+      # Thin vm disks (qcow2 overlays)
+      qemu-utils
 
       # Efi related?
       # OVMF-cloud-hypervisor
@@ -0,0 +1,75 @@
+/*
+* Forward the user terminal to a vm serial console.
+*/
+
+use crossterm::terminal;
+use std::io::Read;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+// Error Handling
+use miette::Result;
+use virshle_error::VirshleError;
+
+/// Ctrl-]
+pub const DETACH_KEY: u8 = 0x1d;
+
+/// Pipe the terminal to the console stream,
+/// until the detach key is pressed or the vm hangs up.
+pub async fn attach<S>(stream: S) -> Result<(), VirshleError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    // Read stdin from a detached thread rather than tokio::io::stdin,
+    // whose pending blocking read would hold the runtime on exit.
+    let (tx, mut rx) = mpsc::channel::<Vec<u8>>(32);
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buf = [0u8; 1024];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if tx.blocking_send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let mut output = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        tokio::io::copy(&mut reader, &mut stdout).await
+    });
+
+    println!("Attached to vm console, press Ctrl-] to detach.\r");
+    terminal::enable_raw_mode()?;
+    let res: Result<(), VirshleError> = async {
+        loop {
+            tokio::select! {
+                // Vm hung up.
+                _ = &mut output => break,
+                input = rx.recv() => {
+                    let Some(input) = input else { break };
+                    match input.iter().position(|e| *e == DETACH_KEY) {
+                        Some(i) => {
+                            writer.write_all(&input[..i]).await?;
+                            break;
+                        }
+                        None => writer.write_all(&input).await?,
+                    };
+                    writer.flush().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+    terminal::disable_raw_mode()?;
+    output.abort();
+    println!("\nDetached from vm console.");
+    res
+}
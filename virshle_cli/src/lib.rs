@@ -1,14 +1,16 @@
 #[cfg(test)]
 mod tests;
 
+mod console;
 mod types;
 pub use types::*;
 
 use virshle_core::{
     config::{Config, Definition, Node, VmTemplate},
-    hypervisor::{UserData, Vm, VmState, VmTable},
+    hypervisor::{PartitionReport, Snapshot, UserData, Vm, VmState, VmTable},
     peer::{HostCpu, HostDisk, HostRam, NodeInfo, Peer},
     utils::testing,
+    Image,
 };
 
 use clap::Parser;
@@ -130,6 +132,43 @@ impl Cli {
                     VmTemplate::display_by_peers(res).await?;
                 }
             },
+            /*
+             * Operations on cached base images
+             */
+            Commands::Image(args) => match args {
+                ImageArgs::Ls(args) => {
+                    let res = client.image().get().maybe_alias(args.peer).exec().await?;
+                    Image::display_by_peers(res)?;
+                }
+                ImageArgs::Pull(args) => {
+                    let mut sp = Spinner::new(spinners::Toggle5, "Pulling image...", None);
+                    let res: Image = client
+                        .image()
+                        .pull()
+                        .source(args.source)
+                        .maybe_sha256(args.sha256)
+                        .maybe_alias(args.current_workgin_node.peer)
+                        .exec()
+                        .await?;
+                    sp.clear();
+                    Image::display(&vec![res])?;
+                }
+                ImageArgs::Rm(args) => {
+                    let res: Image = client
+                        .image()
+                        .rm()
+                        .name(args.name)
+                        .maybe_alias(args.current_workgin_node.peer)
+                        .exec()
+                        .await?;
+                    Image::display(&vec![res])?;
+                }
+                ImageArgs::Prune(args) => {
+                    let res: Vec<Image> =
+                        client.image().prune().maybe_alias(args.peer).exec().await?;
+                    Image::display(&res)?;
+                }
+            },
             /*
              * Operations on virtual machines
              */
@@ -264,7 +303,6 @@ impl Cli {
                         Some(path) => Some(UserData::from_file(&path)?),
                         None => None,
                     };
-                    let methods = Server::new().config(&config).build()?.api()?;
 
                     if args.vm.name.is_some() || args.vm.uuid.is_some() || args.vm.id.is_some() {
                         // Spinner
@@ -283,23 +321,6 @@ impl Cli {
                             .exec()
                             .await;
 
-                        match args.attach {
-                            true => {
-                                // Bypass rest API,
-                                // and run on local node direcly.
-                                methods
-                                    .vm()
-                                    .start()
-                                    .one()
-                                    .maybe_id(args.vm.id)
-                                    .maybe_uuid(args.vm.uuid)
-                                    .maybe_name(args.vm.name.clone())
-                                    .maybe_user_data(user_data.clone())
-                                    .exec()
-                                    .await?;
-                            }
-                            _ => {}
-                        }
                         // Spinner
                         let message = printer
                             .res_vm()
@@ -308,6 +329,19 @@ impl Cli {
                             .content(&res)
                             .print()?;
                         sp.stop_and_persist(&message, "");
+
+                        if args.attach && res.is_ok() {
+                            let stream = client
+                                .vm()
+                                .console()
+                                .maybe_id(args.vm.id)
+                                .maybe_uuid(args.vm.uuid)
+                                .maybe_name(args.vm.name)
+                                .alias(&peer.alias)
+                                .exec()
+                                .await?;
+                            console::attach(stream).await?;
+                        }
                     } else if args.vm.state.is_some() || args.vm.account.is_some() {
                         // Spinner
                         let mut sp = Spinner::new(spinners::Toggle5, "Starting vms...", None);
@@ -327,16 +361,98 @@ impl Cli {
                     let tag = "shutdown";
 
                     // Set working node
-                    let cw_node = args.current_workgin_node.peer;
+                    let cw_node = args.vm.current_workgin_node.peer;
                     let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
 
-                    if args.name.is_some() || args.uuid.is_some() || args.id.is_some() {
+                    // Negative durations are clamped to an immediate fallback.
+                    let timeout = args.timeout.map(|e| e.as_secs().max(0) as u64);
+
+                    if args.vm.name.is_some() || args.vm.uuid.is_some() || args.vm.id.is_some() {
                         // Spinner
                         let mut sp = Spinner::new(spinners::Toggle5, "Shutting down vm...", None);
                         let res = client
                             .vm()
                             .shutdown()
                             .one()
+                            .maybe_id(args.vm.id)
+                            .maybe_uuid(args.vm.uuid)
+                            .maybe_name(args.vm.name)
+                            .maybe_timeout(timeout)
+                            .maybe_force(args.force)
+                            .exec()
+                            .await;
+
+                        // Report which path was taken.
+                        let tag = match &res {
+                            Ok((_, mode)) => format!("{tag}/{mode}"),
+                            Err(_) => tag.to_owned(),
+                        };
+                        let res = res.map(|(vm, _)| vm);
+
+                        // Spinner
+                        let message = printer
+                            .res_vm()
+                            .tag(&tag)
+                            .peer(&peer.alias)
+                            .content(&res)
+                            .print()?;
+
+                        sp.stop_and_persist(&message, "");
+                    } else if args.vm.state.is_some() || args.vm.account.is_some() {
+                        // Spinner
+                        let mut sp = Spinner::new(spinners::Toggle5, "Shutting down vms...", None);
+                        let res = client
+                            .vm()
+                            .shutdown()
+                            .many()
+                            .maybe_state(args.vm.state)
+                            .maybe_account(args.vm.account)
+                            .maybe_timeout(timeout)
+                            .maybe_force(args.force)
+                            .exec()
+                            .await?;
+
+                        // Report which path was taken by grouping vms by shutdown mode.
+                        let mut message = "".to_owned();
+                        for (peer, (res, modes)) in res.iter() {
+                            for (status, vms) in res.iter() {
+                                let mut groups: IndexMap<String, Vec<VmTable>> = IndexMap::new();
+                                for vm in vms {
+                                    let tag = match (status, modes.get(&vm.uuid)) {
+                                        (Status::Succeeded, Some(mode)) => format!("{tag}/{mode}"),
+                                        _ => tag.to_owned(),
+                                    };
+                                    groups.entry(tag).or_default().push(vm.to_owned());
+                                }
+                                for (tag, vms) in groups {
+                                    let content = IndexMap::from([(status.to_owned(), vms)]);
+                                    message += &printer
+                                        .indexmap()
+                                        .tag(&tag)
+                                        .peer(&peer.alias)
+                                        .content(&content)
+                                        .print()?;
+                                }
+                            }
+                        }
+                        // Spinner
+                        sp.stop_and_persist(&message, "");
+                    }
+                }
+                Crud::Pause(args) => {
+                    let tag = "pause";
+
+                    // Set working node
+                    let cw_node = args.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    if args.name.is_some() || args.uuid.is_some() || args.id.is_some() {
+                        // Spinner
+                        let mut sp = Spinner::new(spinners::Toggle5, "Pausing vm...", None);
+                        let res = client
+                            .vm()
+                            .pause()
+                            .one()
                             .maybe_id(args.id)
                             .maybe_uuid(args.uuid)
                             .maybe_name(args.name)
@@ -350,14 +466,57 @@ impl Cli {
                             .peer(&peer.alias)
                             .content(&res)
                             .print()?;
+                        sp.stop_and_persist(&message, "");
+                    } else if args.state.is_some() || args.account.is_some() {
+                        // Spinner
+                        let mut sp = Spinner::new(spinners::Toggle5, "Pausing vms...", None);
+                        let res = client
+                            .vm()
+                            .pause()
+                            .many()
+                            .maybe_state(args.state)
+                            .maybe_account(args.account)
+                            .exec()
+                            .await?;
+                        // Spinner
+                        let message = printer.by_peer_indexmap().tag(tag).content(&res).print()?;
+                        sp.stop_and_persist(&message, "");
+                    }
+                }
+                Crud::Reboot(args) => {
+                    let tag = "reboot";
 
+                    // Set working node
+                    let cw_node = args.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    if args.name.is_some() || args.uuid.is_some() || args.id.is_some() {
+                        // Spinner
+                        let mut sp = Spinner::new(spinners::Toggle5, "Rebooting vm...", None);
+                        let res = client
+                            .vm()
+                            .reboot()
+                            .one()
+                            .maybe_id(args.id)
+                            .maybe_uuid(args.uuid)
+                            .maybe_name(args.name)
+                            .exec()
+                            .await;
+
+                        // Spinner
+                        let message = printer
+                            .res_vm()
+                            .tag(tag)
+                            .peer(&peer.alias)
+                            .content(&res)
+                            .print()?;
                         sp.stop_and_persist(&message, "");
                     } else if args.state.is_some() || args.account.is_some() {
                         // Spinner
-                        let mut sp = Spinner::new(spinners::Toggle5, "Shutting down vms...", None);
+                        let mut sp = Spinner::new(spinners::Toggle5, "Rebooting vms...", None);
                         let res = client
                             .vm()
-                            .shutdown()
+                            .reboot()
                             .many()
                             .maybe_state(args.state)
                             .maybe_account(args.account)
@@ -368,6 +527,474 @@ impl Cli {
                         sp.stop_and_persist(&message, "");
                     }
                 }
+                Crud::Resume(args) => {
+                    let tag = "resume";
+
+                    // Set working node
+                    let cw_node = args.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    if args.name.is_some() || args.uuid.is_some() || args.id.is_some() {
+                        // Spinner
+                        let mut sp = Spinner::new(spinners::Toggle5, "Resuming vm...", None);
+                        let res = client
+                            .vm()
+                            .resume()
+                            .one()
+                            .maybe_id(args.id)
+                            .maybe_uuid(args.uuid)
+                            .maybe_name(args.name)
+                            .exec()
+                            .await;
+
+                        // Spinner
+                        let message = printer
+                            .res_vm()
+                            .tag(tag)
+                            .peer(&peer.alias)
+                            .content(&res)
+                            .print()?;
+                        sp.stop_and_persist(&message, "");
+                    } else if args.state.is_some() || args.account.is_some() {
+                        // Spinner
+                        let mut sp = Spinner::new(spinners::Toggle5, "Resuming vms...", None);
+                        let res = client
+                            .vm()
+                            .resume()
+                            .many()
+                            .maybe_state(args.state)
+                            .maybe_account(args.account)
+                            .exec()
+                            .await?;
+                        // Spinner
+                        let message = printer.by_peer_indexmap().tag(tag).content(&res).print()?;
+                        sp.stop_and_persist(&message, "");
+                    }
+                }
+                Crud::Snapshot(args) => {
+                    let tag = "snapshot";
+
+                    // Set working node
+                    let cw_node = args.vm.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    match args.tag {
+                        Some(snapshot_tag) => {
+                            // Spinner
+                            let mut sp =
+                                Spinner::new(spinners::Toggle5, "Taking vm snapshot...", None);
+                            let res = client
+                                .vm()
+                                .snapshot()
+                                .one()
+                                .maybe_id(args.vm.id)
+                                .maybe_uuid(args.vm.uuid)
+                                .maybe_name(args.vm.name)
+                                .tag(snapshot_tag)
+                                .alias(&peer.alias)
+                                .exec()
+                                .await;
+
+                            // Spinner
+                            let message = printer
+                                .res_snapshot()
+                                .tag(tag)
+                                .peer(&peer.alias)
+                                .content(&res)
+                                .print()?;
+                            sp.stop_and_persist(&message, "");
+                        }
+                        None => {
+                            let res = client
+                                .vm()
+                                .snapshot()
+                                .get()
+                                .maybe_id(args.vm.id)
+                                .maybe_uuid(args.vm.uuid)
+                                .maybe_name(args.vm.name)
+                                .alias(&peer.alias)
+                                .exec()
+                                .await?;
+                            if args.vm.format.ron == Some(true) {
+                                println!("{:#?}", res);
+                            } else if args.vm.format.json == Some(true) {
+                                let string = serde_json::to_string_pretty(&res).unwrap();
+                                println!("{}", string);
+                            } else {
+                                Snapshot::display(&res)?
+                            }
+                        }
+                    }
+                }
+                Crud::Restore(args) => {
+                    let tag = "restore";
+
+                    // Set working node
+                    let cw_node = args.vm.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    // Spinner
+                    let mut sp = Spinner::new(spinners::Toggle5, "Restoring vm...", None);
+                    let res = client
+                        .vm()
+                        .restore()
+                        .one()
+                        .maybe_id(args.vm.id)
+                        .maybe_uuid(args.vm.uuid)
+                        .maybe_name(args.vm.name)
+                        .tag(args.tag)
+                        .alias(&peer.alias)
+                        .exec()
+                        .await;
+
+                    // Spinner
+                    let message = printer
+                        .res_vm()
+                        .tag(tag)
+                        .peer(&peer.alias)
+                        .content(&res)
+                        .print()?;
+                    sp.stop_and_persist(&message, "");
+                }
+                Crud::Migrate(args) => {
+                    let tag = "migrate";
+
+                    // Set working node
+                    let cw_node = args.vm.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    // Spinner
+                    let mut sp = Spinner::new(spinners::Toggle5, "Migrating vm...", None);
+                    let res = client
+                        .vm()
+                        .migrate()
+                        .one()
+                        .maybe_id(args.vm.id)
+                        .maybe_uuid(args.vm.uuid)
+                        .maybe_name(args.vm.name)
+                        .maybe_port(args.port)
+                        .alias(&peer.alias)
+                        .to(&args.to)
+                        .exec()
+                        .await;
+
+                    // Spinner
+                    let message = printer
+                        .res_vm()
+                        .tag(tag)
+                        .peer(&args.to)
+                        .content(&res)
+                        .print()?;
+                    sp.stop_and_persist(&message, "");
+                }
+                Crud::Clone(args) => {
+                    let tag = "clone";
+
+                    // Set working node
+                    let cw_node = args.vm.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    let mut user_data = None;
+                    if let Some(user_data_path) = args.user_data {
+                        user_data = Some(UserData::from_file(&user_data_path)?);
+                    }
+
+                    // Spinner
+                    let mut sp = Spinner::new(spinners::Toggle5, "Cloning vm...", None);
+                    let res: IndexMap<Peer, IndexMap<Status, Vec<VmTable>>> = client
+                        .vm()
+                        .clone_as()
+                        .maybe_id(args.vm.id)
+                        .maybe_uuid(args.vm.uuid)
+                        .maybe_name(args.vm.name)
+                        .maybe_clone_name(args.clone_name)
+                        .maybe_n(args.ntimes)
+                        .maybe_user_data(user_data)
+                        .alias(&peer.alias)
+                        .exec()
+                        .await?;
+
+                    // Spinner
+                    let message = printer.by_peer_indexmap().tag(tag).content(&res).print()?;
+                    sp.stop_and_persist(&message, "");
+                }
+                Crud::Export(args) => {
+                    let tag = "export";
+
+                    // Set working node
+                    let cw_node = args.vm.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    // Spinner
+                    let mut sp = Spinner::new(spinners::Toggle5, "Exporting vm...", None);
+                    let res = client
+                        .vm()
+                        .export()
+                        .maybe_id(args.vm.id)
+                        .maybe_uuid(args.vm.uuid)
+                        .maybe_name(args.vm.name)
+                        .maybe_snapshot(args.snapshot)
+                        .output(&args.output)
+                        .alias(&peer.alias)
+                        .exec()
+                        .await;
+
+                    // Spinner
+                    let message = printer
+                        .res_archive()
+                        .tag(tag)
+                        .peer(&peer.alias)
+                        .path(&args.output)
+                        .content(&res)
+                        .print()?;
+                    sp.stop_and_persist(&message, "");
+                }
+                Crud::Import(args) => {
+                    let tag = "import";
+
+                    // Set working node
+                    let cw_node = args.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    // Spinner
+                    let mut sp = Spinner::new(spinners::Toggle5, "Importing vm...", None);
+                    let res = client
+                        .vm()
+                        .import()
+                        .input(&args.file)
+                        .maybe_keep_uuid(args.keep_uuid)
+                        .maybe_name(args.name)
+                        .alias(&peer.alias)
+                        .exec()
+                        .await;
+
+                    // Spinner
+                    let message = printer
+                        .res_vm()
+                        .tag(tag)
+                        .peer(&peer.alias)
+                        .content(&res)
+                        .print()?;
+                    sp.stop_and_persist(&message, "");
+                }
+                Crud::Update(args) => {
+                    let tag = "update";
+
+                    // Set working node
+                    let cw_node = args.vm.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    let res = client
+                        .vm()
+                        .update()
+                        .one()
+                        .maybe_id(args.vm.id)
+                        .maybe_uuid(args.vm.uuid)
+                        .maybe_name(args.vm.name)
+                        .maybe_autostart(args.autostart)
+                        .maybe_security_groups(args.security_groups)
+                        .maybe_net_name(args.net)
+                        .maybe_ingress(args.ingress)
+                        .maybe_egress(args.egress)
+                        .maybe_burst(args.burst)
+                        .alias(&peer.alias)
+                        .exec()
+                        .await;
+
+                    let message = printer
+                        .res_vm()
+                        .tag(tag)
+                        .peer(&peer.alias)
+                        .content(&res)
+                        .print()?;
+                    println!("{}", message);
+                }
+                Crud::Resize(args) => {
+                    let tag = "resize";
+
+                    // Set working node
+                    let cw_node = args.vm.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    let res = client
+                        .vm()
+                        .resize()
+                        .one()
+                        .maybe_id(args.vm.id)
+                        .maybe_uuid(args.vm.uuid)
+                        .maybe_name(args.vm.name)
+                        .maybe_vcpu(args.vcpu)
+                        .maybe_vram(args.vram)
+                        .alias(&peer.alias)
+                        .exec()
+                        .await;
+
+                    let message = printer
+                        .res_vm()
+                        .tag(tag)
+                        .peer(&peer.alias)
+                        .content(&res)
+                        .print()?;
+                    println!("{}", message);
+                }
+                Crud::Disk(args) => match args {
+                    DiskArgs::Add(args) => {
+                        let tag = "disk/add";
+
+                        // Set working node
+                        let cw_node = args.vm.current_workgin_node.peer;
+                        let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                        let res = client
+                            .vm()
+                            .disk()
+                            .add()
+                            .maybe_id(args.vm.id)
+                            .maybe_uuid(args.vm.uuid)
+                            .maybe_name(args.vm.name)
+                            .disk_name(args.name_disk)
+                            .disk_size(args.size)
+                            .alias(&peer.alias)
+                            .exec()
+                            .await;
+
+                        let message = printer
+                            .res_vm()
+                            .tag(tag)
+                            .peer(&peer.alias)
+                            .content(&res)
+                            .print()?;
+                        println!("{}", message);
+                    }
+                    DiskArgs::Resize(args) => {
+                        let tag = "disk/resize";
+
+                        // Set working node
+                        let cw_node = args.vm.current_workgin_node.peer;
+                        let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                        let res = client
+                            .vm()
+                            .disk()
+                            .resize()
+                            .maybe_id(args.vm.id)
+                            .maybe_uuid(args.vm.uuid)
+                            .maybe_name(args.vm.name)
+                            .disk_name(args.name_disk)
+                            .disk_size(args.size)
+                            .alias(&peer.alias)
+                            .exec()
+                            .await;
+
+                        let message = printer
+                            .res_vm()
+                            .tag(tag)
+                            .peer(&peer.alias)
+                            .content(&res)
+                            .print()?;
+                        println!("{}", message);
+                    }
+                    DiskArgs::Update(args) => {
+                        let tag = "disk/update";
+
+                        // Set working node
+                        let cw_node = args.vm.current_workgin_node.peer;
+                        let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                        let res = client
+                            .vm()
+                            .disk()
+                            .update()
+                            .maybe_id(args.vm.id)
+                            .maybe_uuid(args.vm.uuid)
+                            .maybe_name(args.vm.name)
+                            .disk_name(args.name_disk)
+                            .maybe_direct(args.direct)
+                            .maybe_num_queues(args.num_queues)
+                            .maybe_queue_size(args.queue_size)
+                            .maybe_bandwidth(args.bandwidth)
+                            .maybe_iops(args.iops)
+                            .alias(&peer.alias)
+                            .exec()
+                            .await;
+                        let restart = matches!(res, Ok((_, true)));
+                        let res = res.map(|(vm, _)| vm);
+
+                        let message = printer
+                            .res_vm()
+                            .tag(tag)
+                            .peer(&peer.alias)
+                            .content(&res)
+                            .print()?;
+                        println!("{}", message);
+                        if restart {
+                            println!("Vm is running, restart it to apply the new disk settings.");
+                        }
+                    }
+                    args @ (DiskArgs::Check(_) | DiskArgs::Repair(_)) => {
+                        let repair = matches!(args, DiskArgs::Repair(_));
+                        let (DiskArgs::Check(args) | DiskArgs::Repair(args)) = args else {
+                            unreachable!()
+                        };
+
+                        // Set working node
+                        let cw_node = args.vm.current_workgin_node.peer;
+                        let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                        // Spinner
+                        let mut sp = Spinner::new(spinners::Toggle5, "Checking vm disks...", None);
+                        let res = client
+                            .vm()
+                            .disk()
+                            .check()
+                            .maybe_id(args.vm.id)
+                            .maybe_uuid(args.vm.uuid)
+                            .maybe_name(args.vm.name)
+                            .maybe_disk_name(args.name_disk)
+                            .repair(repair)
+                            .alias(&peer.alias)
+                            .exec()
+                            .await;
+                        sp.clear();
+
+                        let res = res?;
+                        if args.vm.format.ron == Some(true) {
+                            println!("{:#?}", res);
+                        } else if args.vm.format.json == Some(true) {
+                            let string = serde_json::to_string_pretty(&res).unwrap();
+                            println!("{}", string);
+                        } else {
+                            PartitionReport::display(&res)?
+                        }
+                    }
+                    DiskArgs::Rm(args) => {
+                        let tag = "disk/rm";
+
+                        // Set working node
+                        let cw_node = args.vm.current_workgin_node.peer;
+                        let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                        let res = client
+                            .vm()
+                            .disk()
+                            .remove()
+                            .maybe_id(args.vm.id)
+                            .maybe_uuid(args.vm.uuid)
+                            .maybe_name(args.vm.name)
+                            .disk_name(args.name_disk)
+                            .alias(&peer.alias)
+                            .exec()
+                            .await;
+
+                        let message = printer
+                            .res_vm()
+                            .tag(tag)
+                            .peer(&peer.alias)
+                            .content(&res)
+                            .print()?;
+                        println!("{}", message);
+                    }
+                },
                 Crud::Delete(args) => {
                     let tag = "delete";
 
@@ -455,6 +1082,24 @@ impl Cli {
                         }
                     }
                 }
+                Crud::Console(args) => {
+                    // Set working node
+                    let cw_node = args.current_workgin_node.peer;
+                    let peer: Peer = config.peer().maybe_alias(cw_node).get()?;
+
+                    if args.name.is_some() || args.uuid.is_some() || args.id.is_some() {
+                        let stream = client
+                            .vm()
+                            .console()
+                            .maybe_id(args.id)
+                            .maybe_uuid(args.uuid)
+                            .maybe_name(args.name)
+                            .alias(&peer.alias)
+                            .exec()
+                            .await?;
+                        console::attach(stream).await?;
+                    }
+                }
                 Crud::Info(args) => {
                     // Set working node
                     let cw_node = args.current_workgin_node.peer.clone();
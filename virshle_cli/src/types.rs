@@ -1,5 +1,6 @@
 use clap::{Args, Parser, Subcommand, ValueEnum, ValueHint};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
+use jiff::SignedDuration;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use virshle_core::hypervisor::VmState;
@@ -46,6 +47,9 @@ pub enum Commands {
     /// Operations on templates
     #[command(subcommand)]
     Template(TemplateArgs),
+    /// Operations on cached base images
+    #[command(subcommand)]
+    Image(ImageArgs),
     /// Operations on virtual machines
     #[command(subcommand)]
     Vm(Crud),
@@ -66,7 +70,48 @@ pub enum Crud {
 
     /// Stops a virtual machine.
     #[command(alias = "off", arg_required_else_help = true)]
-    Stop(VmArgs),
+    Stop(StopArgs),
+
+    /// Reboots a running virtual machine (network ports and process are kept).
+    #[command(arg_required_else_help = true)]
+    Reboot(VmArgs),
+
+    /// Pauses a running virtual machine (guest state is kept in memory).
+    #[command(alias = "freeze", arg_required_else_help = true)]
+    Pause(VmArgs),
+
+    /// Resumes a paused virtual machine.
+    #[command(alias = "unfreeze", arg_required_else_help = true)]
+    Resume(VmArgs),
+
+    /// Takes a snapshot of a virtual machine,
+    /// or lists its snapshots when no tag is given.
+    #[command(arg_required_else_help = true)]
+    Snapshot(SnapshotArgs),
+
+    /// Rolls a virtual machine back to a snapshot.
+    #[command(arg_required_else_help = true)]
+    Restore(RestoreArgs),
+
+    /// Live migrates a virtual machine to another peer.
+    #[command(arg_required_else_help = true)]
+    Migrate(MigrateArgs),
+
+    /// Clones a virtual machine (disks, account and user data).
+    #[command(arg_required_else_help = true)]
+    Clone(CloneArgs),
+
+    /// Exports a stopped virtual machine into a tar.zst archive.
+    #[command(arg_required_else_help = true)]
+    Export(ExportArgs),
+
+    /// Creates a virtual machine from a tar.zst archive.
+    #[command(arg_required_else_help = true)]
+    Import(ImportArgs),
+
+    /// Attaches to a virtual machine serial console (detach with Ctrl-]).
+    #[command(arg_required_else_help = true)]
+    Console(VmArgs),
 
     /// Parse a virtual machine toml configuration.
     #[command(arg_required_else_help = true)]
@@ -94,8 +139,17 @@ pub enum Crud {
     #[command()]
     Ls(VmArgs),
 
-    #[command(hide = true)]
-    Update(CreateArgs),
+    /// Updates a virtual machine settings.
+    #[command(arg_required_else_help = true)]
+    Update(UpdateArgs),
+
+    /// Grows or shrinks a virtual machine vcpu and ram (live if running).
+    #[command(arg_required_else_help = true)]
+    Resize(ResizeArgs),
+
+    /// Operations on virtual machine data disks.
+    #[command(subcommand)]
+    Disk(DiskArgs),
 }
 #[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
 pub struct InitArgs {
@@ -217,6 +271,234 @@ pub struct EnsureArgs {
     pub vm: VmArgs,
 }
 
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct SnapshotArgs {
+    /// Snapshot name (ex: before-upgrade).
+    #[arg(long, value_name = "TAG")]
+    pub tag: Option<String>,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct RestoreArgs {
+    /// Snapshot name (ex: before-upgrade).
+    #[arg(long, value_name = "TAG")]
+    pub tag: String,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct MigrateArgs {
+    /// Destination peer.
+    #[arg(long, value_name = "PEER_ALIAS")]
+    pub to: String,
+    /// Port the destination node listens on for the migration,
+    /// defaults to a free ephemeral port.
+    #[arg(long, value_name = "PORT")]
+    pub port: Option<u16>,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct CloneArgs {
+    /// Name of the clone (suffixed with an index when cloning many times).
+    #[arg(long = "as", value_name = "VM_NAME")]
+    pub clone_name: Option<String>,
+    /// How many clones to create
+    #[arg(short, value_name = "INTEGER")]
+    pub ntimes: Option<u8>,
+    /// Pass other user data to the clones.
+    #[arg(short, long, value_name = "USERDATA_FILEPATH")]
+    pub user_data: Option<String>,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct ExportArgs {
+    /// Archive file to write.
+    #[arg(short, long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub output: String,
+    /// Bundle a snapshot along with the disks.
+    #[arg(long, value_name = "TAG")]
+    pub snapshot: Option<String>,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct ImportArgs {
+    /// Archive file to read.
+    #[arg(value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub file: String,
+    /// Keep the archived vm uuid (and snapshot), instead of generating a new one.
+    #[arg(long,num_args(0..=1),
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub keep_uuid: Option<bool>,
+    /// Name of the imported vm.
+    #[arg(long, value_name = "VM_NAME")]
+    pub name: Option<String>,
+
+    #[command(flatten)]
+    pub current_workgin_node: CurrentWorkingNode,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct UpdateArgs {
+    /// Wether to boot the vm back when the node starts.
+    #[arg(long,num_args(0..=1),
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub autostart: Option<bool>,
+    /// Replace the vm security groups (comma separated),
+    /// remove them if no value is given.
+    #[arg(long, value_delimiter = ',',
+        num_args(0..=1),
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    pub security_groups: Option<Vec<String>>,
+    /// Network to rate limit, the only vm network if none.
+    #[arg(long, value_name = "NET_NAME")]
+    pub net: Option<String>,
+    /// Max bits per second to the vm (ex: 100Mbit), 0bit to remove the limit.
+    #[arg(long, value_name = "RATE")]
+    pub ingress: Option<String>,
+    /// Max bits per second from the vm (ex: 50Mbit), 0bit to remove the limit.
+    #[arg(long, value_name = "RATE")]
+    pub egress: Option<String>,
+    /// Burst size (ex: 10Mbit), 0bit to restore the default.
+    #[arg(long, value_name = "SIZE")]
+    pub burst: Option<String>,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct StopArgs {
+    /// Time given to the guest to power itself off (ex: 60s, 2m).
+    #[arg(long, value_name = "DURATION")]
+    pub timeout: Option<SignedDuration>,
+    /// Force the vm down if the guest didn't power off in time.
+    #[arg(long,num_args(0..=1),
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub force: Option<bool>,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct ResizeArgs {
+    /// New number of vcpu.
+    #[arg(long, value_name = "VCPU")]
+    pub vcpu: Option<u64>,
+    /// New ram size (ex: 8GiB).
+    #[arg(long, value_name = "VRAM")]
+    pub vram: Option<String>,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Debug, Subcommand, Clone, Eq, PartialEq)]
+pub enum DiskArgs {
+    /// Creates a data disk and attaches it to a virtual machine (live if running).
+    #[command(arg_required_else_help = true)]
+    Add(DiskAddArgs),
+    /// Grows a virtual machine disk (live if running).
+    #[command(arg_required_else_help = true)]
+    Resize(DiskAddArgs),
+    /// Detaches a data disk from a virtual machine and deletes it.
+    #[command(alias = "remove", arg_required_else_help = true)]
+    Rm(DiskRmArgs),
+    /// Changes a virtual machine disk I/O tuning (on next start if running).
+    #[command(arg_required_else_help = true)]
+    Update(DiskUpdateArgs),
+    /// Checks a stopped virtual machine disks filesystems.
+    #[command(arg_required_else_help = true)]
+    Check(DiskCheckArgs),
+    /// Checks and fixes a stopped virtual machine disks filesystems.
+    #[command(arg_required_else_help = true)]
+    Repair(DiskCheckArgs),
+}
+
+// Used to add or resize a disk.
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct DiskAddArgs {
+    /// Disk name (ex: data).
+    #[arg(long, value_name = "DISK_NAME")]
+    pub name_disk: String,
+    /// Disk size (ex: 20GiB).
+    #[arg(long, value_name = "SIZE")]
+    pub size: String,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct DiskUpdateArgs {
+    /// Disk name (ex: data).
+    #[arg(long, value_name = "DISK_NAME")]
+    pub name_disk: String,
+    /// Bypass host page cache.
+    #[arg(long,num_args(0..=1),
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub direct: Option<bool>,
+    /// Number of virtio queues.
+    #[arg(long, value_name = "INTEGER")]
+    pub num_queues: Option<u64>,
+    /// Virtio queues depth.
+    #[arg(long, value_name = "INTEGER")]
+    pub queue_size: Option<u64>,
+    /// Max bytes per second (ex: 100MiB), 0B to remove the limit.
+    #[arg(long, value_name = "SIZE")]
+    pub bandwidth: Option<String>,
+    /// Max operations per second, 0 to remove the limit.
+    #[arg(long, value_name = "INTEGER")]
+    pub iops: Option<u64>,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct DiskCheckArgs {
+    /// Only check this disk (ex: os).
+    #[arg(long, value_name = "DISK_NAME")]
+    pub name_disk: Option<String>,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct DiskRmArgs {
+    /// Disk name (ex: data).
+    #[arg(long, value_name = "DISK_NAME")]
+    pub name_disk: String,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
 #[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, ValueEnum)]
 pub enum DeletionMode {
     #[default]
@@ -226,6 +508,7 @@ pub enum DeletionMode {
 
 #[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
 pub struct StartArgs {
+    /// Attach to the vm serial console once started.
     #[arg(
         long,
         num_args(0..=1),
@@ -259,6 +542,43 @@ pub enum TemplateArgs {
     Ls,
 }
 
+#[derive(Debug, Subcommand, Clone, Eq, PartialEq)]
+pub enum ImageArgs {
+    /// List cached images and how many vm disks use them.
+    Ls(CurrentWorkingNode),
+    /// Fetch an image into cache and check its integrity.
+    #[command(arg_required_else_help = true)]
+    Pull(ImagePullArgs),
+    /// Remove an unused image from cache.
+    #[command(alias = "remove", arg_required_else_help = true)]
+    Rm(ImageRmArgs),
+    /// Remove every unused image from cache.
+    Prune(CurrentWorkingNode),
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct ImagePullArgs {
+    /// Image file path, or file://, http:// or https:// url.
+    #[arg(value_name = "SOURCE")]
+    pub source: String,
+    /// Expected image sha256 (hex encoded), required for remote sources.
+    #[arg(long, value_name = "SHA256")]
+    pub sha256: Option<String>,
+
+    #[command(flatten)]
+    pub current_workgin_node: CurrentWorkingNode,
+}
+
+#[derive(Default, Debug, Args, Clone, Eq, PartialEq, Serialize)]
+pub struct ImageRmArgs {
+    /// Cached image name.
+    #[arg(value_name = "IMAGE_NAME")]
+    pub name: String,
+
+    #[command(flatten)]
+    pub current_workgin_node: CurrentWorkingNode,
+}
+
 #[derive(Debug, Subcommand, Clone, Eq, PartialEq)]
 pub enum PeerArgs {
     Ls(NodeLsArgs),
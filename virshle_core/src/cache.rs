@@ -1,26 +1,501 @@
-use std::fs;
+use crate::config::DiskTemplate;
+use crate::hypervisor::disk::utils as disk_utils;
+use crate::hypervisor::Vm;
+use crate::peer::Peer;
+use crate::utils::display;
+use indexmap::IndexMap;
+use owo_colors::OwoColorize;
+use virshle_network::Uri;
+
+// Globals
+use crate::config::init::MANAGED_DIR;
+
+// Filesystem
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+// Integrity
+use sha2::{Digest, Sha256};
+use url::Url;
+use uuid::Uuid;
+
+// Time
+use chrono::{NaiveDateTime, Utc};
+
+// Serde
+use bon::bon;
+use serde::{Deserialize, Serialize};
+use tabled::{
+    settings::{disable::Remove, location::ByColumnName, Style},
+    Table, Tabled,
+};
+
+//Database
+use crate::database;
+use crate::database::connect_db;
+use sea_orm::{prelude::*, query::*, ActiveValue, IntoActiveModel};
 
 // Error Handling
-use miette::{IntoDiagnostic, Result};
+use log::{log_enabled, Level};
+use miette::Result;
+use tracing::info;
+use virshle_error::{LibError, VirshleError};
+
+/// A base image in the node local cache.
+/// Vm disks are thin copies of those images.
+///
+/// Images are content-addressed and never modified once pulled,
+/// as vm disks keep reading their unchanged blocks from it.
+/// The image itself lives on the filesystem at `path`,
+/// usually at : `/var/lib/virshle/cache/{filename}-{sha256}`.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Tabled)]
+pub struct Image {
+    pub name: String,
+    pub source: String,
+    pub sha256: String,
+    pub path: String,
+    #[tabled(display("display::display_bytes"))]
+    pub size: u64,
+    /// Number of vm disks backed by this image.
+    #[tabled(rename = "refs")]
+    pub ref_count: u64,
+    #[tabled(display("display::display_datetime"))]
+    pub created_at: NaiveDateTime,
+}
+
+impl Image {
+    pub fn display(items: &Vec<Self>) -> Result<(), VirshleError> {
+        let mut res = Table::new(items);
+        if !log_enabled!(Level::Info) {
+            res.with(Remove::column(ByColumnName::new("source")));
+            res.with(Remove::column(ByColumnName::new("sha256")));
+            res.with(Remove::column(ByColumnName::new("path")));
+        }
+        res.with(Style::rounded());
+        println!("{}", res);
+        Ok(())
+    }
+    pub fn display_by_peers(items: IndexMap<Peer, Vec<Self>>) -> Result<(), VirshleError> {
+        for (peer, images) in items {
+            let name = peer.alias.bright_purple().bold().to_string();
+            let header: String = match Uri::new(&peer.url)? {
+                Uri::SshUri(e) => format!(
+                    "{name} on {}@{}",
+                    e.user.yellow().bold(),
+                    e.host.green().bold()
+                ),
+                Uri::LocalUri(e) => format!("{name} on {}", "localhost".green().bold()),
+                Uri::TcpUri(e) => format!(
+                    "{name} on {}{}",
+                    e.host.green().bold(),
+                    e.port.blue().bold()
+                ),
+            };
+            println!("\n{}", header);
+            Self::display(&images)?;
+        }
+        Ok(())
+    }
+}
 
-use virshle_error::VirshleError;
+impl Image {
+    /// Return the cache directory.
+    /// Usually at : `/var/lib/virshle/cache`.
+    pub fn get_dir() -> String {
+        format!("{MANAGED_DIR}/cache")
+    }
+    /// Return the image file name (the last segment of its source).
+    pub fn name_from_source(source: &str) -> Result<String, VirshleError> {
+        let path = match source.contains("://") {
+            true => Url::parse(source)?.path().to_owned(),
+            false => source.to_owned(),
+        };
+        match Path::new(&path).file_name().and_then(|e| e.to_str()) {
+            Some(name) => Ok(name.to_owned()),
+            None => {
+                let message = format!("Couldn't guess an image name from {:#?}.", source);
+                let help = "The source must point to a file.";
+                Err(LibError::builder().msg(&message).help(help).build().into())
+            }
+        }
+    }
+    /// Convert an image source into an url.
+    /// Plain file paths are converted to `file://` urls.
+    pub fn url_from_source(source: &str) -> Result<Url, VirshleError> {
+        if source.contains("://") {
+            return Ok(Url::parse(source)?);
+        }
+        let path = DiskTemplate::shellexpand(source)?;
+        let path = fs::canonicalize(&path)?;
+        match Url::from_file_path(&path) {
+            Ok(url) => Ok(url),
+            Err(_) => {
+                let message = format!("Couldn't convert {:#?} into an url.", source);
+                let help = "The source must be an absolute file path.";
+                Err(LibError::builder().msg(&message).help(help).build().into())
+            }
+        }
+    }
+    /// Return the cached image name,
+    /// made of its source file name and content digest.
+    pub fn versioned_name(filename: &str, sha256: &str) -> String {
+        format!("{filename}-{sha256}")
+    }
+    /// Return the lowercased digest,
+    /// or error out if not a hex encoded sha256.
+    pub fn check_sha256(sha256: &str) -> Result<String, VirshleError> {
+        let sha256 = sha256.to_lowercase();
+        if sha256.len() == 64 && sha256.chars().all(|e| e.is_ascii_hexdigit()) {
+            Ok(sha256)
+        } else {
+            let message = format!("Invalid image digest {:#?}.", sha256);
+            let help = "Must be a hex encoded sha256 (64 characters).";
+            Err(LibError::builder().msg(&message).help(help).build().into())
+        }
+    }
+    /// Return the path of an image in cache,
+    /// whether it has been pulled or not.
+    pub fn get_path(name: &str) -> String {
+        format!("{}/{}", Self::get_dir(), name)
+    }
 
-pub fn ensure_cache_dir() -> Result<(), VirshleError> {
-    Path::new("~/.local/share/virshle");
-    Ok(())
+    async fn from_record(
+        db: &DatabaseConnection,
+        e: database::entity::image::Model,
+    ) -> Result<Self, VirshleError> {
+        let ref_count = database::prelude::VmImage::find()
+            .filter(database::entity::vm_image::Column::ImageId.eq(e.id))
+            .count(db)
+            .await?;
+        Ok(Self {
+            name: e.name,
+            source: e.source,
+            sha256: e.sha256,
+            path: e.path,
+            size: e.size as u64,
+            ref_count,
+            created_at: e.created_at,
+        })
+    }
+    /// Return every cached image.
+    pub async fn get_all() -> Result<Vec<Self>, VirshleError> {
+        let db = connect_db().await?;
+        let records = database::prelude::Image::find()
+            .order_by_asc(database::entity::image::Column::Name)
+            .all(&db)
+            .await?;
+        let mut res = vec![];
+        for record in records {
+            res.push(Self::from_record(&db, record).await?);
+        }
+        Ok(res)
+    }
+    /// Return the cached image with the given name,
+    /// or error out if nothing found.
+    pub async fn get(name: &str) -> Result<Self, VirshleError> {
+        let db = connect_db().await?;
+        let record = database::prelude::Image::find()
+            .filter(database::entity::image::Column::Name.eq(name))
+            .one(&db)
+            .await?;
+        match record {
+            Some(record) => Self::from_record(&db, record).await,
+            None => {
+                let message = format!("Couldn't find image {:#?} in cache.", name);
+                let help = "List cached images with `v image ls`.";
+                Err(LibError::builder().msg(&message).help(help).build().into())
+            }
+        }
+    }
 }
 
-/**
-* Convert string into url and fetch resources to cache.
-*/
-pub fn get_image_from_url(url: &str) -> Result<(), VirshleError> {
-    Ok(())
+#[bon]
+impl Image {
+    /// Fetch an image into cache, verify its integrity and index it.
+    ///
+    /// Images are downloaded once:
+    /// an already cached image is returned as is.
+    /// A source whose content has changed is pulled as a new image,
+    /// so disks built on the previous one are left untouched.
+    ///
+    /// # Arguments
+    /// * `source` - a file path, or a `file://`, `http://`, `https://` url.
+    /// * `sha256` - the expected image hex encoded digest,
+    ///   required for remote sources, computed for local files if none.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    #[tracing::instrument(skip_all)]
+    pub async fn pull(source: String, sha256: Option<String>) -> Result<Self, VirshleError> {
+        let url = Self::url_from_source(&source)?;
+        let source_name = Self::name_from_source(&source)?;
+        let sha256 = match sha256 {
+            Some(sha256) => Some(Self::check_sha256(&sha256)?),
+            // Local files digest is computed on copy.
+            None if url.scheme() == "file" => None,
+            None => {
+                let message = format!("Couldn't pull image {:#?}.", source);
+                let help = "Remote images must be pinned with a sha256.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+        };
+        if let Some(sha256) = &sha256 {
+            if let Ok(image) = Self::get(&Self::versioned_name(&source_name, sha256)).await {
+                return Ok(image);
+            }
+        }
+
+        // Download into a temporary file,
+        // so an interrupted download never ends up in cache.
+        fs::create_dir_all(Self::get_dir())?;
+        let part = format!("{}/.{}.part", Self::get_dir(), Uuid::new_v4());
+        let digest = match Self::fetch(&url, &part).await {
+            Ok(v) => v,
+            Err(e) => {
+                fs::remove_file(&part).ok();
+                return Err(e);
+            }
+        };
+        if let Some(sha256) = &sha256 {
+            if &digest != sha256 {
+                fs::remove_file(&part)?;
+                let name = Self::versioned_name(&source_name, sha256);
+                let message = format!("Image {:#?} failed integrity check.", name);
+                let help = format!("Expected sha256 {sha256}, got {digest}.");
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        }
+        let sha256 = digest;
+        let name = Self::versioned_name(&source_name, &sha256);
+        let path = Self::get_path(&name);
+
+        if Path::new(&path).exists() {
+            fs::remove_file(&part)?;
+            if let Ok(image) = Self::get(&name).await {
+                return Ok(image);
+            }
+            // Unindexed leftover, never replace an image disks may rely on.
+            let leftover = path.clone();
+            let digest =
+                tokio::task::spawn_blocking(move || Self::digest(Path::new(&leftover))).await??;
+            if digest != sha256 {
+                let message = format!("Image {:#?} in cache is corrupted.", name);
+                let help = format!("Expected sha256 {sha256}, got {digest}.");
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        } else {
+            fs::rename(&part, &path)?;
+            // Backing images must never be written to.
+            let mut perms = fs::metadata(&path)?.permissions();
+            perms.set_mode(0o444);
+            fs::set_permissions(&path, perms)?;
+        }
+        let size = fs::metadata(&path)?.len();
+
+        // Index image.
+        let db = connect_db().await?;
+        let now: NaiveDateTime = Utc::now().naive_utc();
+        let record = database::prelude::Image::find()
+            .filter(database::entity::image::Column::Name.eq(&name))
+            .one(&db)
+            .await?;
+        match record {
+            Some(record) => {
+                let mut record = record.into_active_model();
+                record.source = ActiveValue::Set(url.to_string());
+                record.sha256 = ActiveValue::Set(sha256);
+                record.size = ActiveValue::Set(size as i64);
+                record.created_at = ActiveValue::Set(now);
+                record.update(&db).await?;
+            }
+            None => {
+                let record = database::entity::image::ActiveModel {
+                    name: ActiveValue::Set(name.clone()),
+                    source: ActiveValue::Set(url.to_string()),
+                    sha256: ActiveValue::Set(sha256),
+                    path: ActiveValue::Set(path),
+                    size: ActiveValue::Set(size as i64),
+                    created_at: ActiveValue::Set(now),
+                    ..Default::default()
+                };
+                database::prelude::Image::insert(record).exec(&db).await?;
+            }
+        }
+
+        info!("pulled image {:#?}", name);
+        Self::get(&name).await
+    }
 }
 
-/**
-* Check if url resource is in cache.
-*/
-pub fn is_cached(url: &str) -> Result<(), VirshleError> {
-    Ok(())
+impl Image {
+    /// Return a local file hex encoded sha256.
+    fn digest(path: &Path) -> Result<String, VirshleError> {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 1 << 20];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Copy a local file to target while computing its sha256.
+    fn copy(source: &Path, target: &str) -> Result<String, VirshleError> {
+        let mut source = File::open(source)?;
+        let mut file = File::create(target)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 1 << 20];
+        loop {
+            let n = source.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            file.write_all(&buf[..n])?;
+        }
+        file.sync_all()?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Copy the url resource to target while computing its sha256.
+    /// Disk io is kept off the async runtime threads.
+    async fn fetch(url: &Url, target: &str) -> Result<String, VirshleError> {
+        match url.scheme() {
+            "file" => {
+                let path = match url.to_file_path() {
+                    Ok(v) => v,
+                    Err(_) => {
+                        let message = format!("Couldn't read file url {:#?}.", url.as_str());
+                        let help = "File urls must be absolute (ex: file:///path/to/image.img).";
+                        return Err(LibError::builder().msg(&message).help(help).build().into());
+                    }
+                };
+                let target = target.to_owned();
+                tokio::task::spawn_blocking(move || Self::copy(&path, &target)).await?
+            }
+            "http" | "https" => {
+                let mut file = tokio::fs::File::create(target).await?;
+                let mut hasher = Sha256::new();
+                let mut response = reqwest::get(url.as_str()).await?.error_for_status()?;
+                while let Some(chunk) = response.chunk().await? {
+                    hasher.update(&chunk);
+                    file.write_all(&chunk).await?;
+                }
+                file.sync_all().await?;
+                Ok(format!("{:x}", hasher.finalize()))
+            }
+            scheme => {
+                let message = format!("Unsupported image source scheme {:#?}.", scheme);
+                let help = "Use a file path, or a file://, http:// or https:// url.";
+                Err(LibError::builder().msg(&message).help(help).build().into())
+            }
+        }
+    }
+
+    /// Return the vm disks (as "vm/disk") still reading from the image,
+    /// whether indexed or not (ex: qcow2 overlays of imported vms).
+    pub async fn get_dependent_disks(&self) -> Result<Vec<String>, VirshleError> {
+        let mut res = vec![];
+        for vm in Vm::database().await?.many().get().await? {
+            for disk in &vm.disk {
+                let backing = match Path::new(&disk.path).exists() {
+                    true => disk_utils::get_backing_file(&disk.path)?,
+                    false => None,
+                };
+                if disk.image.as_ref() == Some(&self.name) || backing.as_ref() == Some(&self.path) {
+                    res.push(format!("{}/{}", vm.name, disk.name));
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Remove an image from cache.
+    /// Refuses to remove an image that still backs vm disks.
+    pub async fn rm(name: &str) -> Result<Self, VirshleError> {
+        let image = Self::get(name).await?;
+        let disks = image.get_dependent_disks().await?;
+        if image.ref_count > 0 || !disks.is_empty() {
+            let message = format!("Couldn't remove image {:#?}.", name);
+            let help = format!("Image is still used by vm disk(s): [{}].", disks.join(","));
+            return Err(LibError::builder().msg(&message).help(&help).build().into());
+        }
+        if Path::new(&image.path).exists() {
+            fs::remove_file(&image.path)?;
+        }
+        let db = connect_db().await?;
+        database::prelude::Image::delete_many()
+            .filter(database::entity::image::Column::Name.eq(name))
+            .exec(&db)
+            .await?;
+
+        info!("removed image {:#?}", name);
+        Ok(image)
+    }
+
+    /// Remove every image that no vm disk uses anymore.
+    pub async fn prune() -> Result<Vec<Self>, VirshleError> {
+        let mut res = vec![];
+        for image in Self::get_all().await? {
+            if image.ref_count == 0 && image.get_dependent_disks().await?.is_empty() {
+                res.push(Self::rm(&image.name).await?);
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn image_name_from_source() -> Result<()> {
+        let sources = [
+            "https://example.com/images/nixos.efi.img",
+            "https://example.com/images/nixos.efi.img?version=2",
+            "file:///var/lib/images/nixos.efi.img",
+            "~/images/nixos.efi.img",
+        ];
+        for source in sources {
+            assert_eq!(Image::name_from_source(source)?, "nixos.efi.img");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn image_url_from_source() -> Result<()> {
+        let url = Image::url_from_source("https://example.com/nixos.efi.img")?;
+        assert_eq!(url.scheme(), "https");
+
+        let url = Image::url_from_source("/dev/null")?;
+        assert_eq!(url.as_str(), "file:///dev/null");
+        Ok(())
+    }
+
+    #[test]
+    fn image_versioned_name() -> Result<()> {
+        let sha256 = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+        let sha256 = Image::check_sha256(sha256)?;
+        assert_eq!(
+            Image::versioned_name("nixos.efi.img", &sha256),
+            "nixos.efi.img-e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(Image::digest(Path::new("/dev/null"))?, sha256);
+
+        // Digests end up in cache paths.
+        assert!(Image::check_sha256("../../etc/passwd").is_err());
+        assert!(Image::check_sha256("e3b0c442").is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,181 @@
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use uuid::Uuid;
+
+// Error Handling
+use miette::Result;
+use virshle_error::{LibError, VirshleError};
+
+/// Host firewall configuration.
+#[derive(Default, Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FirewallConfig {
+    /// Named sets of rules, attached to templates, accounts or vms.
+    #[serde(default)]
+    pub group: Vec<SecurityGroup>,
+    /// Security groups applied to every vm of an account.
+    #[serde(default)]
+    pub account: Vec<AccountFirewall>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SecurityGroup {
+    pub name: String,
+    #[serde(default)]
+    pub rule: Vec<FirewallRule>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AccountFirewall {
+    pub uuid: Uuid,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct FirewallRule {
+    pub direction: Direction,
+    #[serde(default)]
+    pub action: FirewallAction,
+    #[serde(default)]
+    pub proto: Protocol,
+    /// Vm side port for ingress, remote port for egress (tcp and udp only).
+    pub port: Option<PortRange>,
+    /// Remote network, any if none.
+    pub cidr: Option<IpNet>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Traffic to the vm.
+    Ingress,
+    /// Traffic from the vm.
+    Egress,
+}
+
+#[derive(Default, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum FirewallAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+#[derive(Default, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    #[default]
+    Any,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+/// An inclusive port range, written "22" or "8000-8100".
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(try_from = "RawPortRange", into = "String")]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPortRange {
+    Number(u16),
+    Range(String),
+}
+impl TryFrom<RawPortRange> for PortRange {
+    type Error = String;
+    fn try_from(raw: RawPortRange) -> Result<Self, Self::Error> {
+        let (start, end) = match raw {
+            RawPortRange::Number(port) => (port, port),
+            RawPortRange::Range(string) => {
+                let parse = |e: &str| {
+                    e.trim()
+                        .parse::<u16>()
+                        .map_err(|_| format!("invalid port range {:#?}", string))
+                };
+                match string.split_once('-') {
+                    Some((start, end)) => (parse(start)?, parse(end)?),
+                    None => (parse(&string)?, parse(&string)?),
+                }
+            }
+        };
+        if start > end {
+            return Err(format!(
+                "invalid port range {start}-{end}, start is above end"
+            ));
+        }
+        Ok(PortRange { start, end })
+    }
+}
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.start == self.end {
+            true => write!(f, "{}", self.start),
+            false => write!(f, "{}-{}", self.start, self.end),
+        }
+    }
+}
+impl From<PortRange> for String {
+    fn from(range: PortRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl FirewallConfig {
+    /// Check security groups definition.
+    pub fn validate(&self) -> Result<(), VirshleError> {
+        for (i, group) in self.group.iter().enumerate() {
+            if self.group[..i].iter().any(|e| e.name == group.name) {
+                let message = format!("Security group {:#?} is declared twice.", group.name);
+                let help = "Give every [[firewall.group]] a unique name.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+            for rule in &group.rule {
+                if rule.port.is_some() && !matches!(rule.proto, Protocol::Tcp | Protocol::Udp) {
+                    let message = format!(
+                        "Security group {:#?} has a port on a non tcp/udp rule.",
+                        group.name
+                    );
+                    let help = "Set proto to \"tcp\" or \"udp\", or remove the port.";
+                    return Err(LibError::builder().msg(&message).help(help).build().into());
+                }
+            }
+        }
+        for account in &self.account {
+            self.get_groups(&account.groups)?;
+        }
+        Ok(())
+    }
+    /// Return security groups by name.
+    pub fn get_groups(&self, names: &[String]) -> Result<Vec<SecurityGroup>, VirshleError> {
+        let mut groups = vec![];
+        for name in names {
+            match self.group.iter().find(|e| &e.name == name) {
+                Some(v) => groups.push(v.to_owned()),
+                None => {
+                    let names = self
+                        .group
+                        .iter()
+                        .map(|e| e.name.to_owned())
+                        .collect::<Vec<String>>()
+                        .join(",");
+                    let message = format!("Couldn't find security group {:#?}", name);
+                    let help = format!("Available security groups are:\n[{names}]");
+                    return Err(LibError::builder().msg(&message).help(&help).build().into());
+                }
+            }
+        }
+        Ok(groups)
+    }
+    /// Return the security groups applied to every vm of an account.
+    pub fn get_account_groups(&self, uuid: &Uuid) -> Vec<String> {
+        self.account
+            .iter()
+            .filter(|e| &e.uuid == uuid)
+            .flat_map(|e| e.groups.clone())
+            .collect()
+    }
+}
@@ -80,7 +80,7 @@ impl InitMethods<'_> {
     }
 
     pub async fn network(&self) -> Result<&Self, VirshleError> {
-        ovs::ensure_switches().await?;
+        ovs::ensure_switches(&self.config.network).await?;
         info!(
             "{} created virshle ovs network configuration.",
             "[init]".yellow(),
@@ -103,6 +103,7 @@ impl InitMethods<'_> {
 
     pub async fn database(&self) -> Result<&Self, VirshleError> {
         database::connect_or_fresh_db().await?;
+        database::migrate_db().await?;
         info!("{} ensured virshle database.", "[init]".yellow(),);
         Ok(self)
     }
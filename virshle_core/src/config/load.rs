@@ -1,5 +1,7 @@
-use crate::config::{DhcpType, NodeConfig, Peer, TemplateConfig, UserData};
-use crate::hypervisor::vm::VmExtra;
+use crate::config::{
+    DhcpType, FirewallConfig, NetworkConfig, NodeConfig, Peer, TemplateConfig, UserData,
+};
+use crate::hypervisor::{disk::utils as disk_utils, vm::VmExtra};
 use crate::VmTemplate;
 
 use super::Config;
@@ -35,6 +37,8 @@ pub struct PreConfig {
     pub template: Option<TemplateConfig>,
     /// Network configuration
     pub dhcp: Option<DhcpType>,
+    pub network: Option<NetworkConfig>,
+    pub firewall: Option<FirewallConfig>,
     // Client
     /// List of remote node
     peer: Option<Vec<Peer>>,
@@ -54,6 +58,16 @@ impl TryInto<Config> for &PreConfig {
             dhcp: self.dhcp.clone(),
             ..Config::default()
         };
+        // Network conversion
+        if let Some(network) = &self.network {
+            network.validate()?;
+            config.network = network.clone();
+        }
+        // Firewall conversion
+        if let Some(firewall) = &self.firewall {
+            firewall.validate()?;
+            config.firewall = firewall.clone();
+        }
         // Node conversion
         if let Some(node) = &self.node {
             config.node = node.try_into()?;
@@ -62,6 +76,22 @@ impl TryInto<Config> for &PreConfig {
         if let Some(templates) = &self.template {
             if let Some(vm_templates) = &templates.vm {
                 for e in vm_templates {
+                    // Safeguard: networks must refer to a declared switch.
+                    for net in e.net.iter().flatten() {
+                        config.network.get_switch(net.switch.as_deref())?;
+                        net.validate()?;
+                    }
+                    // Safeguard: sizes must be unambiguous.
+                    for vram in [Some(&e.vram), e.max_vram.as_ref()].into_iter().flatten() {
+                        disk_utils::reverse_human_bytes(vram)?;
+                    }
+                    for disk in e.disk.iter().flatten() {
+                        disk.validate()?;
+                    }
+                    // Safeguard: security groups must be declared.
+                    if let Some(groups) = &e.security_groups {
+                        config.firewall.get_groups(groups)?;
+                    }
                     config.templates.insert(e.name.clone(), e.clone());
                 }
             }
@@ -187,6 +217,7 @@ impl VmExtra {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{PortRange, SwitchType};
     use tracing_test::traced_test;
 
     #[test]
@@ -226,7 +257,7 @@ mod tests {
             [[template.vm.disk]]
             name = "os"
             path = "~/Iso/nixos.efi.raw"
-            size = "50G"
+            size = "50GiB"
 
             [[template.vm]]
             name = "s"
@@ -235,7 +266,7 @@ mod tests {
             [[template.vm.disk]]
             name = "os"
             path = "~/Iso/nixos.efi.raw"
-            size = "80G"
+            size = "80GiB"
 
             [[template.vm]]
             name = "m"
@@ -244,7 +275,7 @@ mod tests {
             [[template.vm.disk]]
             name = "os"
             path = "~/Iso/nixos.efi.raw"
-            size = "100G"
+            size = "100GiB"
 
             [[template.vm]]
             name = "l"
@@ -253,7 +284,7 @@ mod tests {
             [[template.vm.disk]]
             name = "os"
             path = "~/Iso/nixos.efi.raw"
-            size = "150G"
+            size = "150GiB"
 
             [[template.vm]]
             name = "xl"
@@ -262,7 +293,7 @@ mod tests {
             [[template.vm.disk]]
             name = "os"
             path = "~/Iso/nixos.efi.raw"
-            size = "180G"
+            size = "180GiB"
 
         "#;
 
@@ -272,4 +303,197 @@ mod tests {
         println!("{:#?}", config);
         Ok(())
     }
+
+    #[test]
+    fn get_network_config_from_toml() -> Result<()> {
+        let toml = r#"
+            [[network.switch]]
+            name = "br0"
+            patch = "brmain"
+
+            [[network.switch]]
+            name = "br1"
+            type = "netdev"
+            uplink = "eno2"
+
+            [[network.switch]]
+            name = "brmain"
+            uplink = "eno1"
+
+            [[template.vm]]
+            name = "xxs"
+            vcpu = 1
+            vram = "1GiB"
+            [[template.vm.net]]
+            name = "main"
+            switch = "br1"
+            [template.vm.net.type.vhost]
+        "#;
+        let config: Config = PreConfig::from_toml(&toml)?.try_into()?;
+        assert_eq!(config.network.get_switch(None)?.name, "br0");
+        assert_eq!(
+            config.network.get_switch(Some("br1"))?._type,
+            SwitchType::Netdev
+        );
+
+        // Unknown switch
+        let unknown = toml.replace("switch = \"br1\"", "switch = \"br2\"");
+        let res: Result<Config, VirshleError> = PreConfig::from_toml(&unknown)?.try_into();
+        assert!(res.is_err());
+
+        // Unknown patch target
+        let unknown = toml.replace("patch = \"brmain\"", "patch = \"brnowhere\"");
+        let res: Result<Config, VirshleError> = PreConfig::from_toml(&unknown)?.try_into();
+        assert!(res.is_err());
+        Ok(())
+    }
+    #[test]
+    fn get_firewall_config_from_toml() -> Result<()> {
+        let toml = r#"
+            [[firewall.group]]
+            name = "web"
+            [[firewall.group.rule]]
+            direction = "ingress"
+            proto = "tcp"
+            port = "80-443"
+            [[firewall.group.rule]]
+            direction = "ingress"
+            proto = "tcp"
+            port = 22
+            cidr = "192.168.1.0/24"
+            [[firewall.group.rule]]
+            direction = "egress"
+            action = "deny"
+            cidr = "10.0.0.0/8"
+
+            [[firewall.account]]
+            uuid = "6bd1ea22-4ef4-4b4c-a66d-7d1c8c8e0a40"
+            groups = ["web"]
+
+            [[template.vm]]
+            name = "xxs"
+            vcpu = 1
+            vram = "1GiB"
+            security_groups = ["web"]
+        "#;
+        let config: Config = PreConfig::from_toml(&toml)?.try_into()?;
+        let rules = &config.firewall.get_groups(&["web".to_owned()])?[0].rule;
+        assert_eq!(
+            rules[0].port,
+            Some(PortRange {
+                start: 80,
+                end: 443
+            })
+        );
+        assert_eq!(rules[1].port, Some(PortRange { start: 22, end: 22 }));
+
+        // Unknown security group
+        let res: Result<Config, VirshleError> =
+            PreConfig::from_toml(&toml.replace("[\"web\"]", "[\"db\"]"))?.try_into();
+        assert!(res.is_err());
+        // Port on a non tcp/udp rule
+        let res: Result<Config, VirshleError> =
+            PreConfig::from_toml(&toml.replace("proto = \"tcp\"", "proto = \"icmp\""))?.try_into();
+        assert!(res.is_err());
+        // Invalid port range
+        let res = PreConfig::from_toml(&toml.replace("80-443", "443-80"));
+        assert!(res.is_err());
+        Ok(())
+    }
+    #[test]
+    fn get_bandwidth_config_from_toml() -> Result<()> {
+        let toml = r#"
+            [[template.vm]]
+            name = "xxs"
+            vcpu = 1
+            vram = "1GiB"
+            [[template.vm.net]]
+            name = "main"
+            bandwidth = { ingress = "100Mbit", egress = "50Mbit", burst = "10Mbit" }
+            [template.vm.net.type.tap]
+        "#;
+        let config: Config = PreConfig::from_toml(&toml)?.try_into()?;
+        let template = config.template("xxs")?;
+        let bandwidth = template.net.unwrap()[0].bandwidth.clone().unwrap();
+        assert_eq!(bandwidth.get_ingress()?, Some(100_000_000));
+        assert_eq!(bandwidth.get_egress()?, Some(50_000_000));
+        assert_eq!(bandwidth.get_burst()?, Some(10_000_000));
+
+        // Unknown unit
+        let res: Result<Config, VirshleError> =
+            PreConfig::from_toml(&toml.replace("100Mbit", "100MiB"))?.try_into();
+        assert!(res.is_err());
+        // Macvtap is not plugged into ovs
+        let res: Result<Config, VirshleError> =
+            PreConfig::from_toml(&toml.replace("type.tap", "type.macvtap"))?.try_into();
+        assert!(res.is_err());
+        // Egress is policed in kbit
+        let res: Result<Config, VirshleError> =
+            PreConfig::from_toml(&toml.replace("50Mbit", "500bit"))?.try_into();
+        assert!(res.is_err());
+        Ok(())
+    }
+    #[test]
+    fn get_user_data_from_toml() -> Result<()> {
+        let toml = r#"
+            timezone = "Europe/Paris"
+            locale = "en_US.UTF-8"
+
+            [[user]]
+            name = "anon"
+            groups = ["docker"]
+            shell = "/bin/bash"
+            sudo = true
+            hashed_password = "$6$salt$hash"
+            [user.ssh]
+            authorized_keys = ["ssh-ed25519 AAAAC3NzaC1lZDI1NTE5"]
+
+            [[write_files]]
+            path = "/etc/motd"
+            mode = "0644"
+            owner = "root:root"
+            content = "Plus Ultra!"
+
+            [run_commands]
+            post_net = ["echo up"]
+        "#;
+        let res = UserData::from_toml(&toml)?;
+        println!("{:#?}", res);
+        Ok(())
+    }
+    #[test]
+    fn reject_invalid_user_data() -> Result<()> {
+        let base = r#"
+            [[user]]
+            name = "anon"
+        "#;
+        let invalids = [
+            // plain text password
+            r#"hashed_password = "hunter2""#,
+            r#"shell = "bash""#,
+            r#"groups = ["Wheel"]"#,
+        ];
+        for invalid in invalids {
+            let toml = format!("{base}{invalid}\n");
+            assert!(UserData::from_toml(&toml).is_err());
+        }
+        let invalids = [
+            // both content and base64
+            r#"path = "/etc/motd"
+            content = "a"
+            base64 = "YQ==""#,
+            r#"path = "etc/motd"
+            content = "a""#,
+            r#"path = "/etc/motd"
+            mode = "rw-r--r--"
+            content = "a""#,
+            r#"path = "/etc/motd"
+            base64 = "not base64!""#,
+        ];
+        for invalid in invalids {
+            let toml = format!("{base}[[write_files]]\n{invalid}\n");
+            assert!(UserData::from_toml(&toml).is_err());
+        }
+        Ok(())
+    }
 }
@@ -2,6 +2,8 @@ mod definition;
 mod load;
 mod node;
 mod dhcp;
+mod network;
+mod firewall;
 mod template;
 mod user_data;
 /// Initialize system directories, network, database...
@@ -12,11 +14,16 @@ pub use definition::Definition;
 pub use node::{Node, NodeConfig};
 pub use template::{
     disk::DiskTemplate,
-    vm::{NetType, VmNet, VmTemplate, VmTemplateTable},
+    vm::{Bandwidth, NetType, VmNet, VmTemplate, VmTemplateTable},
     TemplateConfig,
 };
-pub use user_data::{Account, SshParams, User, UserData};
+pub use user_data::{Account, RunCommands, SshParams, User, UserData, WriteFile};
 pub use dhcp::{DhcpType,KeaDhcpConfig, FakeDhcpConfig};
+pub use network::{NetworkConfig, SwitchConfig, SwitchType, DEFAULT_SWITCH};
+pub use firewall::{
+    AccountFirewall, Direction, FirewallAction, FirewallConfig, FirewallRule, PortRange, Protocol,
+    SecurityGroup,
+};
 
 use load::PreConfig;
 use crate::peer::Peer;
@@ -49,6 +56,9 @@ pub struct Config {
     pub templates: IndexMap<String, VmTemplate>,
     /// Network configuration
     pub dhcp: Option<DhcpType>,
+    pub network: NetworkConfig,
+    /// Security groups
+    pub firewall: FirewallConfig,
 
     // Client
     /// List of remote node
@@ -61,6 +71,8 @@ impl Default for Config {
             peers: IndexMap::new(),
             templates: IndexMap::new(),
             dhcp: None,
+            network: NetworkConfig::default(),
+            firewall: FirewallConfig::default(),
         }
     }
 }
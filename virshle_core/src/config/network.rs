@@ -0,0 +1,110 @@
+use crate::network::ovs::OvsBridge;
+use serde::{Deserialize, Serialize};
+
+// Error Handling
+use miette::Result;
+use virshle_error::{LibError, VirshleError};
+
+/// Name of the vm switch used when none is declared.
+pub const DEFAULT_SWITCH: &str = "br0";
+
+/// Host network configuration.
+#[derive(Default, Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NetworkConfig {
+    /// Ovs switches vms are plugged into.
+    #[serde(default)]
+    pub switch: Vec<SwitchConfig>,
+}
+
+/// An ovs switch (bridge) managed by virshle.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SwitchConfig {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub _type: SwitchType,
+    /// Host interface plugged into the switch for external connectivity (ex: eno1).
+    pub uplink: Option<String>,
+    /// Switch to link this one to with a patch cable.
+    pub patch: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SwitchType {
+    /// Managed by the kernel (to be used with tap and macvtap).
+    #[default]
+    System,
+    /// Managed by ovs userspace (to be used with dpdk/vhost).
+    Netdev,
+}
+
+impl NetworkConfig {
+    /// Check switches definition.
+    pub fn validate(&self) -> Result<(), VirshleError> {
+        for (i, switch) in self.switch.iter().enumerate() {
+            if self.switch[..i].iter().any(|e| e.name == switch.name) {
+                let message = format!("Switch {:#?} is declared twice.", switch.name);
+                let help = "Give every [[network.switch]] a unique name.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+            if switch.uplink.is_some() && switch.patch.is_some() {
+                let message = format!("Switch {:#?} has both an uplink and a patch.", switch.name);
+                let help = "Set either an uplink interface or a patch target.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+            if switch.patch.as_ref() == Some(&switch.name) {
+                let message = format!("Switch {:#?} is patched to itself.", switch.name);
+                let help = "Set the patch target to another switch.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+            // Safeguard: the patch target must be created along or already be on host.
+            if let Some(target) = &switch.patch {
+                let declared = self.switch.iter().any(|e| &e.name == target);
+                if !declared && OvsBridge::get_switch(target).is_err() {
+                    let message = format!(
+                        "Switch {:#?} is patched to unknown switch {:#?}.",
+                        switch.name, target
+                    );
+                    let help = "Declare the target in [[network.switch]] or create it on host.";
+                    return Err(LibError::builder().msg(&message).help(help).build().into());
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Return declared switches,
+    /// or the default vm switch if none.
+    pub fn switches(&self) -> Vec<SwitchConfig> {
+        match self.switch.is_empty() {
+            true => vec![SwitchConfig {
+                name: DEFAULT_SWITCH.to_owned(),
+                _type: SwitchType::System,
+                uplink: None,
+                patch: None,
+            }],
+            false => self.switch.clone(),
+        }
+    }
+    /// Return a switch by name,
+    /// or the first declared switch if no name is given.
+    pub fn get_switch(&self, name: Option<&str>) -> Result<SwitchConfig, VirshleError> {
+        let switches = self.switches();
+        let switch = match name {
+            Some(name) => switches.iter().find(|e| e.name == name),
+            None => switches.first(),
+        };
+        match switch {
+            Some(v) => Ok(v.to_owned()),
+            None => {
+                let names = switches
+                    .iter()
+                    .map(|e| e.name.to_owned())
+                    .collect::<Vec<String>>()
+                    .join(",");
+                let message = format!("Couldn't find switch {:#?}", name.unwrap_or_default());
+                let help = format!("Available switches are:\n[{names}]");
+                Err(LibError::builder().msg(&message).help(&help).build().into())
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use crate::hypervisor::Disk;
+use crate::cache::Image;
+use crate::hypervisor::{disk::utils, vmm::RateLimiterConfig, Disk};
 
 use serde::{Deserialize, Serialize};
 use std::convert::Into;
@@ -12,23 +13,96 @@ use virshle_error::{LibError, VirshleError};
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct DiskTemplate {
     pub name: String,
+    /// Image source: a file path, or a `file://`, `http://`, `https://` url.
     pub path: String,
+    /// Expected image digest (hex encoded), checked on pull.
+    /// Required for remote sources, pinned on pull for local files.
+    pub sha256: Option<String>,
     pub readonly: Option<bool>,
+    /// Disk size (ex: 50GiB).
+    /// The image is grown to it on vm creation.
+    pub size: Option<String>,
+
+    // I/O tuning
+    /// Bypass host page cache (O_DIRECT).
+    pub direct: Option<bool>,
+    pub num_queues: Option<u64>,
+    pub queue_size: Option<u64>,
+    pub rate_limiter: Option<RateLimiterConfig>,
+
+    /// Store the vm copy of the disk in a LUKS container.
+    pub encrypted: Option<bool>,
 }
 impl DiskTemplate {
+    /// Check the declared size.
+    pub fn validate(&self) -> Result<(), VirshleError> {
+        if let Some(size) = &self.size {
+            if let Err(e) = utils::reverse_human_bytes(size) {
+                let message = format!("Disk {:#?} has an invalid size {:#?}.", self.name, size);
+                let help = e.to_string();
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        }
+        Ok(())
+    }
+    /// Return the disk virtual size (as seen by the guest).
+    /// The declared size if any,
+    /// read from the cached image when pulled, from the source file otherwise.
     pub fn get_size(&self) -> Result<u64, VirshleError> {
-        let source = Self::shellexpand(&self.path)?;
-        let path = Path::new(&source);
-        if path.exists() && path.is_file() {
-            let metadata = std::fs::metadata(path)?;
-            let size = metadata.len();
-            Ok(size)
+        if let Some(size) = &self.size {
+            return utils::reverse_human_bytes(size);
+        }
+        if let Ok(backing) = self.get_backing_image() {
+            return utils::get_virtual_size(&backing);
+        }
+        if !self.path.contains("://") {
+            let source = Self::shellexpand(&self.path)?;
+            let path = Path::new(&source);
+            if path.exists() && path.is_file() {
+                return utils::get_virtual_size(&source);
+            }
+        }
+        Err(LibError::builder()
+            .msg("Counldn't get disk file size.")
+            .help("Disk doesn't exist or is unreachable")
+            .build()
+            .into())
+    }
+    /// Return the cached image name.
+    /// The image digest must be known (set in template or pinned on pull).
+    pub fn get_image_name(&self) -> Result<String, VirshleError> {
+        let name = Image::name_from_source(&self.path)?;
+        match &self.sha256 {
+            Some(sha256) => Ok(Image::versioned_name(&name, &Image::check_sha256(sha256)?)),
+            None => {
+                let message = format!("Couldn't find image for disk {:#?} in cache.", self.name);
+                let help = format!("Pull it first with `v image pull {}`.", self.path);
+                Err(LibError::builder().msg(&message).help(&help).build().into())
+            }
+        }
+    }
+    /// Fetch the template image into cache (if not already),
+    /// and pin the disk to the pulled image digest.
+    pub async fn pull_image(&mut self) -> Result<Image, VirshleError> {
+        let image = Image::pull()
+            .source(&self.path)
+            .maybe_sha256(self.sha256.clone())
+            .exec()
+            .await?;
+        self.sha256 = Some(image.sha256.clone());
+        Ok(image)
+    }
+    /// Return the path of the shared read-only backing image
+    /// vm disks are thin copies of.
+    /// Usually at : `/var/lib/virshle/cache/{filename}-{sha256}`.
+    pub fn get_backing_image(&self) -> Result<String, VirshleError> {
+        let path = Image::get_path(&self.get_image_name()?);
+        if Path::new(&path).exists() {
+            Ok(path)
         } else {
-            Err(LibError::builder()
-                .msg("Counldn't get disk file size.")
-                .help("Disk doesn't exist or is unreachable")
-                .build()
-                .into())
+            let message = format!("Couldn't find image for disk {:#?} in cache.", self.name);
+            let help = format!("Pull it first with `v image pull {}`.", self.path);
+            Err(LibError::builder().msg(&message).help(&help).build().into())
         }
     }
     /// Expand tild "~" in file path.
@@ -59,6 +133,14 @@ impl Into<Disk> for &DiskTemplate {
             name: self.name.to_owned(),
             path: self.path.to_owned(),
             readonly: self.readonly,
+            image: None,
+            size: self.size.to_owned(),
+            direct: self.direct,
+            num_queues: self.num_queues,
+            queue_size: self.queue_size,
+            rate_limiter: self.rate_limiter.to_owned(),
+            encrypted: self.encrypted,
+            data: None,
         }
     }
 }
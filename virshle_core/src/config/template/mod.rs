@@ -51,6 +51,8 @@ impl TemplateConfig {
     pub async fn create_vms(&self) -> Result<Self, VirshleError> {
         if let Some(vms) = &self.vm {
             for template in vms {
+                let mut template = template.clone();
+                template.pull_images().await?;
                 let mut vm: Vm = template.try_into()?;
                 vm.create(None).await?;
             }
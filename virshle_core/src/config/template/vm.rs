@@ -1,5 +1,7 @@
+use crate::cache::Image;
 use crate::config::DiskTemplate;
-use crate::hypervisor::{Disk, DiskInfo, Vm, VmExtra};
+use crate::hypervisor::{disk::utils as disk_utils, Disk, DiskInfo, InitBackend, Vm, VmExtra};
+use crate::network::utils as net_utils;
 use crate::peer::Peer;
 
 use indexmap::IndexMap;
@@ -18,7 +20,7 @@ use crate::config::init::MANAGED_DIR;
 
 // Error Handling
 use miette::Result;
-use virshle_error::{CastError, TomlError, VirshleError};
+use virshle_error::{CastError, LibError, TomlError, VirshleError};
 
 /// A partial Vm definition, with optional disk, network...
 /// All those usually mandatory fields will be handled by virshle with
@@ -28,10 +30,20 @@ pub struct VmTemplate {
     pub name: String,
     pub vcpu: u64,
     pub vram: String,
+    /// Hotplug headroom: vcpu number a vm can be resized up to.
+    /// Defaults to twice vcpu.
+    pub max_vcpu: Option<u64>,
+    /// Hotplug headroom: ram size a vm can be resized up to.
+    /// Defaults to twice vram.
+    pub max_vram: Option<String>,
     pub uuid: Option<Uuid>,
     pub disk: Option<Vec<DiskTemplate>>,
     pub net: Option<Vec<VmNet>>,
+    /// Security groups applied to every vm network.
+    pub security_groups: Option<Vec<String>>,
     pub extra: Option<VmExtra>,
+    /// Provisioning backend: "pipelight" (default) or "cloud-init".
+    pub init: Option<InitBackend>,
 }
 
 impl VmTemplate {
@@ -52,6 +64,16 @@ impl VmTemplate {
     }
 }
 impl VmTemplate {
+    /// Fetch the template disk images into cache (if not already),
+    /// and pin disks to the pulled images.
+    /// Must be called before converting the template into a Vm.
+    pub async fn pull_images(&mut self) -> Result<Vec<Image>, VirshleError> {
+        let mut res = vec![];
+        for disk in self.disk.iter_mut().flatten() {
+            res.push(disk.pull_image().await?);
+        }
+        Ok(res)
+    }
     pub async fn display_by_peers(items: IndexMap<Peer, Vec<Self>>) -> Result<(), VirshleError> {
         // Convert vm to pretty printable type
         let mut tables: IndexMap<Peer, Vec<VmTemplateTable>> = IndexMap::new();
@@ -110,7 +132,13 @@ impl TryInto<Vm> for &VmTemplate {
         let mut vm = Vm {
             vcpu: self.vcpu.clone(),
             vram: self.vram.clone(),
+            max_vcpu: self.max_vcpu.clone(),
+            max_vram: self.max_vram.clone(),
             net: self.net.clone(),
+            security_groups: self.security_groups.clone(),
+            // Template defaults (ex: autostart).
+            extra: self.extra.clone(),
+            init: self.init,
             ..Default::default()
         };
         ensure_directories(&self, &mut vm)?;
@@ -136,35 +164,67 @@ pub fn ensure_directories(template: &VmTemplate, vm: &mut Vm) -> Result<(), Virs
     Ok(())
 }
 
-/// Copy template disks (if some)
-/// to vm storage directory and set file permissions.
+/// Make thin copies of template disks (if some)
+/// into vm storage directory and set file permissions.
 pub fn create_disks(template: &VmTemplate, vm: &mut Vm) -> Result<(), VirshleError> {
     if let Some(disks) = &template.disk {
         for disk in disks {
-            let source = DiskTemplate::shellexpand(&disk.path)?;
-            let filename = Path::new(&source)
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_owned();
+            let image = disk.get_image_name()?;
+            let backing = disk.get_backing_image()?;
+            let filename = Image::name_from_source(&disk.path)?;
             let target = format!("{MANAGED_DIR}/vm/{}/disk/{}", vm.uuid, filename);
 
+            let vm_disk = Disk {
+                name: disk.name.clone(),
+                path: target.clone(),
+                readonly: Some(false),
+                image: Some(image),
+                size: disk.size.clone(),
+                direct: disk.direct,
+                num_queues: disk.num_queues,
+                queue_size: disk.queue_size,
+                rate_limiter: disk.rate_limiter.clone(),
+                encrypted: disk.encrypted,
+                data: None,
+            };
+
+            // Disk size can't be smaller than its image
+            let image_size = disk_utils::get_virtual_size(&backing)?;
+            let size = match &disk.size {
+                Some(size) => disk_utils::reverse_human_bytes(size)?,
+                None => image_size,
+            };
+            if size < image_size {
+                let message = format!("Couldn't create disk {:#?}.", disk.name);
+                let help = format!(
+                    "Disk size can't be smaller than its image ({}).",
+                    disk_utils::human_bytes(&image_size)?
+                );
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+
             // Create disk on host drive
-            let file = fs::File::create(&target)?;
-            fs::copy(&source, &target)?;
+            match disk.encrypted {
+                Some(true) => {
+                    // Full copy into a LUKS container
+                    vm.crypt().create_key()?;
+                    vm.crypt().format(&vm_disk, &backing, size)?;
+                }
+                _ => {
+                    disk_utils::make_thin_copy(&backing, &target)?;
+                    if size > image_size {
+                        disk_utils::grow_image(&target, size)?;
+                    }
 
-            // Set permissions
-            let mut perms = fs::metadata(&target)?.permissions();
-            perms.set_mode(0o766);
-            fs::set_permissions(&target, perms)?;
+                    // Set permissions
+                    let mut perms = fs::metadata(&target)?.permissions();
+                    perms.set_mode(0o766);
+                    fs::set_permissions(&target, perms)?;
+                }
+            };
 
             // Push disk path to vm def
-            vm.disk.push(Disk {
-                name: disk.name.clone(),
-                path: target,
-                readonly: Some(false),
-            })
+            vm.disk.push(vm_disk)
         }
     }
     Ok(())
@@ -175,7 +235,101 @@ pub struct VmNet {
     pub name: String,
     #[serde(rename = "type")]
     pub _type: NetType,
+    /// Switch to plug the interface into, the first declared one if none.
+    pub switch: Option<String>,
+    /// Vlan the interface is an access port of (untagged traffic).
+    pub vlan: Option<u16>,
+    /// Vlans the interface is a trunk port of (tagged traffic).
+    pub trunks: Option<Vec<u16>>,
+    /// Rate limits of the interface.
+    pub bandwidth: Option<Bandwidth>,
 }
+impl VmNet {
+    /// Return the mac address set in template, if any.
+    pub fn get_mac(&self) -> Option<String> {
+        match &self._type {
+            NetType::Vhost(v) => v.mac.clone(),
+            NetType::Tap(v) | NetType::MacVTap(v) => v.mac.clone(),
+        }
+    }
+    /// Check vlan and rate limit settings.
+    pub fn validate(&self) -> Result<(), VirshleError> {
+        if self.vlan.is_some() && self.trunks.is_some() {
+            let message = format!("Network {:#?} has both a vlan and trunks.", self.name);
+            let help = "Set either vlan (access port) or trunks (trunk port).";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        let vlans = self.vlan.iter().chain(self.trunks.iter().flatten());
+        for vlan in vlans {
+            if !(1..=4094).contains(vlan) {
+                let message = format!("Network {:#?} has an invalid vlan {}.", self.name, vlan);
+                let help = "Vlan ids range from 1 to 4094.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+        }
+        if let Some(bandwidth) = &self.bandwidth {
+            // Limits are applied on the interface ovs port.
+            if let NetType::MacVTap(_) = self._type {
+                let message = format!("Network {:#?} can't be rate limited.", self.name);
+                let help = "Macvtap interfaces are not plugged into ovs, use a tap or vhost one.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+            bandwidth.get_ingress()?;
+            // Ovs polices in kbit per second, a lower rate would lift the limit.
+            if let Some(egress) = bandwidth.get_egress()? {
+                if egress < 1000 {
+                    let message = format!("Network {:#?} egress rate is too low.", self.name);
+                    let help = "Set an egress rate of at least 1kbit.";
+                    return Err(LibError::builder().msg(&message).help(help).build().into());
+                }
+            }
+            bandwidth.get_burst()?;
+        }
+        Ok(())
+    }
+    /// Drop the static mac address, so it is derived from vm uuid instead.
+    pub fn without_mac(mut self) -> Self {
+        match &mut self._type {
+            NetType::Vhost(v) => v.mac = None,
+            NetType::Tap(v) | NetType::MacVTap(v) => v.mac = None,
+        }
+        self
+    }
+}
+/// Network interface rate limits, as seen from the vm.
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
+pub struct Bandwidth {
+    /// Traffic to the vm (ex: "100Mbit").
+    pub ingress: Option<String>,
+    /// Traffic from the vm (ex: "50Mbit").
+    pub egress: Option<String>,
+    /// Burst size (ex: "10Mbit"), ovs default if none.
+    pub burst: Option<String>,
+}
+impl Bandwidth {
+    /// Return the ingress rate in bits per second.
+    pub fn get_ingress(&self) -> Result<Option<u64>, VirshleError> {
+        self.ingress
+            .as_deref()
+            .map(net_utils::reverse_human_bits)
+            .transpose()
+    }
+    /// Return the egress rate in bits per second.
+    pub fn get_egress(&self) -> Result<Option<u64>, VirshleError> {
+        self.egress
+            .as_deref()
+            .map(net_utils::reverse_human_bits)
+            .transpose()
+    }
+    /// Return the burst size in bits.
+    pub fn get_burst(&self) -> Result<Option<u64>, VirshleError> {
+        self.burst
+            .as_deref()
+            .map(net_utils::reverse_human_bits)
+            .transpose()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
 #[serde(rename_all = "snake_case")]
 pub enum NetType {
@@ -199,14 +353,14 @@ impl fmt::Display for NetType {
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
 pub struct Tap {
-    // Set static mac address or random if none.
+    // Set static mac address, or one derived from vm uuid and network name if none.
     pub mac: Option<String>,
     // Request a static ipv4 ip on the interface.
     pub ip: Option<String>,
 }
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
 pub struct Vhost {
-    // Set static mac address or random if none.
+    // Set static mac address, or one derived from vm uuid and network name if none.
     pub mac: Option<String>,
     // Request a static ipv4 ip on the interface.
     pub ip: Option<String>,
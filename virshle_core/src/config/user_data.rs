@@ -1,6 +1,8 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use bon::bon;
+use regex::Regex;
 use uuid::Uuid;
 
 // Database
@@ -13,23 +15,267 @@ use sea_orm::{prelude::*, ActiveValue, InsertResult, IntoActiveModel};
 use miette::Result;
 use virshle_error::{LibError, VirshleError};
 
-#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+/*
+* User-data is checked while deserialized,
+* so that toml errors point at the faulty value.
+*/
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
 pub struct UserData {
     pub user: Vec<User>,
+    /// Files written to the vm filesystem.
+    #[serde(default)]
+    pub write_files: Vec<WriteFile>,
+    /// Shell commands run on boot.
+    pub run_commands: Option<RunCommands>,
+    /// Timezone (ex: "Europe/Paris").
+    #[serde(default, deserialize_with = "de_timezone")]
+    pub timezone: Option<String>,
+    /// Locale (ex: "en_US.UTF-8").
+    #[serde(default, deserialize_with = "de_locale")]
+    pub locale: Option<String>,
     /// Only purpose is to remain in database for further VM identification.
     pub account: Option<Account>,
 }
-#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
 pub struct User {
+    #[serde(deserialize_with = "de_unix_name")]
     pub name: String,
     pub ssh: Option<SshParams>,
+    /// Supplementary groups, created if missing.
+    #[serde(default, deserialize_with = "de_unix_names")]
+    pub groups: Vec<String>,
+    /// Login shell (ex: "/bin/bash").
+    #[serde(default, deserialize_with = "de_shell")]
+    pub shell: Option<String>,
+    /// Grant passwordless sudo.
+    pub sudo: Option<bool>,
+    /// Password hash, as generated by `mkpasswd` (never plain text).
+    #[serde(default, deserialize_with = "de_hashed_password")]
+    pub hashed_password: Option<String>,
 }
-#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
 pub struct SshParams {
+    #[serde(deserialize_with = "de_authorized_keys")]
     pub authorized_keys: Vec<String>,
 }
 
-#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+/// A file to write in the vm, from plain text or base64 encoded content.
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
+#[serde(try_from = "RawWriteFile")]
+pub struct WriteFile {
+    /// Absolute path of the file in the vm.
+    pub path: String,
+    /// Octal permissions (ex: "0644").
+    pub mode: Option<String>,
+    /// Owner, as "user" or "user:group".
+    pub owner: Option<String>,
+    pub content: Option<String>,
+    /// Base64 encoded content, for binary files.
+    pub base64: Option<String>,
+}
+#[derive(Deserialize)]
+struct RawWriteFile {
+    #[serde(deserialize_with = "de_absolute_path")]
+    path: String,
+    #[serde(default, deserialize_with = "de_mode")]
+    mode: Option<String>,
+    #[serde(default, deserialize_with = "de_owner")]
+    owner: Option<String>,
+    content: Option<String>,
+    #[serde(default, deserialize_with = "de_base64")]
+    base64: Option<String>,
+}
+impl TryFrom<RawWriteFile> for WriteFile {
+    type Error = String;
+    fn try_from(e: RawWriteFile) -> Result<Self, Self::Error> {
+        if e.content.is_some() == e.base64.is_some() {
+            return Err(format!(
+                "file {:?} must have exactly one of `content` or `base64`",
+                e.path
+            ));
+        }
+        Ok(Self {
+            path: e.path,
+            mode: e.mode,
+            owner: e.owner,
+            content: e.content,
+            base64: e.base64,
+        })
+    }
+}
+impl WriteFile {
+    /// Return the file content, base64 encoded.
+    pub fn to_base64(&self) -> String {
+        match (&self.base64, &self.content) {
+            (Some(base64), _) => base64.to_owned(),
+            (None, Some(content)) => BASE64.encode(content),
+            (None, None) => "".to_owned(),
+        }
+    }
+}
+
+/// Shell commands, run on every boot.
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
+pub struct RunCommands {
+    /// Run before network is up.
+    #[serde(default)]
+    pub pre_net: Vec<String>,
+    /// Run after network is up.
+    #[serde(default)]
+    pub post_net: Vec<String>,
+}
+
+// Checks
+fn check_unix_name(name: &str) -> Result<(), String> {
+    let re = Regex::new(r"^[a-z_][a-z0-9_-]{0,31}$").unwrap();
+    match re.is_match(name) {
+        true => Ok(()),
+        false => Err(format!(
+            "invalid name {name:?}, expected lowercase letters, digits, '_' or '-' (max 32 chars)"
+        )),
+    }
+}
+fn check_absolute_path(path: &str) -> Result<(), String> {
+    match path.starts_with('/') && !path.contains('\0') {
+        true => Ok(()),
+        false => Err(format!("invalid path {path:?}, expected an absolute path")),
+    }
+}
+fn check_hashed_password(hash: &str) -> Result<(), String> {
+    let re = Regex::new(r"^\$[0-9a-z]+\$[^\s:]+$").unwrap();
+    match re.is_match(hash) {
+        true => Ok(()),
+        false => Err(
+            "invalid password hash, generate one with `mkpasswd -m sha-512` (never plain text)"
+                .to_owned(),
+        ),
+    }
+}
+fn check_authorized_key(key: &str) -> Result<(), String> {
+    let re = Regex::new(
+        r"^(ssh-[a-z0-9-]+|ecdsa-sha2-[a-z0-9-]+|sk-[a-z0-9-@.]+) [A-Za-z0-9+/=]+( .*)?$",
+    )
+    .unwrap();
+    match re.is_match(key) {
+        true => Ok(()),
+        false => Err(format!(
+            "invalid ssh public key {key:?}, expected \"<type> <base64> [comment]\""
+        )),
+    }
+}
+fn check_mode(mode: &str) -> Result<(), String> {
+    let re = Regex::new(r"^0?[0-7]{3,4}$").unwrap();
+    match re.is_match(mode) {
+        true => Ok(()),
+        false => Err(format!(
+            "invalid mode {mode:?}, expected octal permissions (ex: \"0644\")"
+        )),
+    }
+}
+fn check_owner(owner: &str) -> Result<(), String> {
+    match owner.split_once(':') {
+        Some((user, group)) => check_unix_name(user).and(check_unix_name(group)),
+        None => check_unix_name(owner),
+    }
+}
+fn check_base64(content: &str) -> Result<(), String> {
+    match BASE64.decode(content) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("invalid base64 content: {e}")),
+    }
+}
+fn check_timezone(timezone: &str) -> Result<(), String> {
+    let re = Regex::new(r"^[A-Za-z0-9_+-]+(/[A-Za-z0-9_+-]+)*$").unwrap();
+    match re.is_match(timezone) {
+        true => Ok(()),
+        false => Err(format!(
+            "invalid timezone {timezone:?} (ex: \"Europe/Paris\")"
+        )),
+    }
+}
+fn check_locale(locale: &str) -> Result<(), String> {
+    let re =
+        Regex::new(r"^[A-Za-z]{1,8}(_[A-Za-z0-9]+)?(\.[A-Za-z0-9-]+)?(@[A-Za-z0-9]+)?$").unwrap();
+    match re.is_match(locale) {
+        true => Ok(()),
+        false => Err(format!("invalid locale {locale:?} (ex: \"en_US.UTF-8\")")),
+    }
+}
+
+// Checked deserializers
+fn de_checked<'de, D>(
+    deserializer: D,
+    check: fn(&str) -> Result<(), String>,
+) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    check(&value).map_err(D::Error::custom)?;
+    Ok(value)
+}
+fn de_checked_option<'de, D>(
+    deserializer: D,
+    check: fn(&str) -> Result<(), String>,
+) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    if let Some(value) = &value {
+        check(value).map_err(D::Error::custom)?;
+    }
+    Ok(value)
+}
+fn de_checked_vec<'de, D>(
+    deserializer: D,
+    check: fn(&str) -> Result<(), String>,
+) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = Vec::<String>::deserialize(deserializer)?;
+    for value in &values {
+        check(value).map_err(D::Error::custom)?;
+    }
+    Ok(values)
+}
+fn de_unix_name<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    de_checked(d, check_unix_name)
+}
+fn de_unix_names<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    de_checked_vec(d, check_unix_name)
+}
+fn de_authorized_keys<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    de_checked_vec(d, check_authorized_key)
+}
+fn de_absolute_path<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    de_checked(d, check_absolute_path)
+}
+fn de_shell<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    de_checked_option(d, check_absolute_path)
+}
+fn de_hashed_password<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    de_checked_option(d, check_hashed_password)
+}
+fn de_mode<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    de_checked_option(d, check_mode)
+}
+fn de_owner<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    de_checked_option(d, check_owner)
+}
+fn de_base64<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    de_checked_option(d, check_base64)
+}
+fn de_timezone<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    de_checked_option(d, check_timezone)
+}
+fn de_locale<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    de_checked_option(d, check_locale)
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
 pub struct Account {
     pub id: Option<i32>,
     pub uuid: Uuid,
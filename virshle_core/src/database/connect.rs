@@ -39,6 +39,13 @@ pub async fn fresh_db() -> Result<DatabaseConnection, VirshleError> {
     Ok(db)
 }
 
+/// Apply pending migrations on an existing database.
+pub async fn migrate_db() -> Result<DatabaseConnection, VirshleError> {
+    let db = connect_db().await?;
+    Migrator::up(&db, None).await?;
+    Ok(db)
+}
+
 pub async fn connect_or_fresh_db() -> Result<DatabaseConnection, VirshleError> {
     match connect_db().await {
         Ok(db) => Ok(db),
@@ -4,5 +4,8 @@ pub mod prelude;
 
 pub mod account;
 pub mod account_vm;
+pub mod image;
 pub mod lease;
+pub mod snapshot;
 pub mod vm;
+pub mod vm_image;
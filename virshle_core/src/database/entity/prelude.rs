@@ -2,5 +2,8 @@
 
 pub use super::account::Entity as Account;
 pub use super::account_vm::Entity as AccountVm;
+pub use super::image::Entity as Image;
 pub use super::lease::Entity as Lease;
+pub use super::snapshot::Entity as Snapshot;
 pub use super::vm::Entity as Vm;
+pub use super::vm_image::Entity as VmImage;
@@ -14,6 +14,7 @@ pub struct Model {
     pub definition: Json,
     pub created_at: DateTime,
     pub updated_at: DateTime,
+    pub running: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -22,6 +23,10 @@ pub enum Relation {
     AccountVm,
     #[sea_orm(has_many = "super::lease::Entity")]
     Lease,
+    #[sea_orm(has_many = "super::snapshot::Entity")]
+    Snapshot,
+    #[sea_orm(has_many = "super::vm_image::Entity")]
+    VmImage,
 }
 
 impl Related<super::account_vm::Entity> for Entity {
@@ -36,6 +41,18 @@ impl Related<super::lease::Entity> for Entity {
     }
 }
 
+impl Related<super::snapshot::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Snapshot.def()
+    }
+}
+
+impl Related<super::vm_image::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::VmImage.def()
+    }
+}
+
 impl Related<super::account::Entity> for Entity {
     fn to() -> RelationDef {
         super::account_vm::Relation::Account.def()
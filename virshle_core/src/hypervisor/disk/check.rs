@@ -0,0 +1,201 @@
+use super::{utils, Disk};
+use crate::utils::display::{display_some_num, display_some_string};
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tabled::{
+    settings::{disable::Remove, location::ByColumnName, Style},
+    Table, Tabled,
+};
+
+// Error Handling
+use log::{log_enabled, Level};
+use miette::Result;
+use tracing::{info, warn};
+use virshle_error::VirshleError;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FsckStatus {
+    /// No error found.
+    Clean,
+    /// Errors found and fixed.
+    Repaired,
+    /// Errors found and left in place.
+    Corrupted,
+    /// No checker for this filesystem.
+    Skipped,
+    /// The checker couldn't run.
+    Failed,
+}
+impl fmt::Display for FsckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let string = match self {
+            FsckStatus::Clean => "clean",
+            FsckStatus::Repaired => "repaired",
+            FsckStatus::Corrupted => "corrupted",
+            FsckStatus::Skipped => "skipped",
+            FsckStatus::Failed => "failed",
+        };
+        write!(f, "{}", string)
+    }
+}
+impl FsckStatus {
+    /// Interpret fsck exit codes (see fsck(8)).
+    /// In check mode, any error found is left in place.
+    pub fn from_exit_code(code: i32, repair: bool) -> Self {
+        match (code, repair) {
+            (0, _) => FsckStatus::Clean,
+            (1 | 2, true) => FsckStatus::Repaired,
+            (1 | 2 | 4, _) => FsckStatus::Corrupted,
+            _ => FsckStatus::Failed,
+        }
+    }
+    /// Interpret qemu-img check exit codes (see qemu-img(1)).
+    /// Leaked clusters only waste space and are considered clean.
+    pub fn from_qemu_img_check(code: i32, output: &str) -> Self {
+        match code {
+            0 if output.contains("were found and repaired") => FsckStatus::Repaired,
+            0 | 3 => FsckStatus::Clean,
+            2 => FsckStatus::Corrupted,
+            _ => FsckStatus::Failed,
+        }
+    }
+}
+
+/// Filesystem check result for a single disk partition.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Tabled)]
+pub struct PartitionReport {
+    pub disk: String,
+    /// Partition number, none if the filesystem spans the whole disk.
+    #[tabled(display = "display_some_num")]
+    pub partition: Option<u64>,
+    #[tabled(display = "display_some_string")]
+    pub fstype: Option<String>,
+    pub status: FsckStatus,
+    /// Checker output.
+    pub output: String,
+}
+impl PartitionReport {
+    pub fn display(items: &Vec<Self>) -> Result<(), VirshleError> {
+        let mut res = Table::new(items);
+        if !log_enabled!(Level::Info) {
+            res.with(Remove::column(ByColumnName::new("output")));
+        }
+        res.with(Style::rounded());
+        println!("{}", res);
+        Ok(())
+    }
+}
+
+impl Disk {
+    /// Check every partition filesystem of the disk,
+    /// and fix errors if `repair` is set.
+    ///
+    /// The disk must not be in use by a running vm.
+    pub fn check(&self, repair: bool) -> Result<Vec<PartitionReport>, VirshleError> {
+        if utils::is_qcow2(&self.path)? {
+            return self.check_qcow2(repair);
+        }
+        let device = utils::attach_loop(&self.path)?;
+        let res = self._check(&device, repair);
+        utils::detach_loop(&device)?;
+        res
+    }
+    /// Check the qcow2 image metadata first,
+    /// then the partitions through a network block device.
+    fn check_qcow2(&self, repair: bool) -> Result<Vec<PartitionReport>, VirshleError> {
+        let (status, output) = match utils::qemu_img_check(&self.path, repair) {
+            Ok((code, output)) => (FsckStatus::from_qemu_img_check(code, &output), output),
+            Err(e) => (FsckStatus::Failed, e.to_string()),
+        };
+        let image = PartitionReport {
+            disk: self.name.clone(),
+            partition: None,
+            fstype: Some("qcow2".to_owned()),
+            status: status.clone(),
+            output,
+        };
+        match status {
+            // Don't expose a broken image to the kernel.
+            FsckStatus::Corrupted | FsckStatus::Failed => {
+                warn!("[disk]: {} image is {}", self.name, status);
+                return Ok(vec![image]);
+            }
+            _ => info!("[disk]: {} image is {}", self.name, status),
+        };
+
+        let device = utils::attach_nbd(&self.path)?;
+        let res = self._check(&device, repair);
+        utils::detach_nbd(&device)?;
+
+        let mut reports = vec![image];
+        reports.extend(res?);
+        Ok(reports)
+    }
+    fn _check(&self, device: &str, repair: bool) -> Result<Vec<PartitionReport>, VirshleError> {
+        let mut reports = vec![];
+        for partition in utils::get_partitions(device)? {
+            // Partitions are named after the device (ex: /dev/loop0p1, /dev/nbd0p1).
+            let number = partition
+                .strip_prefix(&format!("{device}p"))
+                .and_then(|e| e.parse().ok());
+
+            let fstype = utils::get_fstype(&partition)?;
+            let (status, output) = match &fstype {
+                Some(fstype) => match utils::fsck(&partition, fstype, repair) {
+                    Ok(Some((code, output))) => (FsckStatus::from_exit_code(code, repair), output),
+                    Ok(None) => (FsckStatus::Skipped, "".to_owned()),
+                    Err(e) => (FsckStatus::Failed, e.to_string()),
+                },
+                None => (FsckStatus::Skipped, "".to_owned()),
+            };
+            match status {
+                FsckStatus::Corrupted | FsckStatus::Failed => {
+                    warn!("[disk]: {} partition {:?} is {}", self.name, number, status)
+                }
+                _ => info!("[disk]: {} partition {:?} is {}", self.name, number, status),
+            };
+            reports.push(PartitionReport {
+                disk: self.name.clone(),
+                partition: number,
+                fstype,
+                status,
+                output,
+            });
+        }
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn fsck_exit_codes() {
+        assert_eq!(FsckStatus::from_exit_code(0, false), FsckStatus::Clean);
+        assert_eq!(FsckStatus::from_exit_code(1, false), FsckStatus::Corrupted);
+        assert_eq!(FsckStatus::from_exit_code(4, false), FsckStatus::Corrupted);
+        assert_eq!(FsckStatus::from_exit_code(1, true), FsckStatus::Repaired);
+        assert_eq!(FsckStatus::from_exit_code(2, true), FsckStatus::Repaired);
+        assert_eq!(FsckStatus::from_exit_code(4, true), FsckStatus::Corrupted);
+        assert_eq!(FsckStatus::from_exit_code(8, true), FsckStatus::Failed);
+    }
+    #[test]
+    fn qemu_img_check_exit_codes() {
+        let repaired = "The following inconsistencies were found and repaired:";
+        assert_eq!(FsckStatus::from_qemu_img_check(0, ""), FsckStatus::Clean);
+        assert_eq!(
+            FsckStatus::from_qemu_img_check(0, repaired),
+            FsckStatus::Repaired
+        );
+        assert_eq!(FsckStatus::from_qemu_img_check(3, ""), FsckStatus::Clean);
+        assert_eq!(
+            FsckStatus::from_qemu_img_check(2, ""),
+            FsckStatus::Corrupted
+        );
+        assert_eq!(FsckStatus::from_qemu_img_check(1, ""), FsckStatus::Failed);
+    }
+}
@@ -0,0 +1,233 @@
+/*
+* At-rest encryption of vm disks.
+*
+* Encrypted disks are LUKS containers, unlocked with a random per-vm key.
+* The key is stored encrypted with a key derived from the node ed25519 identity,
+* so it is only usable on the node that created the vm.
+*/
+use super::{utils, Disk};
+use crate::config::Config;
+use crate::exec::exec_cmds;
+use crate::hypervisor::Vm;
+
+use chacha20poly1305::aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use russh::keys::PrivateKey;
+use sha2::{Digest, Sha256};
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Error Handling
+use miette::Result;
+use tracing::{info, trace};
+use virshle_error::{LibError, VirshleError};
+
+/// Room left for the LUKS2 header in front of the guest data.
+pub const LUKS_HEADER_SIZE: u64 = 16 * 1024 * 1024;
+/// Vm disk key length, in bytes.
+const KEY_SIZE: usize = 64;
+const NONCE_SIZE: usize = 12;
+
+impl Vm {
+    pub fn crypt(&self) -> VmCryptMethods<'_> {
+        VmCryptMethods { vm: self }
+    }
+}
+pub struct VmCryptMethods<'a> {
+    pub vm: &'a Vm,
+}
+impl VmCryptMethods<'_> {
+    /// Return the device mapper name of an unlocked disk.
+    pub fn get_mapper_name(&self, disk: &Disk) -> String {
+        format!("virshle-{}-{}", self.vm.uuid, disk.name)
+    }
+    /// Return the device the vm should use for a disk:
+    /// the unlocked device for encrypted disks, the disk file otherwise.
+    /// Usually at : `/dev/mapper/virshle-{vm_uuid}-{disk_name}`.
+    pub fn get_device_path(&self, disk: &Disk) -> String {
+        match disk.encrypted {
+            Some(true) => format!("/dev/mapper/{}", self.get_mapper_name(disk)),
+            _ => disk.path.to_owned(),
+        }
+    }
+    pub fn has_encrypted_disks(&self) -> bool {
+        self.vm.disk.iter().any(|e| e.encrypted == Some(true))
+    }
+
+    /// Derive the key encryption key from the node identity.
+    /// Bound to the vm uuid so a key file can't be swapped between vms.
+    fn get_node_key(&self) -> Result<Key, VirshleError> {
+        let config = Config::get()?;
+        let Some(pem) = config.node.private_key else {
+            let message = "Couldn't encrypt vm disk key.";
+            let help = "Set the node private_key in configuration.";
+            return Err(LibError::builder().msg(message).help(help).build().into());
+        };
+        let private_key = PrivateKey::from_openssh(&pem).map_err(|e| {
+            LibError::builder()
+                .msg("Couldn't read the node private_key.")
+                .help(&e.to_string())
+                .build()
+        })?;
+        let Some(keypair) = private_key.key_data().ed25519() else {
+            let message = "Couldn't encrypt vm disk key.";
+            let help = "The node private_key must be an ed25519 key.";
+            return Err(LibError::builder().msg(message).help(help).build().into());
+        };
+        let digest = Sha256::new()
+            .chain_update(b"virshle-disk-key")
+            .chain_update(keypair.private.to_bytes())
+            .chain_update(self.vm.uuid.as_bytes())
+            .finalize();
+        Ok(*Key::from_slice(&digest))
+    }
+    /// Generate the vm disk key and store it encrypted, if not already.
+    pub fn create_key(&self) -> Result<(), VirshleError> {
+        let path = self.vm.get_disk_key()?;
+        if Path::new(&path).exists() {
+            return Ok(());
+        }
+        let mut key = vec![0u8; KEY_SIZE];
+        OsRng.fill_bytes(&mut key);
+
+        let cipher = ChaCha20Poly1305::new(&self.get_node_key()?);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = cipher.encrypt(&nonce, key.as_slice()).map_err(|_| {
+            LibError::builder()
+                .msg("Couldn't encrypt vm disk key.")
+                .help("Encryption with the node key failed.")
+                .build()
+        })?;
+        key.fill(0);
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.write_all(&nonce)?;
+        file.write_all(&sealed)?;
+        trace!("created disk key for vm {}", self.vm.name);
+        Ok(())
+    }
+    /// Return the decrypted vm disk key.
+    fn read_key(&self) -> Result<Vec<u8>, VirshleError> {
+        let path = self.vm.get_disk_key()?;
+        let bytes = fs::read(&path)?;
+        if bytes.len() <= NONCE_SIZE {
+            let message = format!("Couldn't read disk key of vm {:#?}.", self.vm.name);
+            let help = format!("Key file {:#?} is corrupted.", path);
+            return Err(LibError::builder().msg(&message).help(&help).build().into());
+        }
+        let (nonce, sealed) = bytes.split_at(NONCE_SIZE);
+        let cipher = ChaCha20Poly1305::new(&self.get_node_key()?);
+        let key = cipher
+            .decrypt(Nonce::from_slice(nonce), sealed)
+            .map_err(|_| {
+                let message = format!("Couldn't decrypt disk key of vm {:#?}.", self.vm.name);
+                LibError::builder()
+                    .msg(&message)
+                    .help("Was the vm created with another node identity?")
+                    .build()
+            })?;
+        Ok(key)
+    }
+    /// Overwrite and remove the vm disk key.
+    /// Encrypted disks are unrecoverable afterwards.
+    pub fn wipe_key(&self) -> Result<(), VirshleError> {
+        let path = self.vm.get_disk_key()?;
+        if Path::new(&path).exists() {
+            let len = fs::metadata(&path)?.len() as usize;
+            let mut file = OpenOptions::new().write(true).open(&path)?;
+            file.write_all(&vec![0u8; len])?;
+            file.sync_all()?;
+            fs::remove_file(&path)?;
+            info!("wiped disk key of vm {}", self.vm.name);
+        }
+        Ok(())
+    }
+
+    /// Run cryptsetup with the vm disk key on stdin,
+    /// so the key never touches the filesystem or the process list.
+    fn cryptsetup(&self, args: &[&str]) -> Result<(), VirshleError> {
+        let mut key = self.read_key()?;
+        let mut child = Command::new("sudo")
+            .arg("cryptsetup")
+            .args(args)
+            .arg("--key-file=-")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(&key)?;
+        }
+        key.fill(0);
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            let message = format!("[disk]: cryptsetup {} failed.", args.join(" "));
+            let help = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            return Err(LibError::builder().msg(&message).help(&help).build().into());
+        }
+        Ok(())
+    }
+    /// Create a LUKS container at the disk path,
+    /// and fill it with the content of the backing image.
+    pub fn format(&self, disk: &Disk, backing: &str, size: u64) -> Result<(), VirshleError> {
+        utils::make_sparse_file(&disk.path, size + LUKS_HEADER_SIZE)?;
+        let mut perms = fs::metadata(&disk.path)?.permissions();
+        perms.set_mode(0o600);
+        fs::set_permissions(&disk.path, perms)?;
+
+        self.cryptsetup(&["luksFormat", "--batch-mode", "--type", "luks2", &disk.path])?;
+        self.open(disk)?;
+        // Zeroes are written too: unwritten ciphertext would read back as garbage,
+        // so the container ends up fully allocated on host.
+        let device = self.get_device_path(disk);
+        let cmds = vec![format!(
+            "sudo qemu-img convert -n -O raw {backing} {device}"
+        )];
+        let res = exec_cmds("disk", cmds);
+        self.close(disk)?;
+        res
+    }
+    /// Unlock an encrypted disk.
+    pub fn open(&self, disk: &Disk) -> Result<(), VirshleError> {
+        if Path::new(&self.get_device_path(disk)).exists() {
+            return Ok(());
+        }
+        let name = self.get_mapper_name(disk);
+        self.cryptsetup(&["open", "--type", "luks2", &disk.path, &name])?;
+        trace!("unlocked disk {} of vm {}", disk.name, self.vm.name);
+        Ok(())
+    }
+    /// Lock an encrypted disk.
+    pub fn close(&self, disk: &Disk) -> Result<(), VirshleError> {
+        if !Path::new(&self.get_device_path(disk)).exists() {
+            return Ok(());
+        }
+        let name = self.get_mapper_name(disk);
+        let cmds = vec![format!("sudo cryptsetup close {name}")];
+        exec_cmds("disk", cmds)?;
+        trace!("locked disk {} of vm {}", disk.name, self.vm.name);
+        Ok(())
+    }
+    /// Unlock every encrypted disk of the vm.
+    pub fn open_all(&self) -> Result<(), VirshleError> {
+        for disk in self.vm.disk.iter().filter(|e| e.encrypted == Some(true)) {
+            self.open(disk)?;
+        }
+        Ok(())
+    }
+    /// Lock every encrypted disk of the vm.
+    pub fn close_all(&self) -> Result<(), VirshleError> {
+        for disk in self.vm.disk.iter().filter(|e| e.encrypted == Some(true)) {
+            self.close(disk)?;
+        }
+        Ok(())
+    }
+}
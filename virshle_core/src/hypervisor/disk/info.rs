@@ -16,8 +16,12 @@ use virshle_error::VirshleError;
 pub struct DiskInfo {
     pub name: String,
     pub path: String,
+    /// Virtual size (as seen by the guest).
     #[tabled(display = "display_some_bytes")]
     pub size: Option<u64>,
+    /// Space really taken on host.
+    #[tabled(display = "display_some_bytes")]
+    pub allocated_size: Option<u64>,
     #[tabled(display = "display_some_bool")]
     pub readonly: Option<bool>,
 }
@@ -36,6 +40,7 @@ impl DiskInfo {
             name: e.name.clone(),
             path: e.path.clone(),
             size: e.get_size().ok(),
+            allocated_size: e.get_allocated_size().ok(),
             readonly: e.readonly,
         };
         Ok(info)
@@ -45,10 +50,23 @@ impl DiskInfo {
             name: e.name.clone(),
             path: e.path.clone(),
             size: e.get_size().ok(),
+            allocated_size: None,
             readonly: e.readonly,
         };
         Ok(info)
     }
+    /// Display virtual size, and allocated size if known.
+    fn display_sizes(&self) -> Option<String> {
+        match (self.size, self.allocated_size) {
+            (Some(size), Some(allocated)) => Some(format!(
+                "{}, {} allocated",
+                human_bytes(size as f64),
+                human_bytes(allocated as f64)
+            )),
+            (Some(size), None) => Some(human_bytes(size as f64)),
+            _ => None,
+        }
+    }
 }
 
 impl DiskInfo {
@@ -57,9 +75,7 @@ impl DiskInfo {
         if let Some(disks) = disks {
             let mut summary: Vec<String> = vec![];
             for e in disks {
-                if let Some(size) = e.size {
-                    let size = human_bytes(size as f64);
-
+                if let Some(size) = e.display_sizes() {
                     let oneline = format!("{} -> {} ({size})", e.name, e.path);
                     summary.push(oneline);
                 } else {
@@ -76,9 +92,7 @@ impl DiskInfo {
         if let Some(disks) = disks {
             let mut summary: Vec<String> = vec![];
             for e in disks {
-                if let Some(size) = e.size {
-                    let size = human_bytes(size as f64);
-
+                if let Some(size) = e.display_sizes() {
                     let oneline = format!("{} ({size})", e.name);
                     summary.push(oneline);
                 } else {
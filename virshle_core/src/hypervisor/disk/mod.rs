@@ -1,10 +1,14 @@
+mod check;
+pub mod crypt;
 mod info;
 pub mod utils;
+pub use check::{FsckStatus, PartitionReport};
 pub use info::DiskInfo;
 
 // Struct
 use crate::config::DiskTemplate;
-use crate::hypervisor::vm::{InitData, UserData, Vm, VmData};
+use crate::hypervisor::vm::{InitBackend, InitData, UserData, Vm, VmData};
+use crate::hypervisor::vmm::RateLimiterConfig;
 
 // Filesystem
 // use tokio::fs::{self, File};
@@ -28,14 +32,68 @@ pub struct Disk {
     pub name: String,
     pub path: String,
     pub readonly: Option<bool>,
+    /// Name of the cached image this disk is a thin copy of.
+    pub image: Option<String>,
+    /// Declared size (ex: 50GiB).
+    pub size: Option<String>,
+
+    // I/O tuning
+    /// Bypass host page cache (O_DIRECT).
+    pub direct: Option<bool>,
+    pub num_queues: Option<u64>,
+    pub queue_size: Option<u64>,
+    pub rate_limiter: Option<RateLimiterConfig>,
+
+    /// LUKS container, unlocked with the vm disk key.
+    pub encrypted: Option<bool>,
+    /// Data disk added to the vm after creation,
+    /// the only kind of disk that can be removed.
+    pub data: Option<bool>,
 }
 impl Disk {
+    /// Check a disk name before it is used as a file name:
+    /// only letters, digits, '.', '_' and '-' are allowed.
+    pub fn check_name(name: &str) -> Result<(), VirshleError> {
+        let allowed = |e: char| e.is_ascii_alphanumeric() || matches!(e, '.' | '_' | '-');
+        if name.is_empty() || name == "." || name == ".." || !name.chars().all(allowed) {
+            let message = format!("Invalid disk name {:#?}.", name);
+            let help = "Use only letters, digits, '.', '_' or '-'.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        Ok(())
+    }
+    /// Return the disk declared size,
+    /// or its virtual size for disks declared without one.
+    pub fn get_reserved_size(&self) -> Result<u64, VirshleError> {
+        match &self.size {
+            Some(size) => utils::reverse_human_bytes(size),
+            None => self.get_size(),
+        }
+    }
+    /// Return the disk virtual size (as seen by the guest).
     pub fn get_size(&self) -> Result<u64, VirshleError> {
         let path = Path::new(&self.path);
         if path.exists() && path.is_file() {
-            let metadata = std::fs::metadata(path)?;
-            let size = metadata.len();
-            Ok(size)
+            let size = utils::get_virtual_size(&self.path)?;
+            match self.encrypted {
+                // Guest data starts after the LUKS header.
+                Some(true) => Ok(size.saturating_sub(crypt::LUKS_HEADER_SIZE)),
+                _ => Ok(size),
+            }
+        } else {
+            Err(LibError::builder()
+                .msg("Counldn't get disk file size.")
+                .help("Disk doesn't exist or is unreachable")
+                .build()
+                .into())
+        }
+    }
+    /// Return the space the disk really takes on host.
+    /// Much lower than the virtual size for thin copies.
+    pub fn get_allocated_size(&self) -> Result<u64, VirshleError> {
+        let path = Path::new(&self.path);
+        if path.exists() && path.is_file() {
+            utils::get_allocated_size(&self.path)
         } else {
             Err(LibError::builder()
                 .msg("Counldn't get disk file size.")
@@ -46,8 +104,15 @@ impl Disk {
     }
 }
 
+/// Init disk size, the smallest a FAT32 filesystem can fit in.
+/// The image is sparse, it only takes the space of its content on host.
+pub const INIT_DISK_SIZE: u64 = 64 * 1024 * 1024;
+
 /// An ephemeral disk that is mounted/unmounted to vm on boot.
 ///to provision with custom user datas.
+///
+/// The FAT32 image is built and written in userspace,
+/// no root privilege nor loop device is needed.
 #[derive(Debug, Eq, PartialEq)]
 pub struct InitDisk<'a> {
     pub vm: &'a Vm,
@@ -55,60 +120,65 @@ pub struct InitDisk<'a> {
 
 impl<'a> From<&'a InitDisk<'a>> for Disk {
     fn from(e: &InitDisk) -> Self {
-        let disk_dir = e.vm.get_disks_dir().unwrap();
-        let path = format!("{disk_dir}/pipelight-init");
         Self {
             name: "init".to_owned(),
-            path,
+            path: e.get_path().unwrap(),
             ..Default::default() // readonly: Some(true),
         }
     }
 }
 
 impl InitDisk<'_> {
+    /// Return the vm provisioning backend.
+    pub fn get_backend(&self) -> InitBackend {
+        self.vm.init.unwrap_or_default()
+    }
+    /// Return the init disk image path.
+    /// Usually at : `/var/lib/virshle/vm/{vm_uuid}/disk/pipelight-init`,
+    /// or `/var/lib/virshle/vm/{vm_uuid}/disk/cidata` for cloud-init.
+    pub fn get_path(&self) -> Result<String, VirshleError> {
+        let disk_dir = self.vm.get_disks_dir()?;
+        Ok(format!("{disk_dir}/{}", self.get_backend().get_filename()))
+    }
     /*
-     * Write pipelight configuration file to init disk.
+     * Write provisioning files (pipelight configuration or cloud-init seed) to init disk.
      */
     pub fn write_init_files(&self, init_data: &InitData) -> Result<&Self, VirshleError> {
-        let mount_dir = self.vm.get_mount_dir()?;
-        let target = format!("{mount_dir}/pipelight-init/pipelight.toml");
-
-        // Remove old pipeline
-        let path = Path::new(&target);
-        if path.exists() {
-            std::fs::remove_file(path)?;
-        }
-        // Write file to disk
-        let p_config = init_data.to_pipelight_toml_config()?;
-        let bytes = p_config.as_bytes();
-        let mut file = std::fs::File::create(path)?;
-        file.write_all(bytes)?;
-
+        Self::_write_init_files(&self.get_path()?, &self.get_backend(), init_data)?;
         Ok(self)
     }
+    fn _write_init_files(
+        path: &str,
+        backend: &InitBackend,
+        init_data: &InitData,
+    ) -> Result<(), VirshleError> {
+        for (name, content) in init_data.to_files(backend)? {
+            utils::write_fat_file(path, &name, content.as_bytes())?;
+        }
+        trace!("[disk]: wrote init files to {}", path);
+        Ok(())
+    }
     /*
      * Create an init disk on host filesystem.
      */
     pub fn create(&self) -> Result<&Self, VirshleError> {
-        let disk_dir = self.vm.get_disks_dir()?;
-        let source = format!("{disk_dir}/pipelight-init");
-        utils::make_empty_file(&source)?;
-        utils::format_to_vfat(&source)?;
+        let label = self.get_backend().get_label();
+        utils::make_fat_image(&self.get_path()?, INIT_DISK_SIZE, label)?;
         Ok(self)
     }
-
+    /// The image is written in place by `write_init_files`, nothing to mount.
+    /// Only ensures the init disk has been created.
     pub fn mount(&self) -> Result<&Self, VirshleError> {
-        let disk_dir = self.vm.get_disks_dir()?;
-        let source = format!("{disk_dir}/pipelight-init");
-        let mount_dir = self.vm.get_mount_dir()?;
-        let target = format!("{mount_dir}/pipelight-init");
-        utils::mount(&source, &target)?;
+        let path = self.get_path()?;
+        if !Path::new(&path).exists() {
+            let message = format!("[disk]: couldn't open init disk of vm {:#?}.", self.vm.name);
+            let help = "Create the init disk first.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
         Ok(self)
     }
+    /// The image is written in place by `write_init_files`, nothing to unmount.
     pub fn umount(&self) -> Result<&Self, VirshleError> {
-        let mount_dir = self.vm.get_mount_dir()?;
-        let target = format!("{mount_dir}/pipelight-init");
-        utils::umount(&target)?;
         Ok(self)
     }
 }
@@ -116,6 +186,18 @@ impl InitDisk<'_> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn check_disk_names() -> Result<()> {
+        for name in ["data", "data-2", "scratch_disk.v1"] {
+            Disk::check_name(name)?;
+        }
+        for name in ["", ".", "..", "../data", "data/../../etc", "my disk"] {
+            assert!(Disk::check_name(name).is_err());
+        }
+        Ok(())
+    }
 
     #[tokio::test]
     async fn test_init_disk() -> Result<()> {
@@ -133,4 +215,54 @@ mod test {
         // println!("{:#?}", res);
         Ok(())
     }
+    #[test]
+    fn test_unprivileged_init_disk() -> Result<()> {
+        let path = std::env::temp_dir().join("virshle_test_pipelight-init");
+        let path = path.to_str().unwrap();
+        let backend = InitBackend::Pipelight;
+        utils::make_fat_image(path, INIT_DISK_SIZE, backend.get_label())?;
+
+        let init_data = InitData {
+            vm_data: Some(VmData::try_from(&Vm::default())?),
+            user_data: None,
+        };
+        InitDisk::_write_init_files(path, &backend, &init_data)?;
+
+        // Read the pipeline back.
+        let content = utils::read_fat_file(path, "pipelight.toml")?;
+        assert_eq!(content, init_data.to_pipelight_toml_config()?);
+
+        fs::remove_file(path).into_diagnostic()?;
+        Ok(())
+    }
+    #[test]
+    fn test_unprivileged_cloud_init_disk() -> Result<()> {
+        let path = std::env::temp_dir().join("virshle_test_cidata");
+        let path = path.to_str().unwrap();
+        let backend = InitBackend::CloudInit;
+        utils::make_fat_image(path, INIT_DISK_SIZE, backend.get_label())?;
+
+        let init_data = InitData {
+            vm_data: Some(VmData::try_from(&Vm::default())?),
+            user_data: None,
+        };
+        InitDisk::_write_init_files(path, &backend, &init_data)?;
+
+        // The seed is found by label, with the files at the root.
+        let file = File::open(path).into_diagnostic()?;
+        let filesystem = fatfs::FileSystem::new(file, fatfs::FsOptions::new()).into_diagnostic()?;
+        assert_eq!(filesystem.volume_label().to_lowercase(), "cidata");
+        let mut names: Vec<String> = filesystem
+            .root_dir()
+            .iter()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["meta-data", "network-config", "user-data"]);
+        drop(filesystem);
+
+        fs::remove_file(path).into_diagnostic()?;
+        Ok(())
+    }
 }
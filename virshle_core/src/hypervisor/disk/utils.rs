@@ -1,15 +1,20 @@
 // Process
 use crate::exec::exec_cmds;
+use pipelight_exec::{Process, Status};
 
 // Filesystem
 // use tokio::fs::{self, File};
 // use tokio::io::AsyncWrite;
 use bytes::BytesMut;
 use std::fs::{self, File, OpenOptions};
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
-use sys_mount::{unmount, UnmountFlags};
-use sys_mount::{FilesystemType, Mount, MountFlags, SupportedFilesystems};
+
+// Fat filesystem
+use fatfs::{FatType, FileSystem, FormatVolumeOptions, FsOptions};
+
+use serde::Deserialize;
 
 // Error Handling
 use log::{debug, error, info, trace};
@@ -50,6 +55,11 @@ pub fn reverse_human_bytes(string: &str) -> Result<u64, VirshleError> {
         let num: &str = string.trim_end_matches("B");
         let int: u64 = num.parse()?;
         Ok(int)
+    } else if string.ends_with(['K', 'M', 'G', 'T']) {
+        // Could be read as powers of 1000 or 1024.
+        let message = format!("Ambiguous size {:#?}.", string);
+        let help = format!("Use binary units instead (ex: {}iB).", string);
+        Err(LibError::builder().msg(&message).help(&help).build().into())
     } else {
         Err(LibError::builder()
             .msg("Couldn't convert human readable string to bytes")
@@ -59,11 +69,128 @@ pub fn reverse_human_bytes(string: &str) -> Result<u64, VirshleError> {
     }
 }
 
-pub fn make_empty_file(path: &str) -> Result<(), VirshleError> {
-    let cmds = vec![format!("dd if=/dev/null of={path} bs=1M seek=10")];
+/// Qcow2 image files start with "QFI\xfb".
+const QCOW2_MAGIC: [u8; 4] = [b'Q', b'F', b'I', 0xfb];
+
+/// Return true if the file is a qcow2 image.
+pub fn is_qcow2(path: &str) -> Result<bool, VirshleError> {
+    let mut magic = [0u8; 4];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(_) => Ok(magic == QCOW2_MAGIC),
+        // File shorter than a qcow2 header.
+        Err(_) => Ok(false),
+    }
+}
+/// Return the backing file path a qcow2 overlay reads from,
+/// or none for standalone images.
+pub fn get_backing_file(path: &str) -> Result<Option<String>, VirshleError> {
+    if !is_qcow2(path)? {
+        return Ok(None);
+    }
+    // Header: magic(4) version(4) backing_file_offset(8) backing_file_size(4)
+    let mut header = [0u8; 20];
+    let mut file = File::open(path)?;
+    file.read_exact(&mut header)?;
+    let offset = u64::from_be_bytes(header[8..16].try_into().unwrap());
+    let size = u32::from_be_bytes(header[16..20].try_into().unwrap());
+    if offset == 0 {
+        return Ok(None);
+    }
+    let mut backing = vec![0u8; size as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut backing)?;
+    Ok(Some(String::from_utf8_lossy(&backing).into_owned()))
+}
+/// Return the disk size as seen by the guest.
+/// - qcow2: the size stored in the image header,
+/// - raw: the file length.
+pub fn get_virtual_size(path: &str) -> Result<u64, VirshleError> {
+    if is_qcow2(path)? {
+        // Header: magic(4) version(4) backing_file_offset(8)
+        // backing_file_size(4) cluster_bits(4) size(8)
+        let mut header = [0u8; 32];
+        let mut file = File::open(path)?;
+        file.read_exact(&mut header)?;
+        let size = u64::from_be_bytes(header[24..32].try_into().unwrap());
+        Ok(size)
+    } else {
+        Ok(fs::metadata(path)?.len())
+    }
+}
+/// Return the space the disk really takes on the host drive.
+pub fn get_allocated_size(path: &str) -> Result<u64, VirshleError> {
+    // st_blocks is always expressed in 512 bytes units.
+    Ok(fs::metadata(path)?.blocks() * 512)
+}
+
+/// Create a thin copy of a read-only backing image.
+/// Use a reflink (copy-on-write clone) when the filesystem supports it,
+/// and fallback to a qcow2 overlay.
+/// The backing image must be a versioned cache image,
+/// that is never modified while overlays refer to it.
+pub fn make_thin_copy(backing: &str, target: &str) -> Result<(), VirshleError> {
+    match reflink(backing, target) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            trace!("{}", e);
+            // Remove partial copy.
+            fs::remove_file(target).ok();
+            make_qcow2_overlay(backing, target)
+        }
+    }
+}
+/// Copy a disk that keeps being written to (ex: another vm disk).
+/// An overlay would require the source to stay untouched,
+/// so fallback to a sparse full copy when reflinks aren't supported.
+/// A qcow2 thin copy keeps refering to the same backing image.
+pub fn copy_disk(source: &str, target: &str) -> Result<(), VirshleError> {
+    let cmds = vec![format!(
+        "cp --reflink=auto --sparse=always {source} {target}"
+    )];
+    exec_cmds("disk", cmds)?;
+    Ok(())
+}
+/// Clone file extents, without copying data.
+/// Only supported on some filesystems (btrfs, xfs, bcachefs...).
+pub fn reflink(source: &str, target: &str) -> Result<(), VirshleError> {
+    let cmds = vec![format!("cp --reflink=always {source} {target}")];
+    exec_cmds("disk", cmds)?;
+    Ok(())
+}
+/// Create a qcow2 image that only stores changes made to the backing image.
+pub fn make_qcow2_overlay(backing: &str, target: &str) -> Result<(), VirshleError> {
+    let backing_format = match is_qcow2(backing)? {
+        true => "qcow2",
+        false => "raw",
+    };
+    let cmds = vec![format!(
+        "qemu-img create -q -f qcow2 -F {backing_format} -b {backing} {target}"
+    )];
     exec_cmds("disk", cmds)?;
     Ok(())
 }
+
+/// Grow a disk image to the given virtual size (in bytes).
+/// Raw images stay sparse, only qcow2 headers are rewritten.
+pub fn grow_image(path: &str, size: u64) -> Result<(), VirshleError> {
+    if is_qcow2(path)? {
+        let cmds = vec![format!("qemu-img resize -q {path} {size}")];
+        exec_cmds("disk", cmds)?;
+    } else {
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(size)?;
+    }
+    Ok(())
+}
+
+/// Create a sparse file of the given size (in bytes).
+/// No block is allocated on host until the guest writes to it.
+pub fn make_sparse_file(path: &str, size: u64) -> Result<(), VirshleError> {
+    let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.set_len(size)?;
+    Ok(())
+}
 /// Create a sparse file.
 /// The fastest method to create file.
 /// See: https://unix.stackexchange.com/questions/108858/seek-argument-in-command-dd
@@ -81,93 +208,295 @@ pub fn _make_empty_file(path: &str, block_size: &str, file_size: &str) -> Result
     }
     Ok(())
 }
-/// Create a vfat partition on empty file.
-pub fn format_to_vfat(path: &str) -> Result<(), VirshleError> {
-    let cmds = vec![format!("mkfs.vfat -F 32 -n INIT {path}")];
+/// Create a FAT32 image of the given size (in bytes), in userspace.
+/// An existing file is overwritten.
+/// The image stays sparse, only the filesystem metadata is written.
+pub fn make_fat_image(path: &str, size: u64, label: &str) -> Result<(), VirshleError> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.set_len(size)?;
+
+    // Labels are 11 bytes long, padded with spaces.
+    let mut volume_label = [b' '; 11];
+    for (i, byte) in label.to_uppercase().bytes().take(11).enumerate() {
+        volume_label[i] = byte;
+    }
+    let options = FormatVolumeOptions::new()
+        .fat_type(FatType::Fat32)
+        .volume_label(volume_label);
+    fatfs::format_volume(&mut file, options)?;
+    Ok(())
+}
+/// Write a file at the root of a FAT image, replacing any previous content.
+pub fn write_fat_file(image: &str, name: &str, content: &[u8]) -> Result<(), VirshleError> {
+    let file = OpenOptions::new().read(true).write(true).open(image)?;
+    let filesystem = FileSystem::new(file, FsOptions::new())?;
+    {
+        let mut file = filesystem.root_dir().create_file(name)?;
+        file.truncate()?;
+        file.write_all(content)?;
+        file.flush()?;
+    }
+    filesystem.unmount()?;
+    Ok(())
+}
+/// Read a file at the root of a FAT image.
+#[cfg(test)]
+pub fn read_fat_file(image: &str, name: &str) -> Result<String> {
+    let file = File::open(image).into_diagnostic()?;
+    let filesystem = FileSystem::new(file, FsOptions::new()).into_diagnostic()?;
+    let mut content = String::new();
+    filesystem
+        .root_dir()
+        .open_file(name)
+        .into_diagnostic()?
+        .read_to_string(&mut content)
+        .into_diagnostic()?;
+    Ok(content)
+}
+
+/// Attach a disk image to a free loop device and scan its partition table.
+/// Return the loop device path (ex: /dev/loop0).
+pub fn attach_loop(path: &str) -> Result<String, VirshleError> {
+    let cmd = format!("sudo losetup --find --show --partscan {path}");
+    let mut proc = Process::new();
+    let res = proc.stdin(&cmd).run()?;
+    if let (Some(Status::Succeeded), Some(stdout)) = (&res.state.status, &res.io.stdout) {
+        return Ok(stdout.trim().to_owned());
+    }
+    let message = format!("[disk]: couldn't attach {path:#?} to a loop device.");
+    let help = res.io.stderr.unwrap_or_default().trim().to_owned();
+    Err(LibError::builder().msg(&message).help(&help).build().into())
+}
+/// Detach a loop device.
+pub fn detach_loop(device: &str) -> Result<(), VirshleError> {
+    let cmds = vec![format!("sudo losetup --detach {device}")];
     exec_cmds("disk", cmds)?;
     Ok(())
 }
 
-/// Mount init disk to host filesystem.
-pub fn mount(source: &str, target: &str) -> Result<(), VirshleError> {
-    // Ensure mounting directory exists and nothing is already mounted.
-    umount(target).ok();
-    fs::create_dir_all(&target)?;
+/// Attach a qcow2 image to a free network block device.
+/// Return the nbd device path (ex: /dev/nbd0).
+pub fn attach_nbd(path: &str) -> Result<String, VirshleError> {
+    exec_cmds("disk", vec!["sudo modprobe nbd max_part=16".to_owned()])?;
 
-    let mut cmds = vec![];
-    // Mount need root priviledge
-    cmds.push(format!(
-        "sudo mount -t vfat -o loop -o gid=users -o umask=007 {source} {target}"
-    ));
+    // A device is in use when a qemu-nbd process holds it.
+    let device = (0..16)
+        .map(|i| format!("nbd{i}"))
+        .find(|e| !Path::new(&format!("/sys/block/{e}/pid")).exists());
+    let Some(device) = device else {
+        let message = format!("[disk]: couldn't attach {path:#?} to a nbd device.");
+        let help = "No free nbd device left.";
+        return Err(LibError::builder().msg(&message).help(help).build().into());
+    };
+    let device = format!("/dev/{device}");
+
+    let cmds = vec![
+        format!("sudo qemu-nbd --connect={device} --format=qcow2 {path}"),
+        // Wait for partitions to show up.
+        "sudo udevadm settle".to_owned(),
+    ];
+    exec_cmds("disk", cmds)?;
+    Ok(device)
+}
+/// Detach a network block device.
+pub fn detach_nbd(device: &str) -> Result<(), VirshleError> {
+    let cmds = vec![format!("sudo qemu-nbd --disconnect {device}")];
     exec_cmds("disk", cmds)?;
     Ok(())
 }
-/// Mount filesystem with ffi bindings.
-pub fn _mount(source: &str, target: &str) -> Result<(), VirshleError> {
-    // Safeguard
-    _umount(target).ok();
-
-    // Ensure mounting dir exists.
-    fs::create_dir_all(&target)?;
-
-    // Fetch a listed of supported file systems on this system. This will be used
-    // as the fstype to `Mount::new`, as the `Auto` mount parameter.
-    let supported = match SupportedFilesystems::new() {
-        Ok(supported) => supported,
-        Err(why) => {
-            error!("failed to mount filesystems: {}", why);
-            return Err(VirshleError::from(why));
-        }
+/// Check the qcow2 image metadata,
+/// and only fix errors if `repair` is set.
+/// Return the checker exit code and output.
+pub fn qemu_img_check(path: &str, repair: bool) -> Result<(i32, String), VirshleError> {
+    let checker = match repair {
+        false => "qemu-img check",
+        true => "qemu-img check -r all",
     };
+    run_with_exit_code(&format!("sudo {checker} {path}"))
+}
 
-    // The source block will be mounted to the target directory, and the fstype is likely
-    // one of the supported file systems.
-    let result = Mount::builder()
-        .fstype(FilesystemType::from(&supported))
-        .explicit_loopback()
-        .mount(source, target);
-
-    match result {
-        Ok(mount) => {
-            let message = format!("[disk]: mounted init disk.");
-            trace!("{}", &message);
-        }
-        Err(why) => {
-            let message = format!("[disk]: couldn't mount init disk.");
-            error!("{}:{}", &message, &why);
+#[derive(Debug, Deserialize)]
+struct LsblkOutput {
+    blockdevices: Vec<BlockDevice>,
+}
+#[derive(Debug, Deserialize)]
+struct BlockDevice {
+    name: String,
+    children: Option<Vec<BlockDevice>>,
+}
+/// Return the partitions of a block device,
+/// or the device itself if it has no partition table.
+pub fn get_partitions(device: &str) -> Result<Vec<String>, VirshleError> {
+    let cmd = format!("lsblk --json --paths --output NAME {device}");
+    let mut proc = Process::new();
+    let res = proc.stdin(&cmd).run()?;
+
+    let mut partitions = vec![];
+    if let Some(stdout) = res.io.stdout {
+        let output: LsblkOutput = serde_json::from_str(&stdout)?;
+        for e in output.blockdevices {
+            match e.children {
+                Some(children) => partitions.extend(children.into_iter().map(|e| e.name)),
+                None => partitions.push(e.name),
+            }
         }
+    }
+    Ok(partitions)
+}
+/// Return the filesystem type of a partition (ex: ext4, vfat).
+pub fn get_fstype(partition: &str) -> Result<Option<String>, VirshleError> {
+    // Probe the device directly, udev may not have caught up with fresh loop devices.
+    let cmd = format!("sudo blkid --probe --match-tag TYPE --output value {partition}");
+    let mut proc = Process::new();
+    let res = proc.stdin(&cmd).run()?;
+    let fstype = res
+        .io
+        .stdout
+        .map(|e| e.trim().to_owned())
+        .filter(|e| !e.is_empty());
+    Ok(fstype)
+}
+/// Run the filesystem checker matching the partition filesystem,
+/// and only fix errors if `repair` is set.
+/// Return the checker exit code and output,
+/// or None if there is no checker for this filesystem.
+pub fn fsck(
+    partition: &str,
+    fstype: &str,
+    repair: bool,
+) -> Result<Option<(i32, String)>, VirshleError> {
+    let checker = match (fstype, repair) {
+        ("ext2" | "ext3" | "ext4", false) => format!("fsck.{fstype} -f -n"),
+        ("ext2" | "ext3" | "ext4", true) => format!("fsck.{fstype} -f -y"),
+        ("vfat", false) => "fsck.vfat -n".to_owned(),
+        ("vfat", true) => "fsck.vfat -y".to_owned(),
+        ("xfs", false) => "xfs_repair -n".to_owned(),
+        ("xfs", true) => "xfs_repair".to_owned(),
+        _ => return Ok(None),
     };
-    Ok(())
+    let (code, output) = run_with_exit_code(&format!("sudo {checker} {partition}"))?;
+    Ok(Some((code, output)))
 }
+/// Run a checker and return its exit code and merged output.
+/// Checkers report through exit codes.
+fn run_with_exit_code(cmd: &str) -> Result<(i32, String), VirshleError> {
+    let cmd = format!("sh -c '{cmd} 2>&1; echo $?'");
+    let mut proc = Process::new();
+    let res = proc.stdin(&cmd).run()?;
 
-/// Unmount init disk from host filesystem.
-pub fn umount(path: &str) -> Result<(), VirshleError> {
-    let mut cmds = vec![];
+    let stdout = res.io.stdout.unwrap_or_default();
+    let stdout = stdout.trim_end();
+    let (output, code) = match stdout.rsplit_once('\n') {
+        Some((output, code)) => (output, code),
+        None => ("", stdout),
+    };
+    let code: i32 = code.trim().parse()?;
+    Ok((code, output.trim().to_owned()))
+}
 
-    // Umount need root priviledge
-    cmds.push(format!("sudo umount {path}"));
-    exec_cmds("disk", cmds)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
 
-    // Clean mount points
-    fs::remove_dir_all(&path)?;
+    #[test]
+    fn reject_ambiguous_sizes() -> Result<()> {
+        assert_eq!(reverse_human_bytes("50GiB")?, 50 * u64::pow(1024, 3));
+        assert_eq!(reverse_human_bytes("512B")?, 512);
+        assert!(reverse_human_bytes("50G").is_err());
+        assert!(reverse_human_bytes("512M").is_err());
+        Ok(())
+    }
+    #[test]
+    fn read_qcow2_virtual_size() -> Result<()> {
+        let path = std::env::temp_dir().join("virshle_test_header.qcow2");
+        let path = path.to_str().unwrap();
 
-    Ok(())
-}
+        // Minimal qcow2 header for a 20GiB disk.
+        let size: u64 = 20 * u64::pow(1024, 3);
+        let mut header = vec![];
+        header.extend_from_slice(&QCOW2_MAGIC);
+        header.extend_from_slice(&3u32.to_be_bytes());
+        header.extend_from_slice(&0u64.to_be_bytes());
+        header.extend_from_slice(&0u32.to_be_bytes());
+        header.extend_from_slice(&16u32.to_be_bytes());
+        header.extend_from_slice(&size.to_be_bytes());
+        fs::write(path, &header).into_diagnostic()?;
 
-/// Unmount filesystem with ffi bindings.
-///
-/// Not working yet for loopback devices, see:
-/// https://github.com/pop-os/sys-mount
-pub fn _umount(target: &str) -> Result<(), VirshleError> {
-    match unmount(target, UnmountFlags::empty()) {
-        Ok(_) => {
-            let message = format!("[disk]: unmounted init disk.");
-            trace!("{}", &message);
-            Ok(())
-        }
-        Err(why) => {
-            error!("failed to unmount filesystems: {}", why);
-            Err(VirshleError::from(why))
-        }
+        assert_eq!(is_qcow2(path)?, true);
+        assert_eq!(get_virtual_size(path)?, size);
+
+        fs::remove_file(path).into_diagnostic()?;
+        Ok(())
+    }
+    #[test]
+    fn read_qcow2_backing_file() -> Result<()> {
+        let path = std::env::temp_dir().join("virshle_test_backing.qcow2");
+        let path = path.to_str().unwrap();
+
+        // Minimal qcow2 header followed by the backing file name.
+        let backing = "/var/lib/virshle/cache/nixos.efi.img-e3b0c442";
+        let mut header = vec![];
+        header.extend_from_slice(&QCOW2_MAGIC);
+        header.extend_from_slice(&3u32.to_be_bytes());
+        header.extend_from_slice(&32u64.to_be_bytes());
+        header.extend_from_slice(&(backing.len() as u32).to_be_bytes());
+        header.extend_from_slice(&16u32.to_be_bytes());
+        header.extend_from_slice(&u64::pow(1024, 3).to_be_bytes());
+        header.extend_from_slice(backing.as_bytes());
+        fs::write(path, &header).into_diagnostic()?;
+        assert_eq!(get_backing_file(path)?, Some(backing.to_owned()));
+
+        fs::write(path, vec![0u8; 4096]).into_diagnostic()?;
+        assert_eq!(get_backing_file(path)?, None);
+
+        fs::remove_file(path).into_diagnostic()?;
+        Ok(())
+    }
+    #[test]
+    fn read_raw_virtual_size() -> Result<()> {
+        let path = std::env::temp_dir().join("virshle_test_raw.img");
+        let path = path.to_str().unwrap();
+        fs::write(path, vec![0u8; 4096]).into_diagnostic()?;
+
+        assert_eq!(is_qcow2(path)?, false);
+        assert_eq!(get_virtual_size(path)?, 4096);
+
+        fs::remove_file(path).into_diagnostic()?;
+        Ok(())
+    }
+
+    #[test]
+    fn make_fat32_image() -> Result<()> {
+        let path = std::env::temp_dir().join("virshle_test_fat32.img");
+        let path = path.to_str().unwrap();
+        make_fat_image(path, 64 * u64::pow(1024, 2), "init")?;
+
+        let file = File::open(path).into_diagnostic()?;
+        let filesystem = FileSystem::new(file, FsOptions::new()).into_diagnostic()?;
+        assert_eq!(filesystem.fat_type(), FatType::Fat32);
+        assert_eq!(filesystem.volume_label(), "INIT");
+        drop(filesystem);
+
+        fs::remove_file(path).into_diagnostic()?;
+        Ok(())
+    }
+    #[test]
+    fn overwrite_fat_file() -> Result<()> {
+        let path = std::env::temp_dir().join("virshle_test_fat_file.img");
+        let path = path.to_str().unwrap();
+        make_fat_image(path, 64 * u64::pow(1024, 2), "init")?;
+
+        write_fat_file(path, "pipelight.toml", b"a longer first content")?;
+        write_fat_file(path, "pipelight.toml", b"shorter")?;
+        assert_eq!(read_fat_file(path, "pipelight.toml")?, "shorter");
+
+        fs::remove_file(path).into_diagnostic()?;
+        Ok(())
     }
 }
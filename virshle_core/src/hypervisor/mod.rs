@@ -7,10 +7,10 @@ pub mod disk;
 mod rand;
 pub mod vm;
 
-pub use disk::{Disk, DiskInfo, InitDisk};
+pub use disk::{Disk, DiskInfo, FsckStatus, InitDisk, PartitionReport};
 
 pub mod vmm;
 
-pub use vm::{InitData, UserData};
-pub use vm::{Vm, VmExtra, VmData, VmInfo, VmTable};
+pub use vm::{InitBackend, InitData, UserData};
+pub use vm::{ShutdownMode, Snapshot, Vm, VmData, VmExtra, VmInfo, VmTable};
 pub use vmm::{VmInfoResponse, VmState};
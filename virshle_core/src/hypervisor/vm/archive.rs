@@ -0,0 +1,380 @@
+use super::{Disk, Snapshot, Vm};
+use crate::cache::Image;
+use crate::config::init::MANAGED_DIR;
+use crate::config::{Account, Config, UserData, VmNet};
+use crate::hypervisor::disk::utils as disk_utils;
+use crate::hypervisor::rand::random_name;
+use crate::VmState;
+
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Filesystem
+use std::fs;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio::process::Command;
+use tokio_util::io::ReaderStream;
+
+// Error Handling
+use miette::Result;
+use tracing::{error, info, warn};
+use virshle_error::{LibError, VirshleError};
+
+/*
+* Portable vm archives, for backups and offline moves between nodes.
+*
+* A zstd compressed tarball laid out like the vm directory:
+* - vm.json: the vm definition and the account it is linked to,
+* - disk/: the vm disks (but the init disk, rebuilt on start),
+* - disk.key: the encrypted disk key, if any,
+* - snapshots/{tag}: an optional snapshot.
+*
+* Thin copies keep refering to their backing image,
+* which must be in the node cache on import.
+*/
+
+/// Archive entry holding the vm definition.
+pub const ARCHIVE_MANIFEST: &str = "vm.json";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VmArchive {
+    pub vm: Vm,
+    pub account_uuid: Option<Uuid>,
+    /// Tag of the bundled snapshot, if any.
+    pub snapshot: Option<String>,
+}
+
+impl Vm {
+    /// Return the directory the vm archive is assembled in before export.
+    /// Usually at : `/var/lib/virshle/vm/{vm_uuid}/export`.
+    fn get_export_dir(&self) -> Result<String, VirshleError> {
+        let path = format!("{}/export", self.get_dir()?);
+        Ok(path)
+    }
+
+    /// Bundle a stopped vm into a tar.zst archive, returned as a byte stream.
+    /// Disks are not copied beforehand, they are read as the stream is consumed.
+    #[tracing::instrument(skip_all)]
+    pub async fn export(
+        &self,
+        snapshot: Option<String>,
+    ) -> Result<impl Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static, VirshleError>
+    {
+        let state = self.vmm().api()?.state().await?;
+        if matches!(state, VmState::Running | VmState::Paused) {
+            let message = format!("Couldn't export vm {:#?}.", self.name);
+            let help = format!("Vm must be stopped, but is {:?}.", state);
+            return Err(LibError::builder().msg(&message).help(&help).build().into());
+        }
+
+        // Assemble the archive tree with links to the vm files.
+        let directory = self.get_export_dir()?;
+        if Path::new(&directory).exists() {
+            fs::remove_dir_all(&directory)?;
+        }
+        fs::create_dir_all(format!("{directory}/disk"))?;
+        if let Err(e) = self._export(&directory, snapshot).await {
+            fs::remove_dir_all(&directory).ok();
+            return Err(e);
+        }
+
+        let mut child = Command::new("tar")
+            .args([
+                "--zstd",
+                "--dereference",
+                "--sparse",
+                "-cf",
+                "-",
+                "-C",
+                &directory,
+                ".",
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().unwrap();
+
+        // Fail the stream if tar didn't exit cleanly,
+        // so the client doesn't keep a truncated archive.
+        let name = self.name.clone();
+        let trailer = stream::once(async move {
+            let output = child.wait_with_output().await;
+            fs::remove_dir_all(&directory).ok();
+            match output {
+                Ok(output) if output.status.success() => {
+                    info!("exported vm {}", name);
+                    Ok(Bytes::new())
+                }
+                Ok(output) => {
+                    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+                    error!("couldn't export vm {}: {}", name, stderr);
+                    Err(std::io::Error::other(stderr))
+                }
+                Err(e) => Err(e),
+            }
+        });
+        Ok(ReaderStream::new(stdout).chain(trailer))
+    }
+    async fn _export(&self, directory: &str, snapshot: Option<String>) -> Result<(), VirshleError> {
+        for disk in self.disk.iter().filter(|e| e.name != "init") {
+            let path = Path::new(&disk.path);
+            if !path.exists() {
+                let message = format!("Couldn't export vm {:#?}.", self.name);
+                let help = format!("Disk {:#?} is missing.", disk.path);
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+            let filename = path.file_name().unwrap().to_str().unwrap();
+            symlink(&disk.path, format!("{directory}/disk/{filename}"))?;
+        }
+        let key = self.get_disk_key()?;
+        if Path::new(&key).exists() {
+            symlink(&key, format!("{directory}/disk.key"))?;
+        }
+        if let Some(tag) = &snapshot {
+            let snapshot = self.snapshots().get(tag).await?;
+            fs::create_dir_all(format!("{directory}/snapshots"))?;
+            symlink(&snapshot.path, format!("{directory}/snapshots/{tag}"))?;
+        }
+
+        let archive = VmArchive {
+            vm: self.to_owned(),
+            account_uuid: self.get_account_uuid().await.ok(),
+            snapshot,
+        };
+        fs::write(
+            format!("{directory}/{ARCHIVE_MANIFEST}"),
+            serde_json::to_string_pretty(&archive)?,
+        )?;
+        Ok(())
+    }
+
+    /// Recreate a vm from a tar.zst archive stream.
+    ///
+    /// The vm gets a new uuid (and a new name unless one is given),
+    /// except if `keep_uuid` is set, in which case it must not already live on the node.
+    /// The bundled snapshot and encrypted disks are only kept along with the uuid.
+    #[tracing::instrument(skip_all)]
+    pub async fn import<R>(
+        reader: &mut R,
+        keep_uuid: bool,
+        name: Option<String>,
+    ) -> Result<Vm, VirshleError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        // Unpack on the same filesystem as the vm directories,
+        // so the tree can be moved in place.
+        let directory = format!("{MANAGED_DIR}/vm/.import-{}", Uuid::new_v4());
+        fs::create_dir_all(&directory)?;
+        let res = Self::_import(reader, &directory, keep_uuid, name).await;
+        if Path::new(&directory).exists() {
+            fs::remove_dir_all(&directory).ok();
+        }
+        let vm = res?;
+
+        info!("imported vm {}", vm.name);
+        Ok(vm)
+    }
+    async fn _import<R>(
+        reader: &mut R,
+        directory: &str,
+        keep_uuid: bool,
+        name: Option<String>,
+    ) -> Result<Vm, VirshleError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut child = Command::new("tar")
+            // Safeguard: files must belong to the node, not to the archive author.
+            .args([
+                "--zstd",
+                "--sparse",
+                "--no-same-owner",
+                "--no-same-permissions",
+                "-xf",
+                "-",
+                "-C",
+                directory,
+            ])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let copied = tokio::io::copy(reader, &mut stdin).await;
+        stdin.shutdown().await.ok();
+        drop(stdin);
+        let output = child.wait_with_output().await?;
+        copied?;
+        if !output.status.success() {
+            let message = "Couldn't unpack vm archive.";
+            let help = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            return Err(LibError::builder().msg(message).help(&help).build().into());
+        }
+
+        // Safeguard: archives are untrusted,
+        // links could expose host files to the guest.
+        Self::check_archive_tree(Path::new(directory))?;
+
+        let manifest = format!("{directory}/{ARCHIVE_MANIFEST}");
+        if !Path::new(&manifest).exists() {
+            let message = "Couldn't import vm.";
+            let help = format!("Archive has no {ARCHIVE_MANIFEST}, is it a vm archive?");
+            return Err(LibError::builder().msg(message).help(&help).build().into());
+        }
+        let archive: VmArchive = serde_json::from_str(&fs::read_to_string(&manifest)?)?;
+        fs::remove_file(&manifest)?;
+        if let Some(tag) = &archive.snapshot {
+            Snapshot::check_tag(tag)?;
+        }
+
+        let mut vm = archive.vm;
+        vm.id = None;
+        if !keep_uuid {
+            if vm.crypt().has_encrypted_disks() {
+                let message = format!("Couldn't import vm {:#?} with a new uuid.", vm.name);
+                let help = "Encrypted disks keys are bound to the vm uuid, use --keep-uuid.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+            vm.uuid = Uuid::new_v4();
+            vm.name = random_name()?;
+            // Static mac addresses would collide with the source vm ones.
+            vm.net = vm
+                .net
+                .map(|e| e.into_iter().map(VmNet::without_mac).collect());
+            if let Some(tag) = &archive.snapshot {
+                warn!("dropped snapshot {:#?}, it is bound to the vm uuid", tag);
+                fs::remove_dir_all(format!("{directory}/snapshots")).ok();
+            }
+        }
+        if let Some(name) = name {
+            vm.name = name;
+        }
+
+        // Safeguard: the source node may declare other switches and groups.
+        let config = Config::get()?;
+        for net in vm.net.iter().flatten() {
+            config.network.get_switch(net.switch.as_deref())?;
+        }
+        if let Some(groups) = &vm.security_groups {
+            config.firewall.get_groups(groups)?;
+        }
+
+        // Safeguard: vm must not already live on this node.
+        if Vm::database()
+            .await?
+            .one()
+            .uuid(vm.uuid)
+            .get()
+            .await
+            .is_ok()
+        {
+            let message = format!("Vm {:#?} already exists on this node.", vm.uuid);
+            let help = "Delete it first, or import it with a new uuid.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        if Vm::database()
+            .await?
+            .one()
+            .name(&vm.name)
+            .get()
+            .await
+            .is_ok()
+        {
+            let message = format!("A vm named {:#?} already exists on this node.", vm.name);
+            let help = "Import it under another name with --name.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        // Point disks to their new location, the init disk is rebuilt on start.
+        let disks_dir = vm.get_disks_dir()?;
+        let mut disks = vec![];
+        for disk in vm.disk.into_iter().filter(|e| e.name != "init") {
+            let filename = match Path::new(&disk.path).file_name().and_then(|e| e.to_str()) {
+                Some(v) => v.to_owned(),
+                None => {
+                    let message = format!("Couldn't import disk {:#?}.", disk.name);
+                    let help = format!("Invalid disk path {:#?} in archive.", disk.path);
+                    return Err(LibError::builder().msg(&message).help(&help).build().into());
+                }
+            };
+            let unpacked = format!("{directory}/disk/{filename}");
+            if !Path::new(&unpacked).is_file() {
+                let message = format!("Couldn't import disk {:#?}.", disk.name);
+                let help = format!("Archive has no disk/{filename}.");
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+            // Safeguard: thin copies need their backing image,
+            // and may only read from it.
+            let backing = disk_utils::get_backing_file(&unpacked)?;
+            let image = match &disk.image {
+                Some(image) => match Image::get(image).await {
+                    Ok(v) => Some(v),
+                    Err(_) => {
+                        let message = format!("Couldn't import disk {:#?}.", disk.name);
+                        let help = format!(
+                            "Backing image {:#?} is not in cache, pull it first with `v image pull`.",
+                            image
+                        );
+                        return Err(LibError::builder().msg(&message).help(&help).build().into());
+                    }
+                },
+                None => None,
+            };
+            if let Some(backing) = backing {
+                if image.map(|e| e.path) != Some(backing.clone()) {
+                    let message = format!("Couldn't import disk {:#?}.", disk.name);
+                    let help = format!("Disk is backed by {:#?}, not by a cached image.", backing);
+                    return Err(LibError::builder().msg(&message).help(&help).build().into());
+                }
+            }
+            disks.push(Disk {
+                path: format!("{disks_dir}/{filename}"),
+                ..disk
+            });
+        }
+        vm.disk = disks;
+
+        // Move the unpacked tree in place.
+        let vm_dir = vm.get_dir()?;
+        fs::create_dir_all(Path::new(&vm_dir).parent().unwrap())?;
+        fs::rename(directory, &vm_dir)?;
+        fs::create_dir_all(&disks_dir)?;
+        fs::create_dir_all(format!("{vm_dir}/net"))?;
+
+        // Persist vm config into database.
+        let user_data = archive.account_uuid.map(|uuid| UserData {
+            account: Some(Account { id: None, uuid }),
+            ..Default::default()
+        });
+        if let Err(e) = vm.db().await?.create(user_data).await {
+            fs::remove_dir_all(&vm_dir).ok();
+            return Err(e);
+        }
+        if keep_uuid {
+            if let Some(tag) = &archive.snapshot {
+                vm.snapshots().create(tag).await?;
+            }
+        }
+        Ok(vm)
+    }
+    /// Error out if the unpacked archive holds anything else
+    /// than directories and regular files (symlinks, hardlinks, devices...).
+    fn check_archive_tree(path: &Path) -> Result<(), VirshleError> {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?.path();
+            let metadata = fs::symlink_metadata(&entry)?;
+            if metadata.is_dir() {
+                Self::check_archive_tree(&entry)?;
+            } else if !metadata.is_file() || metadata.nlink() > 1 {
+                let message = "Couldn't import vm.";
+                let help = format!("Archive entry {:#?} is not a regular file.", entry);
+                return Err(LibError::builder().msg(message).help(&help).build().into());
+            }
+        }
+        Ok(())
+    }
+}
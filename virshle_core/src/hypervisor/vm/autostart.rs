@@ -0,0 +1,68 @@
+use super::Vm;
+use crate::VmState;
+
+use futures::stream::{self, StreamExt};
+use indexmap::IndexMap;
+use pipelight_exec::Status;
+
+// Error Handling
+use miette::Result;
+use tracing::{error, info};
+use virshle_error::VirshleError;
+
+/// Max number of vms booted concurrently on node start.
+pub const AUTOSTART_PARALLELISM: usize = 4;
+
+impl Vm {
+    /// Whether the vm must be booted back when the node starts.
+    pub fn is_autostart(&self) -> bool {
+        self.extra.as_ref().map(|e| e.autostart).unwrap_or(false)
+    }
+
+    /// Boot back every autostart vm that was running
+    /// before the node went down.
+    ///
+    /// Vms are started a few at a time,
+    /// and a failing vm doesn't prevent the others from starting.
+    #[tracing::instrument(skip_all)]
+    pub async fn autostart_all() -> Result<IndexMap<Status, Vec<Vm>>, VirshleError> {
+        let vms = Vm::database().await?.autostart().await?;
+        info!("[autostart] {} vm(s) to start", vms.len());
+
+        let results: Vec<(Vm, Result<Vm, VirshleError>)> = stream::iter(vms)
+            .map(|mut vm| async move {
+                let res = vm._autostart().await;
+                (vm, res)
+            })
+            .buffer_unordered(AUTOSTART_PARALLELISM)
+            .collect()
+            .await;
+
+        let mut res: IndexMap<Status, Vec<Vm>> =
+            IndexMap::from([(Status::Succeeded, vec![]), (Status::Failed, vec![])]);
+        for (vm, result) in results {
+            match result {
+                Ok(_) => {
+                    info!("[autostart] started vm {:#?}", vm.name);
+                    res.get_mut(&Status::Succeeded).unwrap().push(vm);
+                }
+                Err(e) => {
+                    error!("[autostart] couldn't start vm {:#?}: {}", vm.name, e);
+                    res.get_mut(&Status::Failed).unwrap().push(vm);
+                }
+            }
+        }
+        Ok(res)
+    }
+    async fn _autostart(&mut self) -> Result<Vm, VirshleError> {
+        // Vms survive a mere daemon restart.
+        match self.vmm().api()?.state().await? {
+            VmState::Running | VmState::Paused => Ok(self.to_owned()),
+            _ => {
+                // Provision the init disk as on a manual start.
+                let user_data = self.user_data.clone();
+                self.start().maybe_user_data(user_data).exec().await
+            }
+        }
+    }
+}
@@ -1,20 +1,52 @@
-use super::{Disk, Vm};
+use super::{Disk, Snapshot, Vm};
+use crate::hypervisor::disk::PartitionReport;
+use crate::cache::Image;
+use crate::hypervisor::disk::crypt::LUKS_HEADER_SIZE;
+use crate::hypervisor::disk::utils as disk_utils;
+use crate::hypervisor::vmm::{DiskConfig, RateLimiterConfig, TokenBucketConfig};
+use crate::network::utils as net_utils;
+use crate::peer::NodeInfo;
 // Init disk
 use super::UserData;
+use crate::config::{Account, Bandwidth, Config, VmNet};
 
 use crate::VmState;
-// Globals
-use crate::config::init::MANAGED_DIR;
 
 // Filesystem
 use bon::bon;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
 use std::path::Path;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
 
 // Error Handling
 use miette::Result;
-use tracing::{error, info, trace};
-use virshle_error::VirshleError;
+use tracing::{error, info, trace, warn};
+use virshle_error::{LibError, VirshleError};
+
+/// Default time given to a guest to power itself off (in seconds).
+pub const SHUTDOWN_TIMEOUT: u64 = 60;
+
+/// How a vm was brought down.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownMode {
+    /// The guest powered itself off.
+    Graceful,
+    /// The vm was stopped without the guest consent.
+    Forced,
+}
+impl fmt::Display for ShutdownMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let string = match self {
+            ShutdownMode::Graceful => "graceful",
+            ShutdownMode::Forced => "forced",
+        };
+        write!(f, "{}", string)
+    }
+}
 
 #[bon]
 impl Vm {
@@ -23,6 +55,7 @@ impl Vm {
     #[tracing::instrument(skip_all)]
     pub async fn create(&mut self, user_data: Option<UserData>) -> Result<Self, VirshleError> {
         // Persist vm config into database
+        self.user_data = user_data.clone();
         self.db().await?.create(user_data.clone()).await?;
 
         // Create initial resources
@@ -35,6 +68,127 @@ impl Vm {
         Ok(self.to_owned())
     }
 
+    /// Create a new vm from a copy of this one.
+    ///
+    /// The clone gets its own uuid, name, mac address and vsock cid,
+    /// copies of the source disks, and a fresh init disk provisioned with
+    /// the source user data (unless some is given).
+    /// It is linked to the same account as the source.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    #[tracing::instrument(skip_all)]
+    pub async fn clone_as(
+        &self,
+        name: Option<String>,
+        /// Clone uuid, random if none.
+        uuid: Option<Uuid>,
+        user_data: Option<UserData>,
+    ) -> Result<Vm, VirshleError> {
+        // Safeguard: disks must not be written to while copied.
+        let state = self.vmm().api()?.state().await?;
+        if state == VmState::Running {
+            let message = format!("Couldn't clone vm {:#?}.", self.name);
+            let help = "Vm must be paused or stopped first.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        if let Some(name) = &name {
+            if Vm::database().await?.one().name(name).get().await.is_ok() {
+                let message = format!("Couldn't clone vm {:#?}.", self.name);
+                let help = format!("A vm named {:#?} already exists on this node.", name);
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        }
+        NodeInfo::get().await?.can_clone_vm(self).await?;
+
+        let mut vm = self._clone_config();
+        if let Some(name) = name {
+            vm.name = name;
+        }
+        if let Some(uuid) = uuid {
+            vm.uuid = uuid;
+        }
+        for directory in [
+            vm.get_dir()?,
+            vm.get_disks_dir()?,
+            format!("{}/net", vm.get_dir()?),
+        ] {
+            fs::create_dir_all(&directory)?;
+        }
+        if let Err(e) = self._clone_disks(&mut vm) {
+            vm.crypt().wipe_key().ok();
+            vm.delete_filetree().ok();
+            return Err(e);
+        }
+
+        // Link the clone to the source account.
+        let mut user_data = user_data.or_else(|| self.user_data.clone());
+        if let Ok(uuid) = self.get_account_uuid().await {
+            user_data.get_or_insert_with(UserData::default).account =
+                Some(Account { id: None, uuid });
+        }
+        if let Err(e) = vm.create(user_data).await {
+            vm.delete().await.ok();
+            return Err(e);
+        }
+
+        info!("cloned vm {:#?} into {:#?}", self.name, vm.name);
+        Ok(vm)
+    }
+    /// Return a copy of the vm definition, with a fresh identity
+    /// (name, uuid and mac addresses) and no disks.
+    fn _clone_config(&self) -> Vm {
+        Vm {
+            vcpu: self.vcpu,
+            vram: self.vram.clone(),
+            max_vcpu: self.max_vcpu,
+            max_vram: self.max_vram.clone(),
+            // Static mac addresses would collide with the source vm ones.
+            net: self
+                .net
+                .clone()
+                .map(|e| e.into_iter().map(VmNet::without_mac).collect()),
+            security_groups: self.security_groups.clone(),
+            extra: self.extra.clone(),
+            user_data: self.user_data.clone(),
+            init: self.init,
+            ..Default::default()
+        }
+    }
+    /// Copy the source disks into the clone storage directory.
+    /// The init disk is left out, it is rebuilt on creation.
+    fn _clone_disks(&self, vm: &mut Vm) -> Result<(), VirshleError> {
+        for disk in self.disk.iter().filter(|e| e.name != "init") {
+            let filename = Path::new(&disk.path).file_name().unwrap().to_str().unwrap();
+            let copy = Disk {
+                path: format!("{}/{}", vm.get_disks_dir()?, filename),
+                ..disk.to_owned()
+            };
+            match disk.encrypted {
+                Some(true) => {
+                    // Reencrypt with the clone own key.
+                    let size = disk.get_size()?;
+                    let device = self.crypt().get_device_path(disk);
+                    let was_open = Path::new(&device).exists();
+                    self.crypt().open(disk)?;
+                    let res = vm
+                        .crypt()
+                        .create_key()
+                        .and_then(|_| vm.crypt().format(&copy, &device, size));
+                    if !was_open {
+                        self.crypt().close(disk)?;
+                    }
+                    res?;
+                }
+                _ => disk_utils::copy_disk(&disk.path, &copy.path)?,
+            };
+            vm.disk.push(copy);
+        }
+        Ok(())
+    }
+
     /// Start Vm
     #[builder(finish_fn = exec)]
     #[tracing::instrument(skip_all)]
@@ -43,7 +197,7 @@ impl Vm {
         match fresh {
             Some(true) => {
                 // Replace disk.
-                self.replace_disk().name("os").exec()?;
+                self.replace_disk().name("os").exec().await?;
             },
             _ => {}
         };
@@ -55,6 +209,9 @@ impl Vm {
             .net(true)
             .exec().await?;
 
+        // Unlock encrypted disks
+        self.crypt().open_all()?;
+
         // Start the ch process
         self.vmm().start().exec().await?;
 
@@ -64,6 +221,7 @@ impl Vm {
         self.vmm().api()?.boot().await?;
 
         self.set_vsock_permissions().await?;
+        self.db().await?.set_running(true).await?;
 
         info!("started vm {:#?}", self.name);
         Ok(self.to_owned())
@@ -74,12 +232,18 @@ impl Vm {
     pub async fn delete(&mut self) -> Result<Self, VirshleError> {
         // Remove process and artifacts.
         self.vmm().kill_process()?;
+        // Lock encrypted disks
+        self.crypt().close_all()?;
         // Remove vm networks
         self.networks().delete_all()?;
         // Soft lease deletion
         self.networks().leases().delete_all().await.ok();
         // Remove vm disks
         self.delete_disks()?;
+        // Remove snapshot records
+        self.snapshots().delete_all().await.ok();
+        // Encrypted disks and snapshots are unreadable without the key
+        self.crypt().wipe_key()?;
         // Delete vm directory tree
         self.delete_filetree()?;
         // Finally Remove db record
@@ -90,19 +254,475 @@ impl Vm {
     }
 
     /// Shut the virtual machine down and removes artifacts.
-    /// Should silently fail when vm is already down.
+    ///
+    /// The guest is asked to power itself off (ACPI power button)
+    /// and given `timeout` to do so (defaults to 60s).
+    /// If it doesn't, the vm is forced down only when `force` is set.
+    ///
+    /// Should silently succeed when vm is already down.
+    #[builder(finish_fn = exec)]
     #[tracing::instrument(skip_all)]
-    pub async fn shutdown(&self) -> Result<Self, VirshleError> {
-        self.vmm().api()?.shutdown().await?;
+    pub async fn shutdown(
+        &self,
+        timeout: Option<Duration>,
+        force: Option<bool>,
+    ) -> Result<ShutdownMode, VirshleError> {
+        let timeout = timeout.unwrap_or(Duration::from_secs(SHUTDOWN_TIMEOUT));
+        let mode = match self.vmm().api()?.state().await? {
+            VmState::Running => {
+                self.vmm().api()?.power_button().await?;
+                if self.wait_for_poweroff(timeout).await? {
+                    ShutdownMode::Graceful
+                } else if force == Some(true) {
+                    self.vmm().api()?.shutdown().await?;
+                    ShutdownMode::Forced
+                } else {
+                    let message = format!(
+                        "Vm {:#?} didn't power off within {}s.",
+                        self.name,
+                        timeout.as_secs()
+                    );
+                    let help = "Wait longer with --timeout, or force it down with --force.";
+                    return Err(LibError::builder().msg(&message).help(help).build().into());
+                }
+            }
+            // A paused guest can't handle the power button.
+            VmState::Paused | VmState::BreakPoint => {
+                self.vmm().api()?.shutdown().await?;
+                ShutdownMode::Forced
+            }
+            // Nothing left to shut down, only clean artifacts.
+            _ => ShutdownMode::Forced,
+        };
         // Remove ch process
         self.vmm().kill_process()?;
+        // Lock encrypted disks
+        self.crypt().close_all()?;
         // Remove network ports
         self.networks().delete_all()?;
+        // Do not autostart a vm that was deliberately stopped.
+        self.to_owned().db().await?.set_running(false).await?;
+
+        info!("stopped vm {} ({})", self.name, mode);
+        Ok(mode)
+    }
+    /// Poll the vm state until the guest has powered off,
+    /// return false if it is still up after timeout.
+    async fn wait_for_poweroff(&self, timeout: Duration) -> Result<bool, VirshleError> {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            match self.vmm().api()?.state().await? {
+                // The ch process may exit on guest poweroff.
+                VmState::Shutdown | VmState::NotCreated => return Ok(true),
+                _ => tokio::time::sleep(Duration::from_millis(500)).await,
+            };
+        }
+        Ok(false)
+    }
+
+    /// Update vm settings and persist them into database.
+    #[builder(finish_fn = exec)]
+    #[tracing::instrument(skip_all)]
+    pub async fn update(
+        &mut self,
+        autostart: Option<bool>,
+        security_groups: Option<Vec<String>>,
+    ) -> Result<Self, VirshleError> {
+        if let Some(autostart) = autostart {
+            let mut extra = self.extra.clone().unwrap_or_default();
+            extra.autostart = autostart;
+            self.extra = Some(extra);
+        }
+        if let Some(security_groups) = &security_groups {
+            let security_groups: Vec<String> = security_groups
+                .iter()
+                .filter(|e| !e.is_empty())
+                .cloned()
+                .collect();
+            // Safeguard: security groups must be declared.
+            Config::get()?.firewall.get_groups(&security_groups)?;
+            self.security_groups = match security_groups.is_empty() {
+                true => None,
+                false => Some(security_groups),
+            };
+        }
+        self.db().await?.update().await?;
 
-        info!("stopped vm {}", self.name);
+        // Apply security groups live.
+        if security_groups.is_some() {
+            if let Ok(VmState::Running | VmState::Paused) = self.vmm().api()?.state().await {
+                self.networks().firewall().ensure_all().await?;
+            }
+        }
+
+        info!("updated vm {:#?}", self.name);
         Ok(self.to_owned())
     }
 
+    /// Change a virtual machine network interface rate limits,
+    /// and persist them into database.
+    /// The only vm network is updated if no name is given.
+    ///
+    /// Rates (per second, ex: 100Mbit) and burst are removed when set to 0.
+    /// Limits are set on the interface ovs port,
+    /// so a running vm is updated live.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    #[tracing::instrument(skip_all)]
+    pub async fn update_net(
+        &mut self,
+        name: Option<String>,
+        ingress: Option<String>,
+        egress: Option<String>,
+        burst: Option<String>,
+    ) -> Result<VmNet, VirshleError> {
+        let nets = self.net.as_deref_mut().unwrap_or_default();
+        let index = match &name {
+            Some(name) => nets.iter().position(|e| &e.name == name),
+            None if nets.len() == 1 => Some(0),
+            None => None,
+        };
+        let Some(index) = index else {
+            let message = format!("Couldn't update network of vm {:#?}.", self.name);
+            let help = match name {
+                Some(name) => format!("No network named {:#?}.", name),
+                None => "Vm has several networks, give the one to update.".to_owned(),
+            };
+            return Err(LibError::builder().msg(&message).help(&help).build().into());
+        };
+
+        let net = &mut nets[index];
+        let mut bandwidth = net.bandwidth.clone().unwrap_or_default();
+        // A null value removes the limit.
+        let or_none = |value: String| -> Result<Option<String>, VirshleError> {
+            match net_utils::reverse_human_bits(&value)? {
+                0 => Ok(None),
+                _ => Ok(Some(value)),
+            }
+        };
+        if let Some(ingress) = ingress {
+            bandwidth.ingress = or_none(ingress)?;
+        }
+        if let Some(egress) = egress {
+            bandwidth.egress = or_none(egress)?;
+        }
+        if let Some(burst) = burst {
+            bandwidth.burst = or_none(burst)?;
+        }
+        net.bandwidth = match bandwidth {
+            Bandwidth {
+                ingress: None,
+                egress: None,
+                burst: None,
+            } => None,
+            bandwidth => Some(bandwidth),
+        };
+        net.validate()?;
+        let net = net.to_owned();
+        self.db().await?.update().await?;
+
+        // Apply rate limits live.
+        if let Ok(VmState::Running | VmState::Paused) = self.vmm().api()?.state().await {
+            self.networks().update_port(&net)?;
+        }
+        info!("updated network {:#?} of vm {:#?}", net.name, self.name);
+        Ok(net)
+    }
+
+    /// Grow or shrink the virtual machine vcpu and ram,
+    /// and persist the new sizes into database.
+    ///
+    /// A running vm is resized live, within its hotplug headroom (max_vcpu/max_vram).
+    /// A stopped vm gets its new sizes on next start.
+    #[builder(finish_fn = exec)]
+    #[tracing::instrument(skip_all)]
+    pub async fn resize(
+        &mut self,
+        vcpu: Option<u64>,
+        vram: Option<String>,
+    ) -> Result<Self, VirshleError> {
+        let vram_bytes = match &vram {
+            Some(vram) => Some(disk_utils::reverse_human_bytes(vram)?),
+            None => None,
+        };
+        if vcpu == Some(0) || vram_bytes == Some(0) {
+            let message = format!("Couldn't resize vm {:#?}.", self.name);
+            let help = "Vcpu and vram can't be null.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+
+        let live = matches!(
+            self.vmm().api()?.state().await?,
+            VmState::Running | VmState::Paused
+        );
+        // Hotplug bounds are set on boot.
+        if live {
+            if let Some(vcpu) = vcpu {
+                let max_vcpu = self.get_max_vcpu();
+                if vcpu > max_vcpu {
+                    let message = format!("Couldn't resize vm {:#?}.", self.name);
+                    let help = format!("Vcpu can't exceed {} (max_vcpu).", max_vcpu);
+                    return Err(LibError::builder().msg(&message).help(&help).build().into());
+                }
+            }
+            if let Some(vram) = vram_bytes {
+                let max_vram = self.get_max_vram()?;
+                if vram > max_vram {
+                    let message = format!("Couldn't resize vm {:#?}.", self.name);
+                    let help = format!(
+                        "Vram can't exceed {} (max_vram).",
+                        disk_utils::human_bytes(&max_vram)?
+                    );
+                    return Err(LibError::builder().msg(&message).help(&help).build().into());
+                }
+            }
+        }
+
+        // Node saturation
+        NodeInfo::get()
+            .await?
+            .can_resize_vm(self, vcpu, vram_bytes)
+            .await?;
+
+        if live {
+            self.vmm().api()?.resize(vcpu, vram_bytes).await?;
+
+            // Pin the headroom the vm was booted with,
+            // as defaults are relative to current sizes.
+            if self.max_vcpu.is_none() {
+                self.max_vcpu = Some(self.get_max_vcpu());
+            }
+            if self.max_vram.is_none() {
+                self.max_vram = Some(format!("{}MiB", self.get_max_vram()? / u64::pow(1024, 2)));
+            }
+        }
+        if let Some(vcpu) = vcpu {
+            self.vcpu = vcpu;
+        }
+        if let Some(vram) = vram {
+            self.vram = vram;
+        }
+        self.db().await?.update().await?;
+
+        info!("resized vm {:#?}", self.name);
+        Ok(self.to_owned())
+    }
+
+    /// Reboot the virtual machine.
+    /// Network ports, process and init disk are kept in place.
+    #[tracing::instrument(skip_all)]
+    pub async fn reboot(&self) -> Result<Self, VirshleError> {
+        match self.vmm().api()?.state().await? {
+            VmState::Running => {
+                self.vmm().api()?.reboot().await?;
+            }
+            state => {
+                let message = format!("Couldn't reboot vm {:#?}.", self.name);
+                let help = format!("Vm must be running, but is {:?}.", state);
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        };
+        info!("rebooted vm {}", self.name);
+        Ok(self.to_owned())
+    }
+
+    /// Freeze the virtual machine vcpus.
+    /// Guest memory, network ports and process are kept in place.
+    #[tracing::instrument(skip_all)]
+    pub async fn pause(&self) -> Result<Self, VirshleError> {
+        match self.vmm().api()?.state().await? {
+            VmState::Running => {
+                self.vmm().api()?.pause().await?;
+            }
+            VmState::Paused => {}
+            state => {
+                let message = format!("Couldn't pause vm {:#?}.", self.name);
+                let help = format!("Vm must be running, but is {:?}.", state);
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        };
+        info!("paused vm {}", self.name);
+        Ok(self.to_owned())
+    }
+
+    /// Unfreeze a paused virtual machine.
+    #[tracing::instrument(skip_all)]
+    pub async fn resume(&self) -> Result<Self, VirshleError> {
+        match self.vmm().api()?.state().await? {
+            VmState::Paused => {
+                self.vmm().api()?.resume().await?;
+            }
+            VmState::Running => {}
+            state => {
+                let message = format!("Couldn't resume vm {:#?}.", self.name);
+                let help = format!("Vm must be paused, but is {:?}.", state);
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        };
+        info!("resumed vm {}", self.name);
+        Ok(self.to_owned())
+    }
+
+    /// Save vm memory, device state and disks under a tag.
+    /// Snapshots are stored at `/var/lib/virshle/vm/{vm_uuid}/snapshots/{tag}`.
+    ///
+    /// The vm is paused during the snapshot
+    /// and resumed afterward if it was running.
+    #[tracing::instrument(skip_all)]
+    pub async fn snapshot(&self, tag: &str) -> Result<Snapshot, VirshleError> {
+        // Safeguard: the tag ends up in a path.
+        let directory = self.snapshots().get_dir(tag)?;
+        if self.snapshots().get(tag).await.is_ok() {
+            let message = format!("Snapshot {:#?} already exists for vm {:#?}.", tag, self.name);
+            let help = "Choose another tag.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        let was_running = match self.vmm().api()?.state().await? {
+            VmState::Running => {
+                self.vmm().api()?.pause().await?;
+                true
+            }
+            VmState::Paused => false,
+            state => {
+                let message = format!("Couldn't snapshot vm {:#?}.", self.name);
+                let help = format!("Vm must be running or paused, but is {:?}.", state);
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        };
+
+        let res = self._snapshot(&directory).await;
+        // Resume whatever happened, never leave the vm paused.
+        let resumed = match was_running {
+            true => match self.vmm().api() {
+                Ok(mut api) => api.resume().await,
+                Err(e) => Err(e),
+            },
+            false => Ok(()),
+        };
+        if let Err(e) = res {
+            fs::remove_dir_all(&directory).ok();
+            if let Err(resume_err) = resumed {
+                error!("couldn't resume vm {}: {}", self.name, resume_err);
+            }
+            return Err(e);
+        }
+
+        let snapshot = self.snapshots().create(tag).await?;
+        resumed?;
+        info!("snapshoted vm {} as {:#?}", self.name, tag);
+        Ok(snapshot)
+    }
+    async fn _snapshot(&self, directory: &str) -> Result<(), VirshleError> {
+        fs::create_dir_all(format!("{directory}/disk"))?;
+        self.vmm()
+            .api()?
+            .snapshot(&format!("file://{directory}"))
+            .await?;
+        // Copy disks while vcpus are frozen.
+        for disk in &self.disk {
+            let path = Path::new(&disk.path);
+            if path.exists() {
+                let filename = path.file_name().unwrap().to_str().unwrap();
+                fs::copy(&disk.path, format!("{directory}/disk/{filename}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Roll the vm back to a previously taken snapshot.
+    /// The running process is replaced by a fresh one
+    /// restored from the snapshot memory and disks.
+    ///
+    /// Current disks are only set aside, until the restore succeeds.
+    /// On failure, they are put back and the vm is left stopped.
+    #[tracing::instrument(skip_all)]
+    pub async fn restore(&self, tag: &str) -> Result<Self, VirshleError> {
+        if Path::new(&self.get_migration_marker()?).exists() {
+            let message = format!("Couldn't restore vm {:#?}.", self.name);
+            let help = "Vm is being migrated, wait for the migration to end.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        let snapshot = self.snapshots().get(tag).await?;
+        let directory = snapshot.path;
+        // Safeguard: vm state must be complete.
+        for file in ["config.json", "state.json"] {
+            if !Path::new(&format!("{directory}/{file}")).exists() {
+                let message = format!("Couldn't restore vm {:#?} from {:#?}.", self.name, tag);
+                let help = format!("Snapshot is missing its {:#?} file.", file);
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        }
+
+        // Copy disks as they were at snapshot time next to the current ones.
+        let mut staged: Vec<&Disk> = vec![];
+        for disk in &self.disk {
+            let filename = Path::new(&disk.path).file_name().unwrap().to_str().unwrap();
+            let source = format!("{directory}/disk/{filename}");
+            if Path::new(&source).exists() {
+                if let Err(e) = fs::copy(&source, format!("{}.restore", disk.path)) {
+                    for disk in staged.iter().chain([&disk]) {
+                        fs::remove_file(format!("{}.restore", disk.path)).ok();
+                    }
+                    return Err(e.into());
+                }
+                staged.push(disk);
+            }
+        }
+
+        // Remove ch process
+        self.vmm().kill_process()?;
+        self.crypt().close_all()?;
+
+        if let Err(e) = self._restore(&directory, &staged).await {
+            error!("couldn't restore vm {}: {}", self.name, e);
+            // Tear the new process down and put back current disks.
+            self.vmm().kill_process().ok();
+            self.crypt().close_all().ok();
+            self.networks().delete_all().ok();
+            for disk in &staged {
+                let backup = format!("{}.orig", disk.path);
+                if Path::new(&backup).exists() {
+                    fs::rename(&backup, &disk.path).ok();
+                }
+                fs::remove_file(format!("{}.restore", disk.path)).ok();
+            }
+            self.to_owned().db().await?.set_running(false).await?;
+            return Err(e);
+        }
+        for disk in &staged {
+            fs::remove_file(format!("{}.orig", disk.path)).ok();
+        }
+
+        info!("restored vm {} from {:#?}", self.name, tag);
+        Ok(self.to_owned())
+    }
+    /// Swap staged disks in and restore the snapshot state into a fresh ch process.
+    /// The vmm opens disks at their usual path, so they must be in place beforehand.
+    async fn _restore(&self, directory: &str, staged: &[&Disk]) -> Result<(), VirshleError> {
+        for disk in staged {
+            fs::rename(&disk.path, format!("{}.orig", disk.path))?;
+            fs::rename(format!("{}.restore", disk.path), &disk.path)?;
+        }
+
+        self.networks().ensure_all().await?;
+        self.crypt().open_all()?;
+
+        // Start a fresh ch process and restore state into it.
+        self.vmm().start().exec().await?;
+        self.vmm()
+            .api()?
+            .restore(&format!("file://{directory}"))
+            .await?;
+        self.vmm().api()?.resume().await?;
+
+        self.set_vsock_permissions().await?;
+        self.to_owned().db().await?.set_running(true).await?;
+        Ok(())
+    }
+
     /// Create init disk and network before vm is booted.
     #[builder(
         finish_fn = exec,
@@ -127,16 +747,24 @@ impl Vm {
                     match api.state().await {
                         Ok(VmState::Running) => {
                             self.vmm()._remove_networks().await?;
-                            self.networks().ensure_all()?;
+                            self.networks().ensure_all().await?;
                             self.vmm()._add_networks().await?;
                         },
                         _ => {
-                            self.networks().ensure_all()?;
+                            self.networks().ensure_all().await?;
                         }
                     };
                 }
             }
-            _ => {}
+            _ => {
+                // Reconcile running vm firewall flows,
+                // with up to date security groups and leases.
+                if let Ok(mut api) = self.vmm().api() {
+                    if let Ok(VmState::Running | VmState::Paused) = api.state().await {
+                        self.networks().firewall().ensure_all().await?;
+                    }
+                }
+            }
         };
         Ok(self.to_owned())
     }
@@ -153,33 +781,354 @@ impl Vm {
         Ok(self.to_owned())
     }
 
-    /// Replace vm disks with a fresh disk.
+    /// Replace vm disks with a fresh thin copy of the cached backing image.
     #[builder(
         finish_fn = exec, 
         on(String,into),
         on(Option<String>,into)
     )]
-    pub fn replace_disk(&self, name: String) -> Result<(), VirshleError> {
+    pub async fn replace_disk(&self, name: String) -> Result<(), VirshleError> {
         let disks: Vec<Disk> = self.disk.clone().into_iter().filter(|e| e.name == name).collect();
         if let Some(disk) = disks.first() {
             let path = Path::new(&disk.path);
             if path.exists() {
+                self.crypt().close(disk)?;
                 // remove old disk
                 fs::remove_file(&disk.path)?;
 
                 // create fresh disk
-                let filename = Path::new(&disk.path)
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_owned();
-                let source = format!("{MANAGED_DIR}/cache/{}",filename);
-                fs::copy(&source, &disk.path)?;
+                let backing = match &disk.image {
+                    Some(image) => Image::get(image).await?.path,
+                    // Disks created before the image cache index.
+                    None => {
+                        let filename = Path::new(&disk.path)
+                            .file_name()
+                            .unwrap()
+                            .to_str()
+                            .unwrap()
+                            .to_owned();
+                        Image::get_path(&filename)
+                    }
+                };
+                match disk.encrypted {
+                    Some(true) => {
+                        let size = match &disk.size {
+                            Some(size) => disk_utils::reverse_human_bytes(size)?,
+                            None => disk_utils::get_virtual_size(&backing)?,
+                        };
+                        self.crypt().format(disk, &backing, size)?;
+                    }
+                    _ => disk_utils::make_thin_copy(&backing, &disk.path)?,
+                };
             }
         }
         Ok(())
     }
+    /// Create an empty data disk and attach it to the virtual machine.
+    /// A running vm gets the disk hot-plugged.
+    /// The disk is persisted into database and survives restarts.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+    )]
+    #[tracing::instrument(skip_all)]
+    pub async fn add_disk(&mut self, name: String, size: String) -> Result<Disk, VirshleError> {
+        Disk::check_name(&name)?;
+        if self.disk.iter().any(|e| e.name == name) || name == "init" {
+            let message = format!("Couldn't add disk {:#?} to vm {:#?}.", name, self.name);
+            let help = "A disk with the same name already exists.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        let bytes = disk_utils::reverse_human_bytes(&size)?;
+        if bytes == 0 {
+            let message = format!("Couldn't add disk {:#?} to vm {:#?}.", name, self.name);
+            let help = "Disk size can't be null.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+
+        let disk = Disk {
+            name: name.clone(),
+            path: format!("{}/{}.img", self.get_disks_dir()?, name),
+            readonly: Some(false),
+            image: None,
+            size: Some(size.clone()),
+            data: Some(true),
+            ..Default::default()
+        };
+        // Never overwrite a leftover file.
+        if Path::new(&disk.path).exists() {
+            let message = format!("Couldn't add disk {:#?} to vm {:#?}.", name, self.name);
+            let help = format!("File {:#?} already exists.", disk.path);
+            return Err(LibError::builder().msg(&message).help(&help).build().into());
+        }
+        fs::create_dir_all(self.get_disks_dir()?)?;
+        disk_utils::make_sparse_file(&disk.path, bytes)?;
+
+        let live = matches!(
+            self.vmm().api()?.state().await?,
+            VmState::Running | VmState::Paused
+        );
+        if live {
+            if let Err(e) = self.vmm().api()?.add_disk(&DiskConfig::from(&disk)).await {
+                fs::remove_file(&disk.path)?;
+                return Err(e);
+            }
+        }
+        self.disk.push(disk.clone());
+        self.db().await?.update().await?;
+
+        info!("added disk {:#?} to vm {:#?}", name, self.name);
+        Ok(disk)
+    }
+
+    /// Detach a data disk from the virtual machine and delete its file.
+    /// A running vm gets the disk hot-unplugged.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+    )]
+    #[tracing::instrument(skip_all)]
+    pub async fn remove_disk(&mut self, name: String) -> Result<Disk, VirshleError> {
+        let disk = match self.disk.iter().find(|e| e.name == name) {
+            // Template disks (os, init...) are kept.
+            Some(disk) if disk.data == Some(true) => disk.to_owned(),
+            Some(_) => {
+                let message = format!("Couldn't remove disk {:#?} from vm {:#?}.", name, self.name);
+                let help = "Only data disks can be removed.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+            None => {
+                let message = format!("Couldn't remove disk {:#?} from vm {:#?}.", name, self.name);
+                let help = "No disk with this name.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+        };
+
+        let live = matches!(
+            self.vmm().api()?.state().await?,
+            VmState::Running | VmState::Paused
+        );
+        if live {
+            // Device ids are assigned by cloud-hypervisor on boot.
+            match self.vmm().get_disk_id(&disk).await? {
+                Some(id) => self.vmm().api()?.remove_device(&id).await?,
+                None => {
+                    let message =
+                        format!("Couldn't remove disk {:#?} from vm {:#?}.", name, self.name);
+                    let help = "Disk is not attached to the running vm.";
+                    return Err(LibError::builder().msg(&message).help(help).build().into());
+                }
+            };
+        }
+        self.disk.retain(|e| e.name != name);
+        self.db().await?.update().await?;
+
+        let path = Path::new(&disk.path);
+        if path.exists() {
+            fs::remove_file(&disk.path)?;
+        }
+
+        info!("removed disk {:#?} from vm {:#?}", name, self.name);
+        Ok(disk)
+    }
+
+    /// Grow a virtual machine disk, and persist the new size into database.
+    /// A running vm disk is resized live.
+    ///
+    /// Only the block device grows,
+    /// partitions and filesystems have to be extended from the guest.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+    )]
+    #[tracing::instrument(skip_all)]
+    pub async fn resize_disk(&mut self, name: String, size: String) -> Result<Disk, VirshleError> {
+        let Some(index) = self.disk.iter().position(|e| e.name == name) else {
+            let message = format!("Couldn't resize disk {:#?} of vm {:#?}.", name, self.name);
+            let help = "No disk with this name.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        };
+        let disk = self.disk[index].clone();
+        let bytes = disk_utils::reverse_human_bytes(&size)?;
+        let current = disk.get_size()?;
+        if bytes <= current {
+            let message = format!("Couldn't resize disk {:#?} of vm {:#?}.", name, self.name);
+            let help = format!(
+                "Disks can only grow, current size is {}.",
+                disk_utils::human_bytes(&current)?
+            );
+            return Err(LibError::builder().msg(&message).help(&help).build().into());
+        }
+
+        // Node saturation
+        NodeInfo::get()
+            .await?
+            .can_resize_disk(self, &disk, bytes)
+            .await?;
+
+        let live = matches!(
+            self.vmm().api()?.state().await?,
+            VmState::Running | VmState::Paused
+        );
+        if live && disk.encrypted == Some(true) {
+            let message = format!("Couldn't resize disk {:#?} of vm {:#?}.", name, self.name);
+            let help = "Encrypted disks can only be resized while the vm is stopped.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        if live {
+            match self.vmm().get_disk_id(&disk).await? {
+                Some(id) => self.vmm().api()?.resize_disk(&id, bytes).await?,
+                None => {
+                    let message =
+                        format!("Couldn't resize disk {:#?} of vm {:#?}.", name, self.name);
+                    let help = "Disk is not attached to the running vm.";
+                    return Err(LibError::builder().msg(&message).help(help).build().into());
+                }
+            };
+        } else if disk.encrypted == Some(true) {
+            // The LUKS mapping spans the whole container on next unlock.
+            self.crypt().close(&disk)?;
+            disk_utils::grow_image(&disk.path, bytes + LUKS_HEADER_SIZE)?;
+        } else {
+            disk_utils::grow_image(&disk.path, bytes)?;
+        }
+        self.disk[index].size = Some(size);
+        self.db().await?.update().await?;
+
+        info!("resized disk {:#?} of vm {:#?}", name, self.name);
+        Ok(self.disk[index].to_owned())
+    }
+
+    /// Change a virtual machine disk I/O tuning,
+    /// and persist it into database.
+    ///
+    /// Bandwidth (per second, ex: 100MiB) and iops limits are removed when set to 0.
+    /// Cloud-hypervisor can't update a plugged disk,
+    /// so a running vm gets the new settings on next start.
+    /// Returns whether the vm must be restarted for them to apply.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    #[tracing::instrument(skip_all)]
+    pub async fn update_disk(
+        &mut self,
+        name: String,
+        direct: Option<bool>,
+        num_queues: Option<u64>,
+        queue_size: Option<u64>,
+        bandwidth: Option<String>,
+        iops: Option<u64>,
+    ) -> Result<(Disk, bool), VirshleError> {
+        let Some(index) = self.disk.iter().position(|e| e.name == name) else {
+            let message = format!("Couldn't update disk {:#?} of vm {:#?}.", name, self.name);
+            let help = "No disk with this name.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        };
+        if num_queues == Some(0) || queue_size == Some(0) {
+            let message = format!("Couldn't update disk {:#?} of vm {:#?}.", name, self.name);
+            let help = "Queues number and size can't be null.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+
+        let disk = &mut self.disk[index];
+        if direct.is_some() {
+            disk.direct = direct;
+        }
+        if num_queues.is_some() {
+            disk.num_queues = num_queues;
+        }
+        if queue_size.is_some() {
+            disk.queue_size = queue_size;
+        }
+        let mut rate_limiter = disk.rate_limiter.clone().unwrap_or_default();
+        if let Some(bandwidth) = bandwidth {
+            rate_limiter.bandwidth = match disk_utils::reverse_human_bytes(&bandwidth)? {
+                0 => None,
+                bytes => Some(TokenBucketConfig::per_second(bytes)),
+            };
+        }
+        if let Some(iops) = iops {
+            rate_limiter.ops = match iops {
+                0 => None,
+                ops => Some(TokenBucketConfig::per_second(ops)),
+            };
+        }
+        disk.rate_limiter = match rate_limiter {
+            RateLimiterConfig {
+                bandwidth: None,
+                ops: None,
+            } => None,
+            rate_limiter => Some(rate_limiter),
+        };
+        let disk = disk.to_owned();
+        self.db().await?.update().await?;
+
+        let restart = matches!(
+            self.vmm().api()?.state().await?,
+            VmState::Running | VmState::Paused
+        );
+        if restart {
+            warn!(
+                "disk {:#?} of vm {:#?} will be updated on next start",
+                name, self.name
+            );
+        }
+        info!("updated disk {:#?} of vm {:#?}", name, self.name);
+        Ok((disk, restart))
+    }
+
+    /// Check the filesystems of the virtual machine disks (or of a single one),
+    /// and fix errors if `repair` is set.
+    /// The vm must be stopped.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    #[tracing::instrument(skip_all)]
+    pub async fn check_disks(
+        &self,
+        name: Option<String>,
+        repair: Option<bool>,
+    ) -> Result<Vec<PartitionReport>, VirshleError> {
+        let state = self.vmm().api()?.state().await?;
+        if matches!(state, VmState::Running | VmState::Paused) {
+            let message = format!("Couldn't check disks of vm {:#?}.", self.name);
+            let help = format!("Vm must be stopped, but is {:?}.", state);
+            return Err(LibError::builder().msg(&message).help(&help).build().into());
+        }
+        let disks: Vec<&Disk> = match &name {
+            Some(name) => self.disk.iter().filter(|e| &e.name == name).collect(),
+            None => self.disk.iter().collect(),
+        };
+        if disks.is_empty() {
+            let message = format!("Couldn't check disks of vm {:#?}.", self.name);
+            let help = format!("No disk with name {:#?}.", name.unwrap_or_default());
+            return Err(LibError::builder().msg(&message).help(&help).build().into());
+        }
+
+        let mut reports = vec![];
+        for disk in disks {
+            match disk.encrypted {
+                // Check the unlocked device.
+                Some(true) => {
+                    self.crypt().open(disk)?;
+                    let device = Disk {
+                        path: self.crypt().get_device_path(disk),
+                        ..disk.to_owned()
+                    };
+                    let res = device.check(repair.unwrap_or_default());
+                    self.crypt().close(disk)?;
+                    reports.extend(res?);
+                }
+                _ => reports.extend(disk.check(repair.unwrap_or_default())?),
+            };
+        }
+        Ok(reports)
+    }
+
     /// Remove vm disks file from filesystem.
     pub fn delete_disks(&self) -> Result<Vec<Disk>, VirshleError> {
         for disk in &self.disk {
@@ -202,3 +1151,43 @@ impl Vm {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn clone_keeps_config_but_not_identity() -> Result<()> {
+        let toml = r#"
+            name = "source"
+            uuid = "b30458d1-7c7f-4d06-acc2-159e43892e87"
+            vcpu = 2
+            vram = "4GiB"
+            security_groups = ["web"]
+            disk = []
+
+            [[net]]
+            name = "main"
+            [net.type.tap]
+            mac = "22:60:ec:b7:7d:ed"
+        "#;
+        let mut vm = Vm::from_toml(toml)?;
+        vm.user_data = Some(UserData {
+            timezone: Some("Europe/Paris".to_owned()),
+            ..Default::default()
+        });
+
+        let clone = vm._clone_config();
+        assert_eq!(clone.vcpu, vm.vcpu);
+        assert_eq!(clone.vram, vm.vram);
+        assert_eq!(clone.security_groups, vm.security_groups);
+        assert_eq!(clone.user_data, vm.user_data);
+        assert_ne!(clone.uuid, vm.uuid);
+        assert_ne!(clone.name, vm.name);
+        let net = &clone.net.unwrap()[0];
+        assert_eq!(net.name, "main");
+        assert_eq!(net.get_mac(), None);
+        Ok(())
+    }
+}
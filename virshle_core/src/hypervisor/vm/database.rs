@@ -134,6 +134,23 @@ impl VmDbFunctions {
         }
         Ok(vms)
     }
+    /// Return VMs flagged for autostart that were meant to be running
+    /// (started and never shut down since).
+    pub async fn autostart(&self) -> Result<Vec<Vm>, VirshleError> {
+        let records = database::prelude::Vm::find()
+            .filter(database::entity::vm::Column::Running.eq(true))
+            .order_by_asc(database::entity::vm::Column::CreatedAt)
+            .all(&self.db)
+            .await?;
+        let mut vms: Vec<Vm> = vec![];
+        for record in records {
+            let vm: Vm = record.try_into()?;
+            if vm.is_autostart() {
+                vms.push(vm);
+            }
+        }
+        Ok(vms)
+    }
     async fn filter_by_state(vms: Vec<Vm>, state: &VmState) -> Result<Vec<Vm>, VirshleError> {
         let mut vm_by_state: Vec<Vm> = vec![];
         for vm in vms {
@@ -187,6 +204,55 @@ impl VmDbMethods<'_> {
             }
         };
 
+        // Link vm disks to the cached images they are thin copies of.
+        for disk in &self.vm.disk {
+            if let Some(image_name) = &disk.image {
+                let image_record = database::prelude::Image::find()
+                    .filter(image::Column::Name.eq(image_name))
+                    .one(&self.db)
+                    .await?;
+                if let Some(image_record) = image_record {
+                    let junction_record = database::entity::vm_image::ActiveModel {
+                        vm_id: ActiveValue::Set(self.vm.id.unwrap() as i32),
+                        image_id: ActiveValue::Set(image_record.id),
+                        disk_name: ActiveValue::Set(disk.name.clone()),
+                    };
+                    database::prelude::VmImage::insert(junction_record)
+                        .exec(&self.db)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(self.vm.to_owned())
+    }
+    /// Persist vm definition changes into database.
+    pub async fn update(&self) -> Result<Vm, VirshleError> {
+        let vm_record = database::prelude::Vm::find()
+            .filter(database::entity::vm::Column::Uuid.eq(self.vm.uuid.to_string()))
+            .one(&self.db)
+            .await?;
+        if let Some(vm_record) = vm_record {
+            let now: NaiveDateTime = Utc::now().naive_utc();
+            let mut vm_record = vm_record.into_active_model();
+            vm_record.definition = ActiveValue::Set(serde_json::to_value(&self.vm)?);
+            vm_record.updated_at = ActiveValue::Set(now);
+            vm_record.update(&self.db).await?;
+        }
+        Ok(self.vm.to_owned())
+    }
+    /// Remember whether the vm is meant to be running.
+    /// Autostart vms are booted back on node start only if set.
+    pub async fn set_running(&self, running: bool) -> Result<Vm, VirshleError> {
+        let vm_record = database::prelude::Vm::find()
+            .filter(database::entity::vm::Column::Uuid.eq(self.vm.uuid.to_string()))
+            .one(&self.db)
+            .await?;
+        if let Some(vm_record) = vm_record {
+            let mut vm_record = vm_record.into_active_model();
+            vm_record.running = ActiveValue::Set(running);
+            vm_record.update(&self.db).await?;
+        }
         Ok(self.vm.to_owned())
     }
     /// Remove Vm record from database.
@@ -197,6 +263,16 @@ impl VmDbMethods<'_> {
             .await?;
 
         if let Some(vm_record) = &vm_record {
+            // Delete Snapshot record(s).
+            database::prelude::Snapshot::delete_many()
+                .filter(snapshot::Column::VmId.eq(vm_record.id))
+                .exec(&self.db)
+                .await?;
+            // Delete VmImage junction record(s).
+            database::prelude::VmImage::delete_many()
+                .filter(vm_image::Column::VmId.eq(vm_record.id))
+                .exec(&self.db)
+                .await?;
             // Delete AccountVm junction record(s).
             database::prelude::AccountVm::delete_many()
                 .filter(account_vm::Column::VmId.eq(vm_record.id))
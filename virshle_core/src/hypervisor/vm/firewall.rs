@@ -0,0 +1,218 @@
+use super::networks::VmNetMethods;
+use crate::config::{Config, DhcpType, FirewallRule, NetType, VmNet};
+use crate::hypervisor::{Vm, VmState};
+use crate::network::{ovs::OvsFirewall, utils};
+
+use ipnet::IpNet;
+use std::fs;
+use std::time::Duration;
+
+// Error Handling
+use miette::Result;
+use tracing::{error, trace, warn};
+use virshle_error::{LibError, VirshleError};
+
+/// Seconds between two checks of running vms leases.
+pub const LEASE_WATCH_INTERVAL: u64 = 10;
+
+impl VmNetMethods<'_> {
+    pub fn firewall(&self) -> VmFirewallMethods<'_> {
+        VmFirewallMethods { vm: self.vm }
+    }
+}
+pub struct VmFirewallMethods<'a> {
+    pub vm: &'a Vm,
+}
+impl VmFirewallMethods<'_> {
+    /// Return the vm security groups names:
+    /// its own, then its account ones.
+    pub async fn get_groups(&self) -> Result<Vec<String>, VirshleError> {
+        let config = Config::get()?;
+        let mut names = self.vm.security_groups.clone().unwrap_or_default();
+        if let Ok(uuid) = self.vm.get_account_uuid().await {
+            for name in config.firewall.get_account_groups(&uuid) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        Ok(names)
+    }
+    /// Return the rules of every vm security group.
+    pub async fn get_rules(&self) -> Result<Vec<FirewallRule>, VirshleError> {
+        let config = Config::get()?;
+        let names = self.get_groups().await?;
+        let rules = config
+            .firewall
+            .get_groups(&names)?
+            .into_iter()
+            .flat_map(|e| e.rule)
+            .collect();
+        Ok(rules)
+    }
+    /// Return the addresses a network interface may send from,
+    /// or none if no lease source is configured for it (only its mac is checked).
+    ///
+    /// Leased addresses if any, the network fake dhcp pool otherwise.
+    /// Kea subnets are unknown, so until the vm holds a lease
+    /// it may only request one.
+    async fn get_ips(&self, net: &VmNet) -> Result<Option<Vec<IpNet>>, VirshleError> {
+        let pool = match Config::get()?.dhcp {
+            Some(DhcpType::Fake(fake_dhcp_config)) => match fake_dhcp_config.pool.get(&net.name) {
+                Some(pool) => Some(pool.get_subnet()),
+                None => return Ok(None),
+            },
+            Some(DhcpType::Kea(_)) => None,
+            None => return Ok(None),
+        };
+        let mac = self.vm.networks().get_mac(net)?;
+        let ips: Vec<IpNet> = self
+            .vm
+            .networks()
+            .leases()
+            .list()
+            .await?
+            .into_iter()
+            .filter(|e| e.mac == mac)
+            .map(|e| IpNet::from(e.address))
+            .collect();
+        match pool {
+            Some(subnet) if ips.is_empty() => Ok(Some(vec![subnet])),
+            _ => Ok(Some(ips)),
+        }
+    }
+    /// Return the flow cookie and the ovs port name of a network interface,
+    /// or none if the interface is not plugged into ovs.
+    fn get_port(&self, net: &VmNet) -> Option<(u64, String)> {
+        let port_name = format!("vm-{}--{}", self.vm.name, net.name);
+        let port_name = match net._type {
+            NetType::Vhost(_) => port_name,
+            NetType::Tap(_) => utils::unix_name(&port_name),
+            NetType::MacVTap(_) => return None,
+        };
+        Some((OvsFirewall::cookie(&self.vm.uuid, &net.name), port_name))
+    }
+    /// Return the vm conntrack zone.
+    fn get_zone(&self) -> Result<u16, VirshleError> {
+        match self.vm.id {
+            Some(id) => Ok((id % u16::MAX as u64) as u16 + 1),
+            None => {
+                let message = format!("Couldn't set firewall of vm {:#?}.", self.vm.name);
+                let help = "Vm must be saved into database first.";
+                Err(LibError::builder().msg(&message).help(help).build().into())
+            }
+        }
+    }
+
+    /// Return a network interface firewall,
+    /// or none if the interface is not plugged into ovs
+    /// or if its leases couldn't be read (dhcp server unreachable).
+    async fn get_firewall(&self, net: &VmNet) -> Result<Option<OvsFirewall>, VirshleError> {
+        let (cookie, port) = match self.get_port(net) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let ips = match self.get_ips(net).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!(
+                    "[firewall] couldn't get leases of network {:#?}, skipping firewall: {}",
+                    net.name, e
+                );
+                return Ok(None);
+            }
+        };
+        let firewall = OvsFirewall {
+            bridge: self.vm.networks().get_switch(net)?.name,
+            port,
+            cookie,
+            zone: self.get_zone()?,
+            mac: self.vm.networks().get_mac(net)?,
+            ips,
+            rules: self.get_rules().await?,
+        };
+        Ok(Some(firewall))
+    }
+
+    /// Add the vm security groups and anti-spoofing flows
+    /// on a network interface ovs port.
+    pub async fn create(&self, net: &VmNet) -> Result<(), VirshleError> {
+        let firewall = match self.get_firewall(net).await? {
+            Some(v) => v,
+            None => {
+                trace!("no firewall for network {:#?}", net.name);
+                return Ok(());
+            }
+        };
+        trace!("creating firewall for network {:#?}", net.name);
+        firewall.create(&self.vm.get_net_flows(net)?)?;
+        Ok(())
+    }
+    /// Replace a network interface flows,
+    /// only if they changed (ex: the vm got a lease).
+    pub async fn refresh(&self, net: &VmNet) -> Result<(), VirshleError> {
+        let Some(firewall) = self.get_firewall(net).await? else {
+            return Ok(());
+        };
+        let path = self.vm.get_net_flows(net)?;
+        if !firewall.is_applied(&path) {
+            trace!("updating firewall for network {:#?}", net.name);
+            self.delete(net)?;
+            firewall.create(&path)?;
+        }
+        Ok(())
+    }
+    /// Remove a network interface flows.
+    /// WARNING: Silently fail (due to ".ok()").
+    pub fn delete(&self, net: &VmNet) -> Result<(), VirshleError> {
+        if let Some((cookie, _)) = self.get_port(net) {
+            if let Ok(switch) = self.vm.networks().get_switch(net) {
+                OvsFirewall::delete(&switch.name, cookie).ok();
+            }
+            fs::remove_file(self.vm.get_net_flows(net)?).ok();
+        }
+        Ok(())
+    }
+    /// Replace every network interface flows,
+    /// with up to date security groups and leases.
+    #[tracing::instrument(skip_all)]
+    pub async fn ensure_all(&self) -> Result<(), VirshleError> {
+        trace!("updating firewall for vm {:#?}", self.vm.name);
+        for net in self.vm.net.iter().flatten() {
+            self.delete(net)?;
+            self.create(net).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Vm {
+    /// Keep running vms flows in line with their leases.
+    ///
+    /// A vm only holds a lease some time after booting,
+    /// until then its flows only allow dhcp requests.
+    pub async fn watch_leases() {
+        let mut interval = tokio::time::interval(Duration::from_secs(LEASE_WATCH_INTERVAL));
+        loop {
+            interval.tick().await;
+            if let Err(e) = Self::refresh_firewalls().await {
+                error!("[firewall] {}", e);
+            }
+        }
+    }
+    /// Refresh every running vm flows.
+    async fn refresh_firewalls() -> Result<(), VirshleError> {
+        for vm in Vm::database().await?.many().get().await? {
+            match vm.vmm().api()?.state().await? {
+                VmState::Running | VmState::Paused => {}
+                _ => continue,
+            };
+            for net in vm.net.iter().flatten() {
+                if let Err(e) = vm.networks().firewall().refresh(net).await {
+                    warn!("[firewall] vm {:#?}: {}", vm.name, e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
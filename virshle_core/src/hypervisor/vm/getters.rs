@@ -6,6 +6,8 @@ use uuid::Uuid;
 // Cloud Hypervisor
 use crate::hypervisor::{VmState, VmTable};
 
+use crate::hypervisor::disk::utils::reverse_human_bytes;
+
 // Ips
 use crate::config::{Config, VmNet, VmTemplate};
 use crate::network::dhcp::Lease;
@@ -64,30 +66,61 @@ impl Vm {
         let path = format!("{MANAGED_DIR}/vm/{}/net/{}.sock", self.uuid, net.name);
         Ok(path)
     }
+    /*
+     * Return vm network firewall flows path.
+     */
+    pub fn get_net_flows(&self, net: &VmNet) -> Result<String, VirshleError> {
+        let path = format!("{MANAGED_DIR}/vm/{}/net/{}.flows", self.uuid, net.name);
+        Ok(path)
+    }
+    /// Return the path of the file marking a vm migration in progress.
+    pub fn get_migration_marker(&self) -> Result<String, VirshleError> {
+        let path = format!("{MANAGED_DIR}/vm/{}/migration", self.uuid);
+        Ok(path)
+    }
     /// Return vm's disks directory path.
     pub fn get_disks_dir(&self) -> Result<String, VirshleError> {
         let path = format!("{MANAGED_DIR}/vm/{}/disk", self.uuid);
         Ok(path)
     }
+    /// Return vm's encrypted disk key path.
+    pub fn get_disk_key(&self) -> Result<String, VirshleError> {
+        let path = format!("{MANAGED_DIR}/vm/{}/disk.key", self.uuid);
+        Ok(path)
+    }
+    /// Return vm's snapshots directory path.
+    pub fn get_snapshots_dir(&self) -> Result<String, VirshleError> {
+        let path = format!("{MANAGED_DIR}/vm/{}/snapshots", self.uuid);
+        Ok(path)
+    }
+    /// Return the vcpu number the vm can be resized up to.
+    pub fn get_max_vcpu(&self) -> u64 {
+        self.max_vcpu.unwrap_or(self.vcpu * 2).max(self.vcpu)
+    }
+    /// Return the ram size (in bytes) the vm can be resized up to.
+    pub fn get_max_vram(&self) -> Result<u64, VirshleError> {
+        let vram = reverse_human_bytes(&self.vram)?;
+        let max_vram = match &self.max_vram {
+            Some(max_vram) => reverse_human_bytes(max_vram)?,
+            None => vram * 2,
+        };
+        Ok(max_vram.max(vram))
+    }
     /// Get sum of vm disks size.
     pub fn get_disks_size(&self) -> u64 {
         self.disk.iter().map(|e| e.get_size().unwrap_or(0)).sum()
     }
 
-    /// Return path where to mount vm pipelight-init disk to.
-    /// This path is used to provision a pipelight-init disk (cloud-init alternative)
-    /// with user defined data, mainly:
-    /// - network interface ips (Ipv4 / Ipv6)
-    /// - hostname
-    pub fn get_mount_dir(&self) -> Result<String, VirshleError> {
-        let path = format!("{MANAGED_DIR}/vm/{}/tmp", self.uuid);
-        Ok(path)
-    }
     /// Return vm vsocket path for host guest (ssh) communication.
     pub fn get_vsocket(&self) -> Result<String, VirshleError> {
         let path = format!("{MANAGED_DIR}/vm/{}/ch.vsock", self.uuid);
         Ok(path)
     }
+    /// Return vm serial console socket path.
+    pub fn get_console_socket(&self) -> Result<String, VirshleError> {
+        let path = format!("{MANAGED_DIR}/vm/{}/console.sock", self.uuid);
+        Ok(path)
+    }
 
     /// Return vm state and ips.
     pub async fn get_info(&self) -> Result<VmInfo, VirshleError> {
@@ -120,8 +153,13 @@ impl Vm {
         Err(err.into())
     }
 
+    /// Return the first network interface mac address,
+    /// or the uuid derived one if the vm has no network.
     pub fn get_default_mac(&self) -> Result<MacAddr6, VirshleError> {
-        let mac_address = utils::uuid_to_mac(&self.uuid);
+        let mac_address = match self.net.iter().flatten().next() {
+            Some(net) => self.networks().get_mac(net)?,
+            None => utils::uuid_to_mac(&self.uuid),
+        };
         Ok(mac_address)
     }
 }
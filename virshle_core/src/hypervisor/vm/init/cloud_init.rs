@@ -0,0 +1,164 @@
+/*
+* cloud-init NoCloud seed.
+*
+* Stock distro cloud images look for a disk labelled `cidata`
+* holding a meta-data, a user-data and a network-config file.
+* See: https://cloudinit.readthedocs.io/en/latest/reference/datasources/nocloud.html
+*/
+use super::InitData;
+
+// Error handling
+use miette::Result;
+use virshle_error::VirshleError;
+
+/// Quote a string as a yaml scalar.
+/// Json strings are valid yaml double-quoted scalars.
+fn quote(string: &str) -> Result<String, VirshleError> {
+    Ok(serde_json::to_string(string)?)
+}
+
+impl InitData {
+    /// Render the instance identity.
+    /// The instance-id must not change across boots,
+    /// or cloud-init provisions the vm again.
+    pub fn to_cloud_init_meta_data(&self) -> Result<String, VirshleError> {
+        let mut meta_data = "".to_owned();
+        if let Some(vm_data) = &self.vm_data {
+            meta_data += &format!("instance-id: {}\n", quote(&vm_data.uuid.to_string())?);
+            meta_data += &format!("local-hostname: {}\n", quote(&vm_data.hostname)?);
+        }
+        Ok(meta_data)
+    }
+
+    /// Render user-data into a cloud-config file.
+    pub fn to_cloud_init_user_data(&self) -> Result<String, VirshleError> {
+        let mut user_data = "#cloud-config\n".to_owned();
+
+        if let Some(vm_data) = &self.vm_data {
+            user_data += &format!("hostname: {}\n", quote(&vm_data.hostname)?);
+            user_data += "preserve_hostname: false\n";
+        }
+        if let Some(data) = &self.user_data {
+            if let Some(timezone) = &data.timezone {
+                user_data += &format!("timezone: {}\n", quote(timezone)?);
+            }
+            if let Some(locale) = &data.locale {
+                user_data += &format!("locale: {}\n", quote(locale)?);
+            }
+            if !data.user.is_empty() {
+                user_data += "users:\n";
+            }
+            for user in &data.user {
+                user_data += &format!("  - name: {}\n", quote(&user.name)?);
+                if !user.groups.is_empty() {
+                    let groups = user
+                        .groups
+                        .iter()
+                        .map(|e| quote(e))
+                        .collect::<Result<Vec<String>, VirshleError>>()?;
+                    user_data += &format!("    groups: [{}]\n", groups.join(", "));
+                }
+                if let Some(shell) = &user.shell {
+                    user_data += &format!("    shell: {}\n", quote(shell)?);
+                }
+                if user.sudo == Some(true) {
+                    user_data += "    sudo: \"ALL=(ALL) NOPASSWD:ALL\"\n";
+                }
+                match &user.hashed_password {
+                    Some(hash) => {
+                        user_data += &format!("    passwd: {}\n", quote(hash)?);
+                        user_data += "    lock_passwd: false\n";
+                    }
+                    None => user_data += "    lock_passwd: true\n",
+                }
+                if let Some(ssh) = &user.ssh {
+                    user_data += "    ssh_authorized_keys:\n";
+                    for key in &ssh.authorized_keys {
+                        user_data += &format!("      - {}\n", quote(key)?);
+                    }
+                }
+            }
+            if let Some(run_commands) = &data.run_commands {
+                if !run_commands.pre_net.is_empty() {
+                    user_data += "bootcmd:\n";
+                    for command in &run_commands.pre_net {
+                        user_data += &format!("  - {}\n", quote(command)?);
+                    }
+                }
+            }
+        }
+
+        let mut write_files = "".to_owned();
+        if let Some(vm_data) = &self.vm_data {
+            // Set dhcp persistent identifier (duid-uuid).
+            // So the Vm can obtain the same ip from dhcp accross hardware (disk) changes.
+            let raw_duid = vm_data
+                .uuid
+                .as_bytes()
+                .iter()
+                .map(|e| format!("{:02x}", e))
+                .collect::<Vec<String>>()
+                .join(":");
+            write_files += "  - path: /etc/systemd/networkd.conf.d/virshle-duid.conf\n";
+            write_files += "    content: |\n";
+            for section in ["DHCPv4", "DHCPv6"] {
+                write_files += &format!("      [{section}]\n");
+                write_files += "      DUIDType=uuid\n";
+                write_files += &format!("      DUIDRawData={raw_duid}\n");
+            }
+        }
+        if let Some(data) = &self.user_data {
+            for file in &data.write_files {
+                write_files += &format!("  - path: {}\n", quote(&file.path)?);
+                write_files += "    encoding: b64\n";
+                write_files += &format!("    content: {}\n", quote(&file.to_base64())?);
+                if let Some(mode) = &file.mode {
+                    write_files += &format!("    permissions: {}\n", quote(mode)?);
+                }
+                if let Some(owner) = &file.owner {
+                    write_files += &format!("    owner: {}\n", quote(owner)?);
+                }
+            }
+            // runcmd only runs on first boot,
+            // per-boot scripts run on every boot, once network is up.
+            if let Some(run_commands) = &data.run_commands {
+                if !run_commands.post_net.is_empty() {
+                    write_files +=
+                        "  - path: /var/lib/cloud/scripts/per-boot/virshle-post-net.sh\n";
+                    write_files += "    permissions: \"0755\"\n";
+                    write_files += "    content: |\n";
+                    write_files += "      #!/bin/sh\n";
+                    for line in run_commands.post_net.iter().flat_map(|e| e.lines()) {
+                        write_files += &format!("      {line}\n");
+                    }
+                }
+            }
+        }
+        if !write_files.is_empty() {
+            user_data += "write_files:\n";
+            user_data += &write_files;
+        }
+        Ok(user_data)
+    }
+
+    /// Render the network configuration (version 2).
+    /// Interfaces are matched by their mac address.
+    pub fn to_cloud_init_network_config(&self) -> Result<String, VirshleError> {
+        let mut network_config = "version: 2\n".to_owned();
+        if let Some(vm_data) = &self.vm_data {
+            if !vm_data.net.is_empty() {
+                network_config += "ethernets:\n";
+            }
+            for net in &vm_data.net {
+                network_config += &format!("  {}:\n", quote(&net.name)?);
+                network_config += "    match:\n";
+                network_config +=
+                    &format!("      macaddress: {}\n", quote(&net.mac.to_lowercase())?);
+                network_config += "    dhcp4: true\n";
+                network_config += "    dhcp6: true\n";
+                network_config += "    accept-ra: true\n";
+            }
+        }
+        Ok(network_config)
+    }
+}
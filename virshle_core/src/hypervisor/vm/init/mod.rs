@@ -0,0 +1,321 @@
+use crate::config::UserData;
+use crate::hypervisor::{
+    disk::{Disk, InitDisk},
+    Vm,
+};
+use crate::network::utils::uuid_to_duid;
+
+mod cloud_init;
+mod pipelight;
+
+pub use uuid::Uuid;
+
+// Templating engine
+
+// Mac
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+// "mkdir -p ./scripts/mnt/pipelight-init",
+// "mount -t ext4 -o loop ./scripts/pipelight-init.img ./scripts/mnt/pipelight-init",
+// "cp -r /pipelight-init/.* ./scripts/mnt/pipelight-init",
+// "cp -r /pipelight-init/* ./scripts/mnt/pipelight-init",
+// "umount ./scripts/mnt/pipelight-init",
+
+// Error handling
+use miette::{IntoDiagnostic, Result};
+use tracing::{debug, info};
+use virshle_error::VirshleError;
+
+/// How the guest is provisioned on first boot,
+/// which sets the init disk content.
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum InitBackend {
+    /// A pipelight.toml, run by pipelight-init.
+    #[default]
+    Pipelight,
+    /// A cloud-init NoCloud seed, for stock cloud images.
+    CloudInit,
+}
+impl InitBackend {
+    /// Return the init disk filesystem label.
+    pub fn get_label(&self) -> &'static str {
+        match self {
+            InitBackend::Pipelight => "INIT",
+            // Label NoCloud datasources are looked up with.
+            InitBackend::CloudInit => "cidata",
+        }
+    }
+    /// Return the init disk file name.
+    pub fn get_filename(&self) -> &'static str {
+        match self {
+            InitBackend::Pipelight => "pipelight-init",
+            InitBackend::CloudInit => "cidata",
+        }
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct InitData {
+    // Autogenerated data.
+    pub vm_data: Option<VmData>,
+    // User defined data.
+    pub user_data: Option<UserData>,
+}
+
+/// Autogenerated data:
+/// - uuid is the VM uuid (cloud-init instance-id).
+/// - net lists the VM network interfaces and their mac address.
+/// - dhcp duid-uuid is based on VM uuid.
+/// - hostname is the VM name generated on VM creation.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct VmData {
+    uuid: Uuid,
+    net: Vec<VmNetData>,
+    duid: String,
+    hostname: String,
+}
+/// A VM network interface, as seen from the guest.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct VmNetData {
+    name: String,
+    mac: String,
+}
+
+impl TryFrom<Vm> for VmData {
+    type Error = VirshleError;
+    fn try_from(vm: Vm) -> Result<Self, Self::Error> {
+        VmData::try_from(&vm)
+    }
+}
+impl TryFrom<&Vm> for VmData {
+    type Error = VirshleError;
+    fn try_from(vm: &Vm) -> Result<Self, Self::Error> {
+        let net = vm
+            .networks()
+            .get_macs()?
+            .into_iter()
+            .map(|(name, mac)| VmNetData {
+                name,
+                mac: mac.to_string(),
+            })
+            .collect();
+        Ok(VmData {
+            uuid: vm.uuid,
+            net,
+            duid: uuid_to_duid(&vm.uuid),
+            hostname: vm.name.to_owned(),
+        })
+    }
+}
+
+impl InitData {
+    /// Render the files to write on the init disk,
+    /// as (file name, content) pairs.
+    pub fn to_files(&self, backend: &InitBackend) -> Result<Vec<(String, String)>, VirshleError> {
+        let files = match backend {
+            InitBackend::Pipelight => vec![(
+                "pipelight.toml".to_owned(),
+                self.to_pipelight_toml_config()?,
+            )],
+            InitBackend::CloudInit => vec![
+                ("meta-data".to_owned(), self.to_cloud_init_meta_data()?),
+                ("user-data".to_owned(), self.to_cloud_init_user_data()?),
+                (
+                    "network-config".to_owned(),
+                    self.to_cloud_init_network_config()?,
+                ),
+            ],
+        };
+        Ok(files)
+    }
+}
+
+impl Vm {
+    /// Create and provision an init disk,
+    /// and add it vm config.
+    #[tracing::instrument]
+    pub fn add_init_disk(&mut self, user_data: Option<UserData>) -> Result<&Self, VirshleError> {
+        debug!("Creating an init disk for vm {}", self.uuid);
+
+        // Make disk
+        let init_disk = InitDisk { vm: &*self };
+        let init_data = InitData {
+            vm_data: Some(VmData::try_from(&*self)?),
+            user_data,
+        };
+        init_disk
+            .create()?
+            .mount()?
+            .write_init_files(&init_data)?
+            .umount()?;
+
+        // Add to vm config
+        let disk = Disk::from(&init_disk);
+        self.disk.push(disk);
+
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{RunCommands, SshParams, User, WriteFile};
+    use crate::network::utils::uuid_to_net_mac;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Deterministic init data to render golden files from.
+    fn testing_init_data() -> InitData {
+        let uuid = Uuid::parse_str("1dd70ae9-cd1c-41ab-b3c4-9c1839bb12ce").unwrap();
+        InitData {
+            vm_data: Some(VmData {
+                uuid,
+                net: vec![VmNetData {
+                    name: "main".to_owned(),
+                    mac: uuid_to_net_mac(&uuid, "main").to_string(),
+                }],
+                duid: uuid_to_duid(&uuid),
+                hostname: "izuku_midoryia".to_owned(),
+            }),
+            user_data: Some(UserData {
+                user: vec![User {
+                    name: "anon".to_owned(),
+                    ssh: Some(SshParams {
+                        authorized_keys: vec!["ssh-ed25519 AAAAD3N".to_owned()],
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+        }
+    }
+    /// Init data with every user-data field set.
+    fn testing_full_init_data() -> InitData {
+        let mut init_data = testing_init_data();
+        let vm_data = init_data.vm_data.as_mut().unwrap();
+        vm_data.net.push(VmNetData {
+            name: "storage".to_owned(),
+            mac: uuid_to_net_mac(&vm_data.uuid, "storage").to_string(),
+        });
+        let user_data = init_data.user_data.as_mut().unwrap();
+        let anon = &mut user_data.user[0];
+        anon.groups = vec!["docker".to_owned(), "kvm".to_owned()];
+        anon.shell = Some("/bin/bash".to_owned());
+        anon.sudo = Some(true);
+        anon.hashed_password = Some("$6$rounds=4096$salt$hash".to_owned());
+        user_data.user.push(User {
+            name: "guest".to_owned(),
+            sudo: Some(false),
+            ..Default::default()
+        });
+        user_data.write_files = vec![
+            WriteFile {
+                path: "/etc/motd".to_owned(),
+                mode: Some("0644".to_owned()),
+                owner: Some("root:root".to_owned()),
+                content: Some("Plus Ultra!\n".to_owned()),
+                ..Default::default()
+            },
+            WriteFile {
+                path: "/opt/blob.bin".to_owned(),
+                base64: Some("AAEC".to_owned()),
+                ..Default::default()
+            },
+        ];
+        user_data.run_commands = Some(RunCommands {
+            pre_net: vec!["echo 'pre' > /tmp/pre".to_owned()],
+            post_net: vec!["curl -fsS https://example.com".to_owned()],
+        });
+        user_data.timezone = Some("Europe/Paris".to_owned());
+        user_data.locale = Some("en_US.UTF-8".to_owned());
+        init_data
+    }
+    /// Compare a rendered file with its golden file.
+    /// Run with `VIRSHLE_BLESS=1` to update golden files.
+    fn assert_golden(name: &str, content: &str) -> Result<()> {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("src/hypervisor/vm/init/golden");
+        path.push(name);
+        if std::env::var("VIRSHLE_BLESS").is_ok() {
+            fs::write(&path, content).into_diagnostic()?;
+        }
+        let golden = fs::read_to_string(&path).into_diagnostic()?;
+        assert_eq!(content, golden);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipelight_config_render() -> Result<()> {
+        let uuid = &Uuid::new_v4();
+        let init_data = InitData {
+            vm_data: Some(VmData {
+                uuid: uuid.to_owned(),
+                net: vec![VmNetData {
+                    name: "main".to_owned(),
+                    mac: uuid_to_net_mac(&uuid, "main").to_string(),
+                }],
+                duid: uuid_to_duid(&uuid),
+                hostname: "izuku_midoryia".to_owned(),
+            }),
+            user_data: Some(UserData {
+                user: vec![User {
+                    name: "anon".to_owned(),
+                    ssh: Some(SshParams {
+                        authorized_keys: vec!["ssh-ed25519 AAAAD3N".to_owned()],
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+        };
+        let res = init_data.to_pipelight_toml_config()?;
+        println!("{}", res);
+        Ok(())
+    }
+    #[test]
+    fn test_pipelight_golden() -> Result<()> {
+        let files = testing_init_data().to_files(&InitBackend::Pipelight)?;
+        for (name, content) in files {
+            assert_golden(&format!("pipelight/{name}"), &content)?;
+        }
+        Ok(())
+    }
+    #[test]
+    fn test_cloud_init_golden() -> Result<()> {
+        let files = testing_init_data().to_files(&InitBackend::CloudInit)?;
+        for (name, content) in files {
+            assert_golden(&format!("cloud-init/{name}"), &content)?;
+        }
+        Ok(())
+    }
+    #[test]
+    fn test_pipelight_full_golden() -> Result<()> {
+        let files = testing_full_init_data().to_files(&InitBackend::Pipelight)?;
+        for (name, content) in files {
+            assert_golden(&format!("pipelight-full/{name}"), &content)?;
+        }
+        Ok(())
+    }
+    #[test]
+    fn test_cloud_init_full_golden() -> Result<()> {
+        let files = testing_full_init_data().to_files(&InitBackend::CloudInit)?;
+        for (name, content) in files {
+            assert_golden(&format!("cloud-init-full/{name}"), &content)?;
+        }
+        Ok(())
+    }
+    // #[tokio::test]
+    async fn test_init_disk_creation() -> Result<()> {
+        // let vm = Vm::default();
+        let vms = Vm::database().await?.many().get().await?;
+        let mut vm = vms.first().unwrap().to_owned();
+        // println!("{:#?}", &vm);
+
+        vm.add_init_disk(None)?;
+        Ok(())
+    }
+}
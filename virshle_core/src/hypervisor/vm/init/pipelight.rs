@@ -0,0 +1,222 @@
+use super::InitData;
+use crate::config::{User, UserData};
+use std::path::Path;
+use unindent::unindent;
+
+// Error handling
+use miette::Result;
+use virshle_error::VirshleError;
+
+/// Render a pipeline step that keeps going on failure.
+/// Commands are quoted as toml strings.
+fn to_step(name: &str, commands: &[String]) -> Result<String, VirshleError> {
+    let mut step = "[[pipelines.steps]]\n".to_owned();
+    step += &format!("name = {}\n", serde_json::to_string(name)?);
+    step += "options.mode = \"jump_next\"\n";
+    step += "commands = [\n";
+    for command in commands {
+        step += &format!("    {},\n", serde_json::to_string(command)?);
+    }
+    step += "]\n";
+    Ok(step)
+}
+/// Quote a string as a single shell word.
+fn shell_quote(string: &str) -> String {
+    format!("'{}'", string.replace('\'', r"'\''"))
+}
+
+impl InitData {
+    /// Convert user-data into a pipelight configuration file.
+    pub fn to_pipelight_toml_config(&self) -> Result<String, VirshleError> {
+        // Run before network is up
+        let mut p_config = "".to_owned();
+
+        p_config += &unindent(&format!(
+            r#"
+        [[pipelines]]
+        name = "init_net_pre"
+        "#
+        ))
+        .to_owned();
+
+        if let Some(vm_data) = &self.vm_data {
+            // Add hostname
+            let hostname = vm_data.hostname.clone();
+            p_config += &unindent(&format!(
+                r#"
+            [[pipelines.steps]]
+            name = "set hostname"
+            commands = [
+                "sysctl -w kernel.hostname='{hostname}'",
+            ]
+            "#
+            ));
+            // Set dhcp persistent identifier (duid-uuid).
+            // So the Vm can obtain the same ip from dhcp accross hardware (disk) changes.
+            let duid = vm_data.duid.clone();
+            p_config += &unindent(&format!(
+                r#"
+            [[pipelines.steps]]
+            name = "set dhcp persistent identifier"
+            commands = [
+                "mkdir -p /var/lib/dhcpcd",
+                "touch /var/lib/dhcpcd/duid",
+                "echo \"{duid}\n\" > /var/lib/dhcpcd/duid",
+                "chown -R dhcpcd:dhcpcd /var/lib/dhcpcd"
+            ]
+            "#,
+            ));
+        }
+        if let Some(user_data) = &self.user_data {
+            p_config += &Self::to_pipelight_system_steps(user_data)?;
+            for user in &user_data.user {
+                let username = user.name.clone();
+                p_config += &unindent(&format!(
+                    r#"
+                [[pipelines.steps]]
+                name = "ensure user {username} exists"
+                options.mode = "jump_next"
+                commands = [
+                    "useradd -m {username} -G wheel"
+                ]
+                "#
+                ));
+                if let Some(ssh) = &user.ssh {
+                    let keys = ssh.authorized_keys.to_owned();
+                    let mut commands = vec![];
+
+                    // Add keys
+                    for key in keys {
+                        commands.push(unindent(&format!(
+                            r#""echo \"{key}\n\" >> /etc/ssh/authorized_keys.d/{username}","#
+                        )));
+                    }
+                    let commands = commands.join(",\n");
+
+                    p_config += &unindent(&format!(
+                        r#"
+                    [[pipelines.steps]]
+                    name = "set authorized ssh keys for user {username}"
+                    options.mode = "jump_next"
+                    commands = [
+                        "mkdir -p /etc/ssh/authorized_keys.d",
+                        "touch /etc/ssh/authorized_keys.d/{username}",
+                        {commands}
+                    ]
+                    "#
+                    ));
+                }
+                p_config += &Self::to_pipelight_user_steps(user)?;
+            }
+            p_config += &Self::to_pipelight_files_steps(user_data)?;
+            if let Some(run_commands) = &user_data.run_commands {
+                if !run_commands.pre_net.is_empty() {
+                    p_config += &to_step("run pre-network commands", &run_commands.pre_net)?;
+                }
+            }
+        }
+        // Run after network is up
+        p_config += &unindent(&format!(
+            r#"
+        [[pipelines]]
+        name = "init_net_post"
+        "#
+        ));
+        p_config += &unindent(&format!(
+            r#"
+            [[pipelines.steps]]
+            name = "set main network interface kernel configuration"
+            commands = [
+                "sysctl -w net.ipv6.conf.ens4.accept_ra=1"
+            ]
+            "#
+        ));
+        if let Some(user_data) = &self.user_data {
+            if let Some(run_commands) = &user_data.run_commands {
+                if !run_commands.post_net.is_empty() {
+                    p_config += &to_step("run post-network commands", &run_commands.post_net)?;
+                }
+            }
+        }
+
+        Ok(p_config)
+    }
+
+    /// Set timezone and locale.
+    fn to_pipelight_system_steps(user_data: &UserData) -> Result<String, VirshleError> {
+        let mut steps = "".to_owned();
+        if let Some(timezone) = &user_data.timezone {
+            let commands = vec![format!(
+                "timedatectl set-timezone {}",
+                shell_quote(timezone)
+            )];
+            steps += &to_step("set timezone", &commands)?;
+        }
+        if let Some(locale) = &user_data.locale {
+            let commands = vec![format!(
+                "localectl set-locale {}",
+                shell_quote(&format!("LANG={locale}"))
+            )];
+            steps += &to_step("set locale", &commands)?;
+        }
+        Ok(steps)
+    }
+
+    /// Set user groups, shell, password and sudo rights.
+    fn to_pipelight_user_steps(user: &User) -> Result<String, VirshleError> {
+        let username = &user.name;
+        let mut commands = vec![];
+        if !user.groups.is_empty() {
+            for group in &user.groups {
+                commands.push(format!("groupadd -f {group}"));
+            }
+            commands.push(format!("usermod -aG {} {username}", user.groups.join(",")));
+        }
+        if let Some(shell) = &user.shell {
+            commands.push(format!("usermod -s {} {username}", shell_quote(shell)));
+        }
+        if let Some(hash) = &user.hashed_password {
+            commands.push(format!("usermod -p {} {username}", shell_quote(hash)));
+        }
+        match user.sudo {
+            Some(true) => {
+                let rule = shell_quote(&format!("{username} ALL=(ALL) NOPASSWD:ALL"));
+                commands.push("mkdir -p /etc/sudoers.d".to_owned());
+                commands.push(format!("echo {rule} > /etc/sudoers.d/{username}"));
+                commands.push(format!("chmod 440 /etc/sudoers.d/{username}"));
+            }
+            Some(false) => {
+                commands.push(format!("rm -f /etc/sudoers.d/{username}"));
+                commands.push(format!("gpasswd -d {username} wheel"));
+            }
+            None => {}
+        }
+        if commands.is_empty() {
+            return Ok("".to_owned());
+        }
+        to_step(&format!("configure user {username}"), &commands)
+    }
+
+    /// Write files, content is passed base64 encoded so it is left untouched.
+    fn to_pipelight_files_steps(user_data: &UserData) -> Result<String, VirshleError> {
+        let mut commands = vec![];
+        for file in &user_data.write_files {
+            let path = shell_quote(&file.path);
+            if let Some(parent) = Path::new(&file.path).parent() {
+                let parent = shell_quote(&parent.to_string_lossy());
+                commands.push(format!("mkdir -p {parent}"));
+            }
+            commands.push(format!("echo {} | base64 -d > {path}", file.to_base64()));
+            if let Some(mode) = &file.mode {
+                commands.push(format!("chmod {mode} {path}"));
+            }
+            if let Some(owner) = &file.owner {
+                commands.push(format!("chown {owner} {path}"));
+            }
+        }
+        if commands.is_empty() {
+            return Ok("".to_owned());
+        }
+        to_step("write files", &commands)
+    }
+}
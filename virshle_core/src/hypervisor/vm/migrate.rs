@@ -0,0 +1,301 @@
+use super::{Disk, Vm};
+use crate::config::{Account, UserData};
+use crate::hypervisor::disk::InitDisk;
+use crate::hypervisor::DiskInfo;
+use crate::VmState;
+
+// Filesystem
+use std::fs;
+use std::path::Path;
+
+// Network
+use pipelight_exec::Process;
+use std::net::{SocketAddr, TcpListener};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+// Error Handling
+use miette::Result;
+use tracing::{error, info, warn};
+use virshle_error::{LibError, VirshleError};
+
+/// Seconds to wait for the destination vmm to listen for the incoming vm.
+pub const RECEIVER_TIMEOUT: u64 = 10;
+/// Free ports to try before giving up on listening for the incoming vm.
+pub const RECEIVER_RETRIES: u32 = 3;
+
+/*
+* Live migration between two nodes.
+*
+* Cloud-hypervisor only streams the vm memory and device state.
+* Vm disks are expected to be reachable on both nodes at the same path
+* (shared storage), as the vm uuid (and so its directory) is kept untouched.
+*/
+impl Vm {
+    /// Prepare this node to receive a vm from another node.
+    /// - persist the vm record,
+    /// - create init disk and network ports,
+    /// - start a fresh ch process listening for the migration.
+    ///
+    /// The ch process only listens on `host` (the address the sending node reaches
+    /// this node at), on `port` or a free ephemeral port.
+    ///
+    /// `source_disks` are the disks as seen from the sending node,
+    /// to make sure both nodes use the same files.
+    ///
+    /// Returns the receiver url as soon as the ch process waits for the incoming vm,
+    /// the migration itself is then driven by the sending node.
+    #[tracing::instrument(skip_all)]
+    pub async fn receive_migration(
+        &mut self,
+        account_uuid: Option<uuid::Uuid>,
+        host: &str,
+        port: Option<u16>,
+        source_disks: Option<Vec<DiskInfo>>,
+    ) -> Result<String, VirshleError> {
+        // Safeguard: vm must not already live on this node.
+        if Vm::database()
+            .await?
+            .one()
+            .uuid(self.uuid)
+            .get()
+            .await
+            .is_ok()
+        {
+            let message = format!("Vm {:#?} already exists on this node.", self.name);
+            let help = "Delete it before migrating it here.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        // Safeguard: disks must be reachable, and shared with the sending node.
+        for disk in &self.disk {
+            if !Path::new(&disk.path).exists() {
+                let message = format!("Couldn't find disk {:#?} on this node.", disk.path);
+                let help =
+                    "Vm disks must be reachable at the same path on both nodes (shared storage).";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+            // A file at the same path may still be another disk.
+            let source = source_disks.iter().flatten().find(|e| e.path == disk.path);
+            if let Some(size) = source.and_then(|e| e.size) {
+                if disk.get_size().ok() != Some(size) {
+                    let message =
+                        format!("Disk {:#?} differs from the sending node one.", disk.path);
+                    let help = "Vm disks must be the same files on both nodes (shared storage).";
+                    return Err(LibError::builder().msg(&message).help(help).build().into());
+                }
+            }
+        }
+        let mut address = Self::get_receiver_address(host, port).await?;
+
+        // Persist vm config into database, with the same uuid.
+        self.id = None;
+        let user_data = account_uuid.map(|uuid| UserData {
+            account: Some(Account { id: None, uuid }),
+            ..Default::default()
+        });
+        self.db().await?.create(user_data.clone()).await?;
+
+        // Ensure vm storage directories exists on host.
+        for directory in [self.get_disks_dir()?, format!("{}/net", self.get_dir()?)] {
+            fs::create_dir_all(&directory)?;
+        }
+        // Create initial resources.
+        // The init disk is only read on boot, so an existing one is kept as is.
+        let init_disk: Disk = (&InitDisk { vm: &*self }).into();
+        let init_disk_exists = Path::new(&init_disk.path).exists();
+        self.create_init_resources()
+            .init_disk(!init_disk_exists)
+            .maybe_user_data(user_data)
+            .net(true)
+            .exec()
+            .await?;
+
+        // Block restores until the vm is received.
+        fs::write(self.get_migration_marker()?, "")?;
+
+        // Start the ch process
+        self.vmm().start().exec().await?;
+
+        // The free port is only taken once the vmm listens on it,
+        // so pick another one if it was taken in between.
+        let mut attempts = 0;
+        let task = loop {
+            attempts += 1;
+            let task = self.spawn_receiver(&address);
+            let err = match self.wait_for_receiver(&address, &task).await {
+                Ok(()) => break task,
+                Err(e) => e,
+            };
+            // A receiver that failed that early couldn't bind the port.
+            let err = match task.is_finished() {
+                true => match task.await {
+                    Ok(Err(e)) if port.is_none() && attempts < RECEIVER_RETRIES => {
+                        if let Ok(v) = Self::get_receiver_address(host, port).await {
+                            warn!("couldn't listen on {}, retrying on {}: {}", address, v, e);
+                            address = v;
+                            continue;
+                        }
+                        e
+                    }
+                    Ok(Err(e)) => e,
+                    _ => err,
+                },
+                false => {
+                    task.abort();
+                    err
+                }
+            };
+            self.clean_migration().await.ok();
+            return Err(err);
+        };
+        let receiver_url = format!("tcp:{address}");
+        tokio::spawn({
+            let mut vm = self.clone();
+            async move {
+                let res = task.await.map_err(VirshleError::from).and_then(|e| e);
+                match res {
+                    Ok(_) => {
+                        if let Ok(path) = vm.get_migration_marker() {
+                            fs::remove_file(path).ok();
+                        }
+                        vm.set_vsock_permissions().await.ok();
+                        if let Ok(db) = vm.db().await {
+                            db.set_running(true).await.ok();
+                        }
+                        info!("received vm {}", vm.name);
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        vm.clean_migration().await.ok();
+                    }
+                }
+            }
+        });
+
+        info!("waiting for vm {} on {}", self.name, address);
+        Ok(receiver_url)
+    }
+
+    /// Return a socket address to listen on for an incoming vm,
+    /// on a local address `host` resolves to.
+    /// The port is only probed, the vmm may lose it before binding it.
+    async fn get_receiver_address(
+        host: &str,
+        port: Option<u16>,
+    ) -> Result<SocketAddr, VirshleError> {
+        let addresses = tokio::net::lookup_host((host, port.unwrap_or(0))).await?;
+        for address in addresses {
+            // Binding fails on foreign addresses and ports in use.
+            // An unset port is replaced by a free ephemeral one.
+            if let Ok(listener) = TcpListener::bind(address) {
+                return Ok(listener.local_addr()?);
+            }
+        }
+        let message = format!("Couldn't listen for an incoming vm on {:#?}.", host);
+        let help = "The peer url must point to an address of this node, with a free port.";
+        Err(LibError::builder().msg(&message).help(help).build().into())
+    }
+
+    /// Make the ch process listen for the incoming vm on address.
+    /// The returned task ends once the migration is over.
+    fn spawn_receiver(&self, address: &SocketAddr) -> JoinHandle<Result<(), VirshleError>> {
+        let vm = self.clone();
+        let receiver_url = format!("tcp:{address}");
+        tokio::spawn(async move {
+            let vmm = vm.vmm();
+            let mut api = vmm.api()?;
+            api.receive_migration(&receiver_url).await
+        })
+    }
+
+    /// Wait for the ch process to listen on the receiver address.
+    async fn wait_for_receiver(
+        &self,
+        address: &SocketAddr,
+        task: &JoinHandle<Result<(), VirshleError>>,
+    ) -> Result<(), VirshleError> {
+        // Probing with a tcp connection would consume the migration socket.
+        let port = address.port();
+        let cmd = format!("ss --no-header --listening --tcp --numeric sport = :{port}");
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(RECEIVER_TIMEOUT) && !task.is_finished() {
+            let mut proc = Process::new();
+            let res = proc.stdin(&cmd).run()?;
+            if res.io.stdout.is_some_and(|e| !e.trim().is_empty()) {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let message = format!("Vm {:#?} couldn't wait for the migration.", self.name);
+        let help = format!("The vmm didn't listen on {} in time.", address);
+        Err(LibError::builder().msg(&message).help(&help).build().into())
+    }
+
+    /// Remove a vm that awaits a migration which won't come.
+    #[tracing::instrument(skip_all)]
+    pub async fn cancel_migration(&mut self) -> Result<Self, VirshleError> {
+        // Safeguard: never remove a vm that was already received.
+        match self.vmm().api()?.state().await? {
+            VmState::NotCreated => {}
+            state => {
+                let message = format!("Couldn't cancel vm {:#?} migration.", self.name);
+                let help = format!("Vm was already received and is {:?}.", state);
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        };
+        self.clean_migration().await?;
+        info!("canceled vm {} migration", self.name);
+        Ok(self.to_owned())
+    }
+
+    /// Stream the vm to a node that awaits it,
+    /// and remove it from this node.
+    ///
+    /// Disk files are left in place as they are now used by the destination.
+    #[tracing::instrument(skip_all)]
+    pub async fn send_migration(&mut self, destination_url: &str) -> Result<Self, VirshleError> {
+        match self.vmm().api()?.state().await? {
+            VmState::Running | VmState::Paused => {}
+            state => {
+                let message = format!("Couldn't migrate vm {:#?}.", self.name);
+                let help = format!("Vm must be running or paused, but is {:?}.", state);
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+        };
+        if self.crypt().has_encrypted_disks() {
+            let message = format!("Couldn't migrate vm {:#?}.", self.name);
+            let help = "Encrypted disks keys are bound to the node that created the vm.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        // Block restores while the vm is streamed.
+        let marker = self.get_migration_marker()?;
+        fs::write(&marker, "")?;
+        let res = match self.vmm().api() {
+            Ok(mut api) => api.send_migration(destination_url).await,
+            Err(e) => Err(e),
+        };
+        fs::remove_file(&marker).ok();
+        res?;
+
+        // The guest now renews its leases from the destination dhcp.
+        self.networks().leases().delete_all().await.ok();
+        self.clean_migration().await?;
+        info!("migrated vm {} to {}", self.name, destination_url);
+        Ok(self.to_owned())
+    }
+
+    /// Remove every node local artifact of a vm,
+    /// but its disks (init disk included).
+    async fn clean_migration(&mut self) -> Result<(), VirshleError> {
+        // Remove process and artifacts.
+        self.vmm().kill_process()?;
+        // Remove vm networks and their firewall flows
+        self.networks().delete_all()?;
+        // Remove snapshot records
+        self.snapshots().delete_all().await.ok();
+        fs::remove_file(self.get_migration_marker()?).ok();
+        // Finally Remove db record
+        self.db().await?.delete().await?;
+        Ok(())
+    }
+}
@@ -4,6 +4,8 @@ pub mod getters;
 pub mod init;
 
 // High level methods to orchestrate VMs.
+pub mod archive;
+pub mod autostart;
 pub mod crud;
 
 // Methods
@@ -12,13 +14,19 @@ pub mod crud;
 // - database operations.
 // - host network manipulation.
 pub mod database;
+pub mod firewall;
+pub mod migrate;
 pub mod networks;
+pub mod snapshot;
 
 // Reexports
 pub use crate::config::{DiskTemplate, UserData, VmNet};
+pub use archive::VmArchive;
 pub use display::VmTable;
 pub use getters::VmInfo;
-pub use init::{InitData, VmData};
+pub use init::{InitBackend, InitData, VmData, VmNetData};
+pub use crud::{ShutdownMode, SHUTDOWN_TIMEOUT};
+pub use snapshot::Snapshot;
 
 // Time
 use chrono::{NaiveDateTime, Utc};
@@ -48,7 +56,9 @@ pub struct VmExtra {
     /// A field with Json data,
     /// containing the decentralized account the VM is linked to.
     pub inner: Option<String>,
-    // Unused
+    /// Boot the VM back when the node starts,
+    /// if it was running before the node went down.
+    #[serde(default)]
     pub autostart: bool,
 }
 
@@ -80,7 +90,14 @@ pub struct Vm {
     pub name: String,
     pub vcpu: u64,
     pub vram: String,
+    /// Hotplug headroom: vcpu number the vm can be resized up to.
+    pub max_vcpu: Option<u64>,
+    /// Hotplug headroom: ram size the vm can be resized up to.
+    pub max_vram: Option<String>,
     pub net: Option<Vec<VmNet>>,
+    /// Security groups applied to every vm network,
+    /// along with the vm account ones.
+    pub security_groups: Option<Vec<String>>,
     pub uuid: Uuid,
     pub disk: Vec<Disk>,
 
@@ -93,6 +110,11 @@ pub struct Vm {
     // Very optional vm parameters.
     /// Room for additional parameters (unused for now).
     pub extra: Option<VmExtra>,
+    /// User data the vm was created with,
+    /// reused to provision its clones.
+    pub user_data: Option<UserData>,
+    /// Provisioning backend, defaults to pipelight-init.
+    pub init: Option<InitBackend>,
 
     /// Catch extra undefined fields
     #[serde(flatten)]
@@ -109,7 +131,10 @@ impl Default for Vm {
             vcpu: 1,
             // vram in Gib
             vram: "1GiB".to_owned(),
+            max_vcpu: None,
+            max_vram: None,
             net: None,
+            security_groups: None,
             uuid: Uuid::new_v4(),
             disk: vec![],
 
@@ -118,6 +143,8 @@ impl Default for Vm {
             updated_at: now,
 
             extra: None,
+            user_data: None,
+            init: None,
 
             other: serde_json::Value::Null,
         }
@@ -177,7 +204,7 @@ mod test {
     // #[tokio::test]
     async fn delete_vm() -> Result<()> {
         let item = Vm::default();
-        item.shutdown().await?;
+        item.shutdown().exec().await?;
         Ok(())
     }
 }
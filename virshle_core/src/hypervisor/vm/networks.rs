@@ -1,14 +1,17 @@
-use crate::config::{Config, DhcpType, NetType, VmNet};
+use crate::config::{Config, DhcpType, NetType, SwitchType, VmNet};
 use crate::hypervisor::{Vm, VmTable};
 use crate::network::{
     dhcp::{FakeDhcp, KeaDhcp, Lease},
     ip,
-    ovs::{OvsBridge, OvsPort},
+    ovs::{self, OvsBridge, OvsPort},
+    utils,
 };
 
+use macaddr::MacAddr6;
 use std::fs;
 use std::net::IpAddr;
 use std::path::Path;
+use std::str::FromStr;
 
 // Error Handling
 use miette::Result;
@@ -34,15 +37,75 @@ impl VmNetMethods<'_> {
         }
         Ok(ips)
     }
+    /// Return a network interface mac address:
+    /// the one set in template, or one derived from vm uuid
+    /// (and network name for other interfaces than the first).
+    pub fn get_mac(&self, net: &VmNet) -> Result<MacAddr6, VirshleError> {
+        match net.get_mac() {
+            Some(mac) => MacAddr6::from_str(&mac).map_err(|e| {
+                let message = format!("Couldn't parse mac address of network {:#?}.", net.name);
+                LibError::builder()
+                    .msg(&message)
+                    .help(&e.to_string())
+                    .build()
+                    .into()
+            }),
+            None => {
+                // The first interface keeps the mac address vms always had,
+                // so dhcp reservations and guest configurations survive upgrades.
+                let first = self.vm.net.iter().flatten().next();
+                match first.map(|e| &e.name) == Some(&net.name) {
+                    true => Ok(utils::uuid_to_mac(&self.vm.uuid)),
+                    false => Ok(utils::uuid_to_net_mac(&self.vm.uuid, &net.name)),
+                }
+            }
+        }
+    }
+    /// Return every network interface name and mac address.
+    /// Error out if two interfaces share the same mac address.
+    pub fn get_macs(&self) -> Result<Vec<(String, MacAddr6)>, VirshleError> {
+        let mut macs: Vec<(String, MacAddr6)> = vec![];
+        for net in self.vm.net.iter().flatten() {
+            let mac = self.get_mac(net)?;
+            if let Some((name, _)) = macs.iter().find(|(_, e)| *e == mac) {
+                let message = format!("Vm {:#?} networks share a mac address.", self.vm.name);
+                let help = format!(
+                    "Networks {:#?} and {:#?} both use {}, set distinct mac addresses in template.",
+                    name, net.name, mac
+                );
+                return Err(LibError::builder().msg(&message).help(&help).build().into());
+            }
+            macs.push((net.name.to_owned(), mac));
+        }
+        Ok(macs)
+    }
+    /// Return the ovs switch a network interface is plugged into.
+    pub fn get_switch(&self, net: &VmNet) -> Result<OvsBridge, VirshleError> {
+        let switch = Config::get()?.network.get_switch(net.switch.as_deref())?;
+        // Safeguard: vhost needs a userspace switch, tap a kernel one.
+        let expected = match net._type {
+            NetType::Vhost(_) => SwitchType::Netdev,
+            NetType::Tap(_) | NetType::MacVTap(_) => SwitchType::System,
+        };
+        if switch._type != expected {
+            let message = format!(
+                "Network {:#?} of type {} can't be plugged into switch {:#?}.",
+                net.name, net._type, switch.name
+            );
+            let help = format!("Plug it into a switch of type {:?}.", expected);
+            return Err(LibError::builder().msg(&message).help(&help).build().into());
+        }
+        OvsBridge::get_switch(&switch.name)
+    }
     /// Create all networks associated to Vm on host (and ovs configuration).
     #[tracing::instrument(skip_all)]
-    pub fn ensure_all(&self) -> Result<(), VirshleError> {
+    pub async fn ensure_all(&self) -> Result<(), VirshleError> {
         trace!("creating networks for vm {:#?}", self.vm.name);
         if let Some(networks) = &self.vm.net {
             for net in networks {
                 // Clean up
                 self._delete(&net)?;
-                self._create(&net)?;
+                self._create(&net).await?;
             }
         }
         Ok(())
@@ -50,13 +113,13 @@ impl VmNetMethods<'_> {
 
     /// Create network <name> on host (and ovs configuration).
     #[tracing::instrument(skip_all)]
-    pub fn create_one(&self, name: &str) -> Result<(), VirshleError> {
+    pub async fn create_one(&self, name: &str) -> Result<(), VirshleError> {
         if let Some(e) = self.vm.net.clone() {
             let nets: Vec<VmNet> = e.into_iter().filter(|e| e.name == name).collect();
             let net = nets.first();
             match net {
                 Some(v) => {
-                    self._create(v)?;
+                    self._create(v).await?;
                 }
                 None => {}
             };
@@ -65,17 +128,17 @@ impl VmNetMethods<'_> {
     }
     /// Create all networks associated to Vm on host (and ovs configuration).
     #[tracing::instrument(skip_all)]
-    pub fn create_all(&self) -> Result<(), VirshleError> {
+    pub async fn create_all(&self) -> Result<(), VirshleError> {
         trace!("creating networks for vm {:#?}", self.vm.name);
         if let Some(networks) = &self.vm.net {
             for net in networks {
-                self._create(&net)?;
+                self._create(&net).await?;
             }
         }
         Ok(())
     }
-    /// Create a network on host (and ovs configuration).
-    fn _create(&self, net: &VmNet) -> Result<(), VirshleError> {
+    /// Create a network on host (and ovs configuration and firewall).
+    async fn _create(&self, net: &VmNet) -> Result<(), VirshleError> {
         // This results in "machin_name-network_name".
         let port_name = format!("vm-{}--{}", self.vm.name, net.name);
         match &net._type {
@@ -83,17 +146,25 @@ impl VmNetMethods<'_> {
             // the bridge must be of type "netdev".
             NetType::Vhost(v) => {
                 let socket_path = self.vm.get_net_socket(&net)?;
-                OvsBridge::get_vm_switch()?.create_dpdk_port(&port_name, &socket_path)?;
+                self.get_switch(net)?.create_dpdk_port(
+                    &port_name,
+                    &socket_path,
+                    net.vlan,
+                    net.trunks.clone(),
+                    net.bandwidth.clone(),
+                )?;
+                self.firewall().create(net).await?;
             }
             // Tap do not work on ovs-bridge of type "netdev",
             // the bridge must be of type "system".
             NetType::Tap(v) => {
                 // Create tap device
                 ip::tap::create(&port_name)?;
+                ip::tap::set_mac(&port_name, &self.get_mac(net)?)?;
                 ip::up(&port_name)?;
 
                 // Link to ovs bridge
-                let vmbr = OvsBridge::get_vm_switch()?;
+                let vmbr = self.get_switch(net)?;
                 // Silently try to delete old port if any.
                 match OvsPort::get_by_name(&port_name) {
                     Ok(v) => {
@@ -101,18 +172,52 @@ impl VmNetMethods<'_> {
                     }
                     Err(_) => {}
                 };
-                vmbr.create_tap_port(&port_name)?;
+                vmbr.create_tap_port(
+                    &port_name,
+                    net.vlan,
+                    net.trunks.clone(),
+                    net.bandwidth.clone(),
+                )?;
+                self.firewall().create(net).await?;
             }
             // MacVTap do not work on ovs-bridge of type "netdev",
             // the bridge must be of type "system".
             NetType::MacVTap(v) => {
                 // Create macvtap device
-                ip::macvtap::create(&port_name)?;
+                ip::macvtap::create(&port_name, &self.get_switch(net)?)?;
+                ip::tap::set_mac(&port_name, &self.get_mac(net)?)?;
                 ip::up(&port_name)?;
             }
         };
         Ok(())
     }
+    /// Apply a network settings (vlans, rate limits) to its existing ovs port.
+    pub fn update_port(&self, net: &VmNet) -> Result<(), VirshleError> {
+        let port_name = format!("vm-{}--{}", self.vm.name, net.name);
+        match &net._type {
+            NetType::Vhost(_) => {
+                let socket_path = self.vm.get_net_socket(&net)?;
+                self.get_switch(net)?.create_dpdk_port(
+                    &port_name,
+                    &socket_path,
+                    net.vlan,
+                    net.trunks.clone(),
+                    net.bandwidth.clone(),
+                )?;
+            }
+            NetType::Tap(_) => {
+                self.get_switch(net)?.create_tap_port(
+                    &port_name,
+                    net.vlan,
+                    net.trunks.clone(),
+                    net.bandwidth.clone(),
+                )?;
+            }
+            // Not plugged into ovs.
+            NetType::MacVTap(_) => {}
+        };
+        Ok(())
+    }
     /// Remove network <name> from host (and ovs configuration).
     pub fn delete_one(&self, name: &str) -> Result<(), VirshleError> {
         if let Some(e) = self.vm.net.clone() {
@@ -136,14 +241,20 @@ impl VmNetMethods<'_> {
         }
         Ok(())
     }
-    /// Remove a network from host (and ovs configuration).
+    /// Remove a network from host (and ovs configuration and firewall).
     /// WARNING: Silently fail (due to ".ok()").
     fn _delete(&self, net: &VmNet) -> Result<(), VirshleError> {
         // This results in "machin_name-network_name".
         let port_name = format!("vm-{}--{}", self.vm.name, net.name);
 
+        self.firewall().delete(net)?;
+
         // Ovs: try to delete the port and silently fail.
-        if let Some(port) = OvsBridge::get_vm_switch()?.get_port(&port_name).ok() {
+        if let Some(port) = self
+            .get_switch(net)
+            .ok()
+            .and_then(|e| e.get_port(&port_name).ok())
+        {
             port.delete().ok();
         }
 
@@ -151,6 +262,7 @@ impl VmNetMethods<'_> {
             NetType::Tap(_) | NetType::MacVTap(_) => {
                 // Use the ip command to delete interfaces.
                 ip::tap::delete(&port_name).ok();
+                ovs::clear_qos(&utils::unix_name(&port_name)).ok();
             }
             NetType::Vhost(_) => {
                 // Delete existing socket if any because
@@ -160,6 +272,7 @@ impl VmNetMethods<'_> {
                 if path.exists() {
                     fs::remove_file(&socket_path).ok();
                 }
+                ovs::clear_qos(&port_name).ok();
             }
         };
         Ok(())
@@ -210,9 +323,42 @@ impl VmLeaseMethods<'_> {
     /// Return vm leases,
     /// or error out if nothing found
     pub async fn get_all(&self) -> Result<Vec<Lease>, VirshleError> {
+        let leases = self.list().await?;
+        if leases.is_empty() {
+            let message = format!("Couldn't find a lease for vm: {}", self.vm.name);
+            let help = "Are you sure the VM has already requested an address from kea-dhcp";
+            let err = LibError::builder().msg(&message).help(&help).build();
+            Err(err.into())
+        } else {
+            Ok(leases)
+        }
+    }
+    /// Return vm leases (kea or fake dhcp ones), if any,
+    /// or error out if the dhcp server couldn't be reached.
+    pub async fn list(&self) -> Result<Vec<Lease>, VirshleError> {
         let mut leases: Vec<Lease> = vec![];
         let config = Config::get()?;
         match config.dhcp {
+            Some(DhcpType::Fake(fake_dhcp_config)) => {
+                if let Some(id) = self.vm.id {
+                    for ip in FakeDhcp::get_leases(id.try_into().unwrap()).await? {
+                        // An address belongs to the network whose pool holds it.
+                        let net = self.vm.net.iter().flatten().find(|net| {
+                            fake_dhcp_config
+                                .pool
+                                .get(&net.name)
+                                .is_some_and(|pool| pool.get_subnet().contains(&ip))
+                        });
+                        if let Some(net) = net {
+                            leases.push(Lease {
+                                address: ip,
+                                hostname: self.vm.name.to_owned(),
+                                mac: self.vm.networks().get_mac(net)?,
+                            });
+                        }
+                    }
+                }
+            }
             Some(DhcpType::Kea(kea_dhcp_config)) => {
                 let mut cli = KeaDhcp::builder().config(kea_dhcp_config).build().await?;
                 leases = cli
@@ -227,14 +373,7 @@ impl VmLeaseMethods<'_> {
             }
             _ => {}
         };
-        if leases.is_empty() {
-            let message = format!("Couldn't find a lease for vm: {}", self.vm.name);
-            let help = "Are you sure the VM has already requested an address from kea-dhcp";
-            let err = LibError::builder().msg(&message).help(&help).build();
-            Err(err.into())
-        } else {
-            Ok(leases)
-        }
+        Ok(leases)
     }
 }
 
@@ -243,6 +382,30 @@ mod test {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn keep_first_network_mac() -> Result<()> {
+        let toml = r#"
+            name = "default_xs"
+            uuid = "c37b3266-9c59-42bb-8ecf-bdd643236a78"
+            vcpu = 1
+            vram = "1GiB"
+            disk = []
+
+            [[net]]
+            name = "main"
+            [net.type.tap]
+
+            [[net]]
+            name = "storage"
+            [net.type.tap]
+        "#;
+        let vm = Vm::from_toml(&toml)?;
+        let macs = vm.networks().get_macs()?;
+        // Mac address given to vms before they could have several networks.
+        assert_eq!(macs[0].1.to_string(), "CE:7B:32:66:9C:59");
+        assert_eq!(macs[1].1.to_string(), "DE:CB:BB:92:95:A5");
+        Ok(())
+    }
     #[tokio::test]
     async fn fetch_one_ips() -> Result<()> {
         let items = Vm::database().await?.many().get().await?;
@@ -0,0 +1,176 @@
+use crate::hypervisor::Vm;
+use crate::utils::display;
+
+// Time
+use chrono::{NaiveDateTime, Utc};
+
+// Serde
+use serde::{Deserialize, Serialize};
+use tabled::{
+    settings::{disable::Remove, location::ByColumnName, Style},
+    Table, Tabled,
+};
+
+//Database
+use crate::database;
+use crate::database::connect_db;
+use sea_orm::{prelude::*, query::*, ActiveValue, IntoActiveModel};
+
+// Error Handling
+use log::{log_enabled, Level};
+use miette::Result;
+use virshle_error::{LibError, VirshleError};
+
+/// A vm snapshot record.
+/// The snapshot itself (memory, device state and disk copies)
+/// lives on the filesystem at `path`.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Tabled)]
+pub struct Snapshot {
+    pub tag: String,
+    pub path: String,
+    #[tabled(display("display::display_datetime"))]
+    pub created_at: NaiveDateTime,
+}
+impl From<database::entity::snapshot::Model> for Snapshot {
+    fn from(e: database::entity::snapshot::Model) -> Self {
+        Self {
+            tag: e.tag,
+            path: e.path,
+            created_at: e.created_at,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Check a snapshot tag before it is used as a directory name:
+    /// only letters, digits, '.', '_' and '-' are allowed.
+    pub fn check_tag(tag: &str) -> Result<(), VirshleError> {
+        let allowed = |e: char| e.is_ascii_alphanumeric() || matches!(e, '.' | '_' | '-');
+        if tag.is_empty() || tag == "." || tag == ".." || !tag.chars().all(allowed) {
+            let message = format!("Invalid snapshot tag {:#?}.", tag);
+            let help = "Use only letters, digits, '.', '_' or '-'.";
+            return Err(LibError::builder().msg(&message).help(help).build().into());
+        }
+        Ok(())
+    }
+    pub fn display(items: &Vec<Self>) -> Result<(), VirshleError> {
+        let mut res = Table::new(items);
+        if !log_enabled!(Level::Info) {
+            res.with(Remove::column(ByColumnName::new("path")));
+        }
+        res.with(Style::rounded());
+        println!("{}", res);
+        Ok(())
+    }
+}
+
+impl Vm {
+    pub fn snapshots(&self) -> VmSnapshotMethods<'_> {
+        VmSnapshotMethods { vm: self }
+    }
+}
+pub struct VmSnapshotMethods<'a> {
+    pub vm: &'a Vm,
+}
+impl VmSnapshotMethods<'_> {
+    fn vm_id(&self) -> Result<i32, VirshleError> {
+        match self.vm.id {
+            Some(id) => Ok(id as i32),
+            None => {
+                let message = format!("Vm {:#?} has no database id.", self.vm.name);
+                let help = "Create the vm before taking snapshots.";
+                Err(LibError::builder().msg(&message).help(help).build().into())
+            }
+        }
+    }
+    /// Return path where to store snapshot with the given tag.
+    /// Usually at : `/var/lib/virshle/vm/{vm_uuid}/snapshots/{tag}`.
+    pub fn get_dir(&self, tag: &str) -> Result<String, VirshleError> {
+        Snapshot::check_tag(tag)?;
+        let path = format!("{}/{}", self.vm.get_snapshots_dir()?, tag);
+        Ok(path)
+    }
+    /// Return every snapshot of the vm, oldest first.
+    pub async fn get_all(&self) -> Result<Vec<Snapshot>, VirshleError> {
+        let db = connect_db().await?;
+        let records = database::prelude::Snapshot::find()
+            .filter(database::entity::snapshot::Column::VmId.eq(self.vm_id()?))
+            .order_by_asc(database::entity::snapshot::Column::CreatedAt)
+            .all(&db)
+            .await?;
+        Ok(records.into_iter().map(Snapshot::from).collect())
+    }
+    /// Return the snapshot with the given tag,
+    /// or error out if nothing found.
+    pub async fn get(&self, tag: &str) -> Result<Snapshot, VirshleError> {
+        let db = connect_db().await?;
+        let record = database::prelude::Snapshot::find()
+            .filter(database::entity::snapshot::Column::VmId.eq(self.vm_id()?))
+            .filter(database::entity::snapshot::Column::Tag.eq(tag))
+            .one(&db)
+            .await?;
+        match record {
+            Some(record) => Ok(record.into()),
+            None => {
+                let message = format!("Couldn't find snapshot {:#?} for vm {:#?}.", tag, self.vm.name);
+                let help = "List existing snapshots with `v vm snapshot --name <vm_name>`.";
+                Err(LibError::builder().msg(&message).help(help).build().into())
+            }
+        }
+    }
+    /// Persist snapshot record into database.
+    pub async fn create(&self, tag: &str) -> Result<Snapshot, VirshleError> {
+        Snapshot::check_tag(tag)?;
+        let db = connect_db().await?;
+        let now: NaiveDateTime = Utc::now().naive_utc();
+        let record = database::entity::snapshot::ActiveModel {
+            vm_id: ActiveValue::Set(self.vm_id()?),
+            tag: ActiveValue::Set(tag.to_owned()),
+            path: ActiveValue::Set(self.get_dir(tag)?),
+            created_at: ActiveValue::Set(now),
+            ..Default::default()
+        };
+        database::prelude::Snapshot::insert(record).exec(&db).await?;
+        self.get(tag).await
+    }
+    /// Remove snapshot record from database.
+    pub async fn delete(&self, tag: &str) -> Result<(), VirshleError> {
+        let db = connect_db().await?;
+        let record = database::prelude::Snapshot::find()
+            .filter(database::entity::snapshot::Column::VmId.eq(self.vm_id()?))
+            .filter(database::entity::snapshot::Column::Tag.eq(tag))
+            .one(&db)
+            .await?;
+        if let Some(record) = record {
+            database::prelude::Snapshot::delete(record.into_active_model())
+                .exec(&db)
+                .await?;
+        }
+        Ok(())
+    }
+    /// Remove every snapshot record of the vm from database.
+    pub async fn delete_all(&self) -> Result<(), VirshleError> {
+        let db = connect_db().await?;
+        database::prelude::Snapshot::delete_many()
+            .filter(database::entity::snapshot::Column::VmId.eq(self.vm_id()?))
+            .exec(&db)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_snapshot_tags() -> Result<()> {
+        for tag in ["before-upgrade", "v1.2_rc", "2024.01.01"] {
+            Snapshot::check_tag(tag)?;
+        }
+        for tag in ["", ".", "..", "../../x", "a/b", "tag with spaces"] {
+            assert!(Snapshot::check_tag(tag).is_err());
+        }
+        Ok(())
+    }
+}
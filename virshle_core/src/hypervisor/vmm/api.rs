@@ -1,18 +1,22 @@
 use super::VmmMethods;
 use crate::config::init::MANAGED_DIR;
 use crate::hypervisor::{
-    vmm::{NetConfig, VmConfig, VmInfoResponse, VmRemoveDeviceData, VmState},
-    Vm,
+    vmm::{
+        DiskConfig, NetConfig, RestoreConfig, VmConfig, VmInfoResponse, VmReceiveMigrationData,
+        VmRemoveDeviceData, VmResizeData, VmResizeDiskData, VmSendMigrationData, VmSnapshotConfig,
+        VmState,
+    },
+    Disk, Vm,
 };
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time;
 use tokio::time::timeout;
 // Http
 use hyper::StatusCode;
 use virshle_network::{
     connection::{Connection, ConnectionHandle, UnixConnection},
-    http::{Rest, RestClient},
+    http::{Response, Rest, RestClient},
 };
 
 // Error Handling
@@ -78,6 +82,19 @@ impl VmmMethods<'_> {
         }
         Ok(())
     }
+    /// Return the device id cloud-hypervisor assigned to a vm disk,
+    /// if the disk is attached to the running vm.
+    pub async fn get_disk_id(&self, disk: &Disk) -> Result<Option<String>, VirshleError> {
+        let response: VmInfoResponse = self.api()?.info().await?;
+        let id = response
+            .config
+            .disks
+            .unwrap_or_default()
+            .into_iter()
+            .find(|e| e.path == Some(PathBuf::from(self.vm.crypt().get_device_path(disk))))
+            .and_then(|e| e.id);
+        Ok(id)
+    }
     /// Add networks:
     /// - push config to vmm process.
     /// This function does not create network on host.
@@ -111,6 +128,20 @@ pub struct VmmApiMethods<'a> {
 /// See cloud-hypervisor docs/api
 /// Some methods doesn't expect any answer in body.
 impl VmmApiMethods<'_> {
+    /// Return the response if the vmm accepted the request,
+    /// or an error carrying the vmm answer otherwise.
+    async fn check(res: Response, message: &str) -> Result<Response, VirshleError> {
+        if res.status().is_success() {
+            return Ok(res);
+        }
+        let err_msg = &res.to_string().await?;
+        error!("{}", &err_msg);
+        Err(LibError::builder()
+            .msg(message)
+            .help(err_msg)
+            .build()
+            .into())
+    }
     /// If we can't establish connection to socket,
     /// this means cloud-hypervisor is dead.
     /// We should start a new viable process.
@@ -177,19 +208,9 @@ impl VmmApiMethods<'_> {
         self.ping().await?;
         let endpoint = "/vm.boot";
         let res = self.client.put::<()>(endpoint, None).await?;
-        if res.status().is_success() {
-            let msg = &res.to_string().await?;
-            trace!("{}", &msg);
-        } else {
-            let err_msg = &res.to_string().await?;
-            error!("{}", &err_msg);
-            let message = "Couldn't boot vm.";
-            return Err(LibError::builder()
-                .msg(&message)
-                .help(&err_msg)
-                .build()
-                .into());
-        }
+        let res = Self::check(res, "Couldn't boot vm.").await?;
+        let msg = &res.to_string().await?;
+        trace!("{}", &msg);
         Ok(())
     }
     #[tracing::instrument(skip_all)]
@@ -198,15 +219,152 @@ impl VmmApiMethods<'_> {
         self.ping().await?;
         let endpoint = "/vm.pause";
         let res = self.client.put::<()>(endpoint, None).await?;
+        Self::check(res, "Couldn't pause vm.").await?;
         trace!("paused vm {}", self.vm.name);
         Ok(())
     }
+    /// Resume a previously paused virtual machine.
+    #[tracing::instrument(skip_all)]
+    pub async fn resume(&mut self) -> Result<(), VirshleError> {
+        // Safeguard
+        self.ping().await?;
+        let endpoint = "/vm.resume";
+        let res = self.client.put::<()>(endpoint, None).await?;
+        Self::check(res, "Couldn't resume vm.").await?;
+        trace!("resumed vm {}", self.vm.name);
+        Ok(())
+    }
+    /// Reset the virtual machine, as a hardware reboot would.
+    #[tracing::instrument(skip_all)]
+    pub async fn reboot(&mut self) -> Result<(), VirshleError> {
+        // Safeguard
+        self.ping().await?;
+        let endpoint = "/vm.reboot";
+        let res = self.client.put::<()>(endpoint, None).await?;
+        Self::check(res, "Couldn't reboot vm.").await?;
+        trace!("rebooted vm {}", self.vm.name);
+        Ok(())
+    }
+    /// Dump the vm memory and device state to a directory.
+    /// The vm must be paused beforehand.
+    #[tracing::instrument(skip_all)]
+    pub async fn snapshot(&mut self, destination_url: &str) -> Result<(), VirshleError> {
+        // Safeguard
+        self.ping().await?;
+        let endpoint = "/vm.snapshot";
+        let req = VmSnapshotConfig {
+            destination_url: destination_url.to_owned(),
+        };
+        let res = self
+            .client
+            .put::<VmSnapshotConfig>(endpoint, Some(req))
+            .await?;
+        Self::check(res, "Couldn't snapshot vm.").await?;
+        trace!("snapshoted vm {} to {}", self.vm.name, destination_url);
+        Ok(())
+    }
+    /// Restore a vm from a snapshot directory.
+    /// The vmm process must be fresh (no vm created).
+    /// The vm is left paused after restoration.
+    #[tracing::instrument(skip_all)]
+    pub async fn restore(&mut self, source_url: &str) -> Result<(), VirshleError> {
+        // Safeguard
+        self.ping().await?;
+        let endpoint = "/vm.restore";
+        let req = RestoreConfig {
+            source_url: source_url.to_owned(),
+            ..Default::default()
+        };
+        let res = self
+            .client
+            .put::<RestoreConfig>(endpoint, Some(req))
+            .await?;
+        Self::check(res, "Couldn't restore vm.").await?;
+        trace!("restored vm {} from {}", self.vm.name, source_url);
+        Ok(())
+    }
+    /// Hotplug/unplug vcpus and ram,
+    /// within the max_vcpus and hotplug_size bounds set on boot.
+    #[tracing::instrument(skip_all)]
+    pub async fn resize(
+        &mut self,
+        vcpu: Option<u64>,
+        vram: Option<u64>,
+    ) -> Result<(), VirshleError> {
+        // Safeguard
+        self.ping().await?;
+        let endpoint = "/vm.resize";
+        let req = VmResizeData {
+            desired_vcpus: vcpu,
+            desired_ram: vram,
+        };
+        let res = self.client.put::<VmResizeData>(endpoint, Some(req)).await?;
+        Self::check(res, "Couldn't resize vm.").await?;
+        trace!("resized vm {}", self.vm.name);
+        Ok(())
+    }
+    /// Grow a disk of the running vm (size in bytes).
+    pub async fn resize_disk(&mut self, device_id: &str, size: u64) -> Result<(), VirshleError> {
+        // Safeguard
+        self.ping().await?;
+        let endpoint = "/vm.resize-disk";
+        let req = VmResizeDiskData {
+            id: device_id.to_owned(),
+            desired_size: size,
+        };
+        let res = self
+            .client
+            .put::<VmResizeDiskData>(endpoint, Some(req))
+            .await?;
+        Self::check(res, "Couldn't resize vm disk.").await?;
+        trace!("resized disk {} of vm {}", device_id, self.vm.name);
+        Ok(())
+    }
+    /// Stream the running vm to a receiving vmm.
+    /// Returns once the migration is over.
+    #[tracing::instrument(skip_all)]
+    pub async fn send_migration(&mut self, destination_url: &str) -> Result<(), VirshleError> {
+        // Safeguard
+        self.ping().await?;
+        let endpoint = "/vm.send-migration";
+        let req = VmSendMigrationData {
+            destination_url: destination_url.to_owned(),
+            local: false,
+        };
+        let res = self
+            .client
+            .put::<VmSendMigrationData>(endpoint, Some(req))
+            .await?;
+        Self::check(res, "Couldn't send vm migration.").await?;
+        trace!("sent vm {} to {}", self.vm.name, destination_url);
+        Ok(())
+    }
+    /// Wait for an incoming vm on a fresh vmm.
+    /// Returns once the migration is over.
+    #[tracing::instrument(skip_all)]
+    pub async fn receive_migration(&mut self, receiver_url: &str) -> Result<(), VirshleError> {
+        // Safeguard
+        self.ping().await?;
+        let endpoint = "/vm.receive-migration";
+        let req = VmReceiveMigrationData {
+            receiver_url: receiver_url.to_owned(),
+        };
+        let res = self
+            .client
+            .put::<VmReceiveMigrationData>(endpoint, Some(req))
+            .await?;
+        Self::check(res, "Couldn't receive vm migration.").await?;
+        trace!("received vm {} on {}", self.vm.name, receiver_url);
+        Ok(())
+    }
     /// Delete the virtual machine process.
     pub async fn delete(&mut self) -> Result<(), VirshleError> {
         // Safeguard
         self.ping().await?;
         let endpoint = "/vm.delete";
         let res = self.client.put::<()>(endpoint, None).await?;
+        Self::check(res, "Couldn't delete vm.").await?;
+        trace!("deleted vm {}", self.vm.name);
         Ok(())
     }
     pub async fn shutdown(&mut self) -> Result<(), VirshleError> {
@@ -214,6 +372,20 @@ impl VmmApiMethods<'_> {
         self.ping().await?;
         let endpoint = "/vm.shutdown";
         let res = self.client.put::<()>(endpoint, None).await?;
+        Self::check(res, "Couldn't shut vm down.").await?;
+        trace!("shut vm {} down", self.vm.name);
+        Ok(())
+    }
+    /// Press the virtual ACPI power button,
+    /// to let the guest power itself off.
+    #[tracing::instrument(skip_all)]
+    pub async fn power_button(&mut self) -> Result<(), VirshleError> {
+        // Safeguard
+        self.ping().await?;
+        let endpoint = "/vm.power-button";
+        let res = self.client.put::<()>(endpoint, None).await?;
+        Self::check(res, "Couldn't press the vm power button.").await?;
+        trace!("pressed power button of vm {}", self.vm.name);
         Ok(())
     }
     /// Remove a device from Vm.
@@ -243,4 +415,17 @@ impl VmmApiMethods<'_> {
 
         Ok(())
     }
+    /// Hot-plug a disk into Vm.
+    pub async fn add_disk(&mut self, disk_config: &DiskConfig) -> Result<(), VirshleError> {
+        // Safeguard
+        self.ping().await?;
+        let endpoint = "/vm.add-disk";
+        let res = self
+            .client
+            .put::<DiskConfig>(endpoint, Some(disk_config.to_owned()))
+            .await?;
+        Self::check(res, "Couldn't add disk to vm.").await?;
+        trace!("added disk to vm {}", self.vm.name);
+        Ok(())
+    }
 }
@@ -16,7 +16,11 @@ use std::os::unix::fs::PermissionsExt;
 use crate::config::VmTemplate;
 use crate::hypervisor::Vm;
 
-pub use types::{NetConfig, VmConfig, VmInfoResponse, VmRemoveDeviceData, VmState};
+pub use types::{
+    DiskConfig, NetConfig, RateLimiterConfig, RestoreConfig, TokenBucketConfig, VmConfig,
+    VmInfoResponse, VmReceiveMigrationData, VmRemoveDeviceData, VmResizeData, VmResizeDiskData,
+    VmSendMigrationData, VmSnapshotConfig, VmState,
+};
 
 // Error Handling
 use miette::Result;
@@ -74,6 +78,19 @@ impl VmmMethods<'_> {
             fs::remove_file(&vsock)?;
         }
 
+        // Serial console socket can't be rebound if left behind.
+        let console = &self.vm.get_console_socket()?;
+        let path = Path::new(&console);
+        if path.exists() {
+            #[cfg(debug_assertions)]
+            Process::new()
+                .stdin(&format!("sudo rm {}", &console))
+                .run()?;
+
+            #[cfg(not(debug_assertions))]
+            fs::remove_file(&console)?;
+        }
+
         Ok(())
     }
     /// Start or Restart a VMM.
@@ -5,21 +5,33 @@ use crate::{config::DiskTemplate, VmTemplate};
 use pretty_assertions::assert_eq;
 
 /// Create a testing Vm to try Vmm methods.
-fn testing_vm() -> Result<Vm, VirshleError> {
-    let template = VmTemplate {
+async fn testing_vm() -> Result<Vm, VirshleError> {
+    let mut template = VmTemplate {
         name: "test_vmm".to_owned(),
         vcpu: 1,
         vram: "1GiB".to_owned(),
+        max_vcpu: None,
+        max_vram: None,
         uuid: None,
         disk: Some(vec![DiskTemplate {
             name: "os".to_owned(),
             path: "/var/lib/virshle/cache/nixos.xxs.efi.img".to_owned(),
 
+            sha256: None,
             readonly: None,
+            size: None,
+            direct: None,
+            num_queues: None,
+            queue_size: None,
+            rate_limiter: None,
+            encrypted: None,
         }]),
         net: None,
+        security_groups: None,
         extra: None,
+        init: None,
     };
+    template.pull_images().await?;
     let vm: Vm = template.try_into()?;
     Ok(vm)
 }
@@ -32,7 +44,7 @@ async fn test_vmm() -> Result<()> {
         .set()?;
 
     // Create and start a testing Vm
-    let mut vm = testing_vm()?;
+    let mut vm = testing_vm().await?;
     vm.create(None).await?;
     vm.start().exec().await?;
 
@@ -54,6 +66,11 @@ async fn test_vmm() -> Result<()> {
     assert!(res.is_ok());
     let res: Result<VmState, VirshleError> = vm.vmm().api()?.state().await;
     assert_eq!(res.unwrap(), VmState::Paused);
+    // Resume
+    let res: Result<(), VirshleError> = vm.vmm().api()?.resume().await;
+    assert!(res.is_ok());
+    let res: Result<VmState, VirshleError> = vm.vmm().api()?.state().await;
+    assert_eq!(res.unwrap(), VmState::Running);
     // Shutdown
     let res: Result<(), VirshleError> = vm.vmm().api()?.shutdown().await;
     assert!(res.is_ok());
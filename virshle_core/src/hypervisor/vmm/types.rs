@@ -8,9 +8,12 @@
 * to be sent to cloud-hypervisor http api,
 * in just a few lines.
 */
-use crate::config::{Config, DhcpType, NetType};
+use crate::config::{Config, DhcpType, NetType, VmNet};
 use crate::hypervisor::{
-    disk::{utils::reverse_human_bytes, Disk},
+    disk::{
+        utils::{self as disk_utils, reverse_human_bytes},
+        Disk,
+    },
     vm::Vm,
 };
 use crate::network::utils;
@@ -114,9 +117,24 @@ pub struct BalloonConfig {
 // Disk
 #[derive(Default, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct DiskConfig {
+    /// Device id, assigned by cloud-hypervisor if none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub path: Option<PathBuf>,
     #[serde(default)]
     pub readonly: bool,
+    /// Allow qcow2 images to open their backing file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backing_files: Option<bool>,
+    /// Bypass host page cache (O_DIRECT).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direct: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_queues: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter_config: Option<RateLimiterConfig>,
     // Removed ch unused default
     #[serde(flatten)]
     other: serde_json::Value,
@@ -126,10 +144,49 @@ impl From<&Disk> for DiskConfig {
         DiskConfig {
             path: Some(PathBuf::from(&e.path)),
             readonly: e.readonly.unwrap_or(Default::default()),
+            // Thin copies (qcow2 overlays) rely on a backing image.
+            backing_files: match disk_utils::is_qcow2(&e.path) {
+                Ok(true) => Some(true),
+                _ => None,
+            },
+            direct: e.direct,
+            num_queues: e.num_queues,
+            queue_size: e.queue_size,
+            rate_limiter_config: e.rate_limiter.clone(),
             ..Default::default()
         }
     }
 }
+/// Disk I/O limits.
+/// Both buckets can be set, the first to run out throttles the disk.
+#[skip_serializing_none]
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct RateLimiterConfig {
+    /// Bytes.
+    pub bandwidth: Option<TokenBucketConfig>,
+    /// Operations.
+    pub ops: Option<TokenBucketConfig>,
+}
+#[skip_serializing_none]
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct TokenBucketConfig {
+    /// Bucket capacity (bytes or operations).
+    pub size: u64,
+    /// Extra tokens granted once, on start.
+    pub one_time_burst: Option<u64>,
+    /// Time to refill the bucket, in milliseconds.
+    pub refill_time: u64,
+}
+impl TokenBucketConfig {
+    /// A bucket refilled every second.
+    pub fn per_second(size: u64) -> Self {
+        Self {
+            size,
+            one_time_burst: None,
+            refill_time: 1000,
+        }
+    }
+}
 
 // Network
 #[skip_serializing_none]
@@ -220,6 +277,9 @@ impl VmState {
 pub struct VmInfoResponse {
     pub config: VmConfig,
     pub state: VmState,
+    /// Vm networks definition (switch, vlans...),
+    /// added by virshle as cloud-hypervisor doesn't know about it.
+    pub net: Option<Vec<VmNet>>,
 }
 /// Api return type
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
@@ -227,6 +287,47 @@ pub struct VmRemoveDeviceData {
     pub id: String,
 }
 
+/// Api request type
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct VmSnapshotConfig {
+    pub destination_url: String,
+}
+/// Api request type
+#[skip_serializing_none]
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct RestoreConfig {
+    pub source_url: String,
+    pub prefault: Option<bool>,
+}
+
+/// Api request type
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct VmResizeData {
+    pub desired_vcpus: Option<u64>,
+    /// Ram size in bytes.
+    pub desired_ram: Option<u64>,
+}
+/// Api request type
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct VmResizeDiskData {
+    pub id: String,
+    /// Disk size in bytes.
+    pub desired_size: u64,
+}
+/// Api request type
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct VmSendMigrationData {
+    pub destination_url: String,
+    /// Local (same host) migration through shared memory.
+    #[serde(default)]
+    pub local: bool,
+}
+/// Api request type
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct VmReceiveMigrationData {
+    pub receiver_url: String,
+}
+
 /// Api return type
 pub struct PciDeviceInfo {
     pub id: String,
@@ -243,10 +344,12 @@ impl VmConfig {
         let kernel = "/run/cloud-hypervisor/hypervisor-fw";
 
         let vram: u64 = reverse_human_bytes(&e.vram)?;
+        // Room left to grow the vm ram while running.
+        let hotplug_size: u64 = e.get_max_vram()? - vram;
         let mut config = VmConfig {
             cpus: CpusConfig {
                 boot_vcpus: e.vcpu,
-                max_vcpus: e.vcpu * 2,
+                max_vcpus: e.get_max_vcpu(),
                 ..Default::default()
             },
             memory: MemoryConfig {
@@ -255,7 +358,7 @@ impl VmConfig {
                 hugepages: false,
                 mergeable: true,
                 // hugepage_size: Some(2048),
-                // hotplug_size: Some(mem_size),
+                hotplug_size: (hotplug_size > 0).then_some(hotplug_size),
                 ..Default::default()
             },
             disks: None,
@@ -282,14 +385,11 @@ impl VmConfig {
         };
         config.payload = Some(payload);
 
-        // Attach/Detach from standard output.
-        // let console_socket = format!("{}/console.sock", e.get_dir()?);
+        // Expose serial on a socket,
+        // to attach/detach from it through the virshle api.
         config.serial = Some(ConsoleConfig {
-            // mode: ConsoleOutputMode::Socket,
-            // socket: Some(console_socket),
-            // mode: ConsoleOutputMode::Null,
-            mode: ConsoleOutputMode::Tty,
-            ..Default::default()
+            mode: ConsoleOutputMode::Socket,
+            socket: Some(e.get_console_socket()?),
         });
         config.console = Some(ConsoleConfig {
             mode: ConsoleOutputMode::Null,
@@ -310,15 +410,21 @@ impl VmConfig {
         // Add disks
         let mut disk: Vec<DiskConfig> = vec![];
         for def in &e.disk {
-            disk.push(DiskConfig::from(def));
+            let mut disk_config = DiskConfig::from(def);
+            // Encrypted disks are plugged unlocked.
+            disk_config.path = Some(PathBuf::from(e.crypt().get_device_path(def)));
+            disk.push(disk_config);
         }
         config.disks = Some(disk);
 
         // Add networks
         if let Some(nets) = &e.net {
+            // Safeguard: every interface gets its own mac address.
+            e.networks().get_macs()?;
             let mut net_configs: Vec<NetConfig> = vec![];
             for net in nets {
                 let port_name = format!("vm-{}--{}", e.name, net.name);
+                let mac = e.networks().get_mac(net)?;
 
                 // Get fake_dhcp ip
                 let mut ip: Option<IpAddr> = None;
@@ -344,7 +450,7 @@ impl VmConfig {
                     }
                     NetType::Vhost(_) => {
                         net_configs.push(NetConfig {
-                            mac: Some(utils::uuid_to_mac(&e.uuid).to_string()),
+                            mac: Some(mac.to_string()),
                             // dpdk specific
                             vhost_user: Some(true),
                             vhost_mode: Some(VhostMode::Server),
@@ -359,7 +465,7 @@ impl VmConfig {
                         // external Tap via name
                         let tap_name = utils::unix_name(&port_name);
                         net_configs.push(NetConfig {
-                            mac: Some(utils::uuid_to_mac(&e.uuid).to_string()),
+                            mac: Some(mac.to_string()),
                             //tap
                             tap: Some(tap_name),
 
@@ -408,7 +514,8 @@ mod test {
         [net.type.macvtap]
         "#;
 
-        let vm_template = VmTemplate::from_toml(&toml)?;
+        let mut vm_template = VmTemplate::from_toml(&toml)?;
+        vm_template.pull_images().await?;
         println!("{:#?}", vm_template);
 
         let vm: Vm = vm_template.try_into()?;
@@ -431,7 +538,7 @@ mod test {
         [[disk]]
         name = "os"
         path = "~/Iso/nixos.xxs.efi.img"
-        size = "50G"
+        size = "50GiB"
 
         [[net]]
         name = "main"
@@ -16,11 +16,15 @@ pub use network::dhcp::KeaDhcp;
 /// Interact with cloud hypervisor processes and API.
 pub mod hypervisor;
 pub use hypervisor::disk::utils::{human_bytes, reverse_human_bytes};
-pub use hypervisor::{Vm, VmInfo, VmState, VmTable};
+pub use hypervisor::{ShutdownMode, Snapshot, Vm, VmInfo, VmState, VmTable};
 
 pub use config::{Account, Config, Node, VmTemplate};
 
 pub mod exec;
 
+/// Fetch, verify and index base images.
+pub mod cache;
+pub use cache::Image;
+
 // Stores vm definitions in sqlite database
 pub mod database;
@@ -56,6 +56,19 @@ impl FakeDhcp {
             .await?;
         Ok(())
     }
+    /// Return the addresses leased to a vm.
+    pub async fn get_leases(vm_id: i32) -> Result<Vec<IpAddr>, VirshleError> {
+        let db = connect_db().await?;
+        let leases = database::prelude::Lease::find()
+            .filter(database::entity::lease::Column::VmId.eq(vm_id))
+            .all(&db)
+            .await?;
+        let ips = leases
+            .iter()
+            .filter_map(|e| IpAddr::from_str(&e.ip).ok())
+            .collect();
+        Ok(ips)
+    }
 }
 
 pub struct FakeDhcpLease {
@@ -68,6 +81,9 @@ pub struct LeaseList {
 }
 
 impl IpPool {
+    pub fn get_subnet(&self) -> IpNet {
+        self.subnet
+    }
     pub fn get_mask(&self) -> Result<IpAddr, VirshleError> {
         let default_mask = IpAddr::V6(Ipv6Addr::from_str("ffff:ffff:ffff:ffff::").unwrap());
         Ok(default_mask)
@@ -175,7 +175,7 @@ impl LeaseGetterMethods<'_> {
 
         let mut cmds: Vec<KeaCommand> = vec![];
         if inet4 {
-            let mut cmd = KeaCommand {
+            let cmd = KeaCommand {
                 command: "lease4-get-all".to_owned(),
                 service: vec!["dhcp4".to_owned()],
                 ..default_cmd.clone()
@@ -198,17 +198,20 @@ impl LeaseGetterMethods<'_> {
                 // };
                 
                 // Alternative:
-                let hwaddr = uuid_to_mac(&vm.uuid).to_string();
-                let args: HashMap<String, String> = HashMap::from([
-                    ("hw-address".to_owned(), hwaddr)
-                ]);
-                cmd = KeaCommand {
-                    command: cmd.command.replace("all", "by-hw-address"),
-                    arguments: Some(args),
-                    ..cmd.clone()
-                };
+                // one request per network interface mac address.
+                for (_, mac) in vm.networks().get_macs()? {
+                    let args: HashMap<String, String> = HashMap::from([
+                        ("hw-address".to_owned(), mac.to_string())
+                    ]);
+                    cmds.push(KeaCommand {
+                        command: cmd.command.replace("all", "by-hw-address"),
+                        arguments: Some(args),
+                        ..cmd.clone()
+                    });
+                }
+            } else {
+                cmds.push(cmd)
             }
-            cmds.push(cmd)
         }
         if inet6 {
             let mut cmd = KeaCommand {
@@ -63,9 +63,8 @@ pub fn get_path(name: &str) -> Result<String, VirshleError> {
     }
 }
 
-pub fn create(name: &str) -> Result<(), VirshleError> {
-    let vm_bridge = OvsBridge::get_vm_switch()?;
-    let vm_bridge_name = vm_bridge.name;
+pub fn create(name: &str, switch: &OvsBridge) -> Result<(), VirshleError> {
+    let vm_bridge_name = &switch.name;
     let main_interface = get_main_interface()?;
     let main_interface_name = main_interface.name;
 
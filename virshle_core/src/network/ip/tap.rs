@@ -19,13 +19,10 @@ use crate::exec::exec_cmds;
 use crate::network::{
     interface,
     ip::{get_interfaces, get_main_interface},
-    ovs::OvsBridge,
     utils,
 };
 
 pub fn create(name: &str) -> Result<(), VirshleError> {
-    let vm_bridge = OvsBridge::get_vm_switch()?;
-
     let name = utils::unix_name(name);
     let mut cmds: Vec<String> = vec![];
 
@@ -74,6 +71,7 @@ pub fn delete(name: &str) -> Result<(), VirshleError> {
 }
 
 pub fn set_mac(name: &str, mac: &MacAddr6) -> Result<(), VirshleError> {
+    let name = utils::unix_name(name);
     let mut cmds: Vec<String> = vec![];
     #[cfg(debug_assertions)]
     cmds.push(format!(
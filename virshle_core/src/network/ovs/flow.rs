@@ -0,0 +1,514 @@
+use crate::config::{Direction, FirewallAction, FirewallRule, Protocol};
+
+use ipnet::IpNet;
+use macaddr::MacAddr6;
+use sha2::{Digest, Sha256};
+use std::fs;
+use uuid::Uuid;
+
+// Error handling
+use miette::Result;
+use pipelight_exec::Process;
+use tracing::trace;
+use virshle_error::{LibError, VirshleError};
+
+/*
+* Vm firewall as openflow rules on the vm ovs port.
+*
+* Packets from a vm port go through:
+* - table 0: anti-spoofing, source mac/ip must be the vm ones,
+* - table 10: the vm egress rules,
+* - table 20: dispatch to the destination vm, if any,
+* - table 21: the destination vm ingress rules.
+* Packets from elsewhere (uplink, patch...) to a vm mac
+* skip straight to table 20.
+*
+* Ingress rules only apply to unicast traffic (dl_dst is the vm mac).
+* Broadcast and multicast frames (arp, dhcp, neighbor discovery, mdns...)
+* are flooded by the NORMAL action and reach every vm on the switch.
+*
+* Connections are tracked (conntrack zone per vm),
+* so replies to allowed traffic are let through.
+* Every vm port flows share a cookie, to be removed at once.
+*/
+
+pub const TABLE_EGRESS: u8 = 10;
+pub const TABLE_DISPATCH: u8 = 20;
+pub const TABLE_INGRESS: u8 = 21;
+/// Cookie of the flows shared by every vm port.
+pub const BASE_COOKIE: u64 = 0x7669_7273_686c_6500;
+
+/// Icmpv6 neighbor discovery types the vm may send:
+/// router solicitation, neighbor solicitation and advertisement.
+const ND_EGRESS: [u8; 3] = [133, 135, 136];
+/// Icmpv6 neighbor discovery types the vm may receive:
+/// router advertisement, neighbor solicitation and advertisement.
+const ND_INGRESS: [u8; 3] = [134, 135, 136];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OvsFirewall {
+    pub bridge: String,
+    /// Ovs port name.
+    pub port: String,
+    pub cookie: u64,
+    /// Conntrack zone.
+    pub zone: u16,
+    pub mac: MacAddr6,
+    /// Addresses (or subnets) the vm is allowed to send from.
+    /// Without an ipv4 one, the vm may only request a lease.
+    /// Ipv6 link-local addresses are always allowed.
+    /// None when no lease source is known, only the source mac is checked.
+    pub ips: Option<Vec<IpNet>>,
+    pub rules: Vec<FirewallRule>,
+}
+
+/// Return the openflow match of an address or subnet,
+/// without prefix for a single address (ex: 10.0.0.2, 10.0.0.0/24).
+fn to_match(net: &IpNet) -> String {
+    match net.prefix_len() == net.max_prefix_len() {
+        true => net.addr().to_string(),
+        false => net.to_string(),
+    }
+}
+
+/// Return the openflow matches of a port range,
+/// as values and masks (ex: 8000-8015 -> 0x1f40/0xfff0).
+pub fn port_masks(start: u16, end: u16) -> Vec<(u16, u16)> {
+    let mut res = vec![];
+    let end = end as u32;
+    let mut start = start as u32;
+    while start <= end {
+        // Largest aligned block starting at start and ending before end.
+        let mut size: u32 = 1;
+        while start % (size * 2) == 0 && start + size * 2 - 1 <= end && size * 2 <= 0x10000 {
+            size *= 2;
+        }
+        res.push((start as u16, (!(size - 1) & 0xffff) as u16));
+        start += size;
+    }
+    res
+}
+
+impl OvsFirewall {
+    /// Return a predictable flow cookie for a vm network.
+    pub fn cookie(uuid: &Uuid, net_name: &str) -> u64 {
+        let digest = Sha256::new()
+            .chain_update(uuid.as_bytes())
+            .chain_update(net_name.as_bytes())
+            .finalize();
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        u64::from_be_bytes(bytes)
+    }
+
+    /// Compile anti-spoofing and firewall rules into ovs-ofctl flows.
+    pub fn to_flows(&self) -> Vec<String> {
+        let port = &self.port;
+        let mac = self.mac.to_string().to_lowercase();
+        let zone = self.zone;
+        let head = format!("cookie={:#x}", self.cookie);
+        let ct = format!("ct(commit,zone={zone})");
+
+        let mut flows = vec![format!(
+            "cookie={BASE_COOKIE:#x},table={TABLE_DISPATCH},priority=0,actions=NORMAL"
+        )];
+
+        // Anti-spoofing
+        let spoof = format!("{head},table=0,priority=210,in_port={port}");
+        let to_egress = format!("ct(zone={zone},table={TABLE_EGRESS})");
+        match &self.ips {
+            Some(ips) => {
+                let ipv4: Vec<String> = ips
+                    .iter()
+                    .filter(|e| matches!(e, IpNet::V4(_)))
+                    .map(to_match)
+                    .collect();
+                let ipv6: Vec<String> = ips
+                    .iter()
+                    .filter(|e| matches!(e, IpNet::V6(_)))
+                    .map(to_match)
+                    .collect();
+                for ip in &ipv4 {
+                    flows.push(format!(
+                        "{spoof},ip,dl_src={mac},nw_src={ip},actions={to_egress}"
+                    ));
+                }
+                // Dhcp discover and arp probes, before the vm has an address.
+                flows.push(format!(
+                    "{spoof},udp,dl_src={mac},nw_src=0.0.0.0,tp_src=68,tp_dst=67,actions={to_egress}"
+                ));
+                for ip in ipv4.iter().cloned().chain(["0.0.0.0".to_owned()]) {
+                    flows.push(format!(
+                        "{spoof},arp,dl_src={mac},arp_sha={mac},arp_spa={ip},actions=resubmit(,{TABLE_EGRESS})"
+                    ));
+                }
+                // Allowed addresses, link-local addresses and duplicate address detection.
+                for ip in ipv6
+                    .iter()
+                    .cloned()
+                    .chain(["fe80::/10".to_owned(), "::".to_owned()])
+                {
+                    flows.push(format!(
+                        "{spoof},ipv6,dl_src={mac},ipv6_src={ip},actions={to_egress}"
+                    ));
+                }
+            }
+            // Addresses are unknown (static, cloud-init...), only the mac is checked.
+            None => {
+                for proto in ["ip", "ipv6"] {
+                    flows.push(format!("{spoof},{proto},dl_src={mac},actions={to_egress}"));
+                }
+                flows.push(format!(
+                    "{spoof},arp,dl_src={mac},arp_sha={mac},actions=resubmit(,{TABLE_EGRESS})"
+                ));
+            }
+        }
+        flows.push(format!(
+            "{head},table=0,priority=200,in_port={port},actions=drop"
+        ));
+        flows.push(format!(
+            "{head},table=0,priority=100,dl_dst={mac},actions=resubmit(,{TABLE_DISPATCH})"
+        ));
+
+        // Egress
+        let egress = format!("{head},table={TABLE_EGRESS}");
+        let allow = format!("{ct},resubmit(,{TABLE_DISPATCH})");
+        flows.push(format!(
+            "{egress},priority=400,in_port={port},arp,actions=resubmit(,{TABLE_DISPATCH})"
+        ));
+        for proto in ["ip", "ipv6"] {
+            flows.push(format!(
+                "{egress},priority=400,ct_state=+est+trk,in_port={port},{proto},actions=resubmit(,{TABLE_DISPATCH})"
+            ));
+            flows.push(format!(
+                "{egress},priority=400,ct_state=+rel+trk,in_port={port},{proto},actions=resubmit(,{TABLE_DISPATCH})"
+            ));
+            flows.push(format!(
+                "{egress},priority=400,ct_state=+inv+trk,in_port={port},{proto},actions=drop"
+            ));
+        }
+        flows.push(format!(
+            "{egress},priority=350,in_port={port},udp,tp_src=68,tp_dst=67,actions={allow}"
+        ));
+        flows.push(format!(
+            "{egress},priority=350,in_port={port},udp6,tp_src=546,tp_dst=547,actions={allow}"
+        ));
+        for icmp_type in ND_EGRESS {
+            flows.push(format!(
+                "{egress},priority=350,in_port={port},icmp6,icmp_type={icmp_type},actions=resubmit(,{TABLE_DISPATCH})"
+            ));
+        }
+        flows.extend(self.rules_to_flows(Direction::Egress, &format!("in_port={port}"), &allow));
+
+        // Dispatch to the vm ingress rules.
+        for proto in ["ip", "ipv6"] {
+            flows.push(format!(
+                "{head},table={TABLE_DISPATCH},priority=100,dl_dst={mac},{proto},actions=ct_clear,ct(zone={zone},table={TABLE_INGRESS})"
+            ));
+        }
+
+        // Ingress
+        let ingress = format!("{head},table={TABLE_INGRESS}");
+        let allow = format!("{ct},NORMAL");
+        for proto in ["ip", "ipv6"] {
+            flows.push(format!(
+                "{ingress},priority=400,ct_state=+est+trk,dl_dst={mac},{proto},actions=NORMAL"
+            ));
+            flows.push(format!(
+                "{ingress},priority=400,ct_state=+rel+trk,dl_dst={mac},{proto},actions=NORMAL"
+            ));
+            flows.push(format!(
+                "{ingress},priority=400,ct_state=+inv+trk,dl_dst={mac},{proto},actions=drop"
+            ));
+        }
+        flows.push(format!(
+            "{ingress},priority=350,dl_dst={mac},udp,tp_src=67,tp_dst=68,actions={allow}"
+        ));
+        flows.push(format!(
+            "{ingress},priority=350,dl_dst={mac},udp6,tp_src=547,tp_dst=546,actions={allow}"
+        ));
+        for icmp_type in ND_INGRESS {
+            flows.push(format!(
+                "{ingress},priority=350,dl_dst={mac},icmp6,icmp_type={icmp_type},actions=NORMAL"
+            ));
+        }
+        flows.extend(self.rules_to_flows(Direction::Ingress, &format!("dl_dst={mac}"), &allow));
+
+        flows
+    }
+
+    /// Compile the rules of a direction,
+    /// deny rules first, then allow rules, then the default policy:
+    /// deny if some allow rules are set, allow otherwise.
+    fn rules_to_flows(&self, direction: Direction, matcher: &str, allow: &str) -> Vec<String> {
+        let (table, cidr_key) = match direction {
+            Direction::Egress => (TABLE_EGRESS, ("nw_dst", "ipv6_dst")),
+            Direction::Ingress => (TABLE_INGRESS, ("nw_src", "ipv6_src")),
+        };
+        let head = format!("cookie={:#x},table={table}", self.cookie);
+        let rules = self
+            .rules
+            .iter()
+            .filter(|e| e.direction == direction)
+            .collect::<Vec<&FirewallRule>>();
+
+        let mut flows = vec![];
+        for rule in &rules {
+            let (priority, actions) = match rule.action {
+                FirewallAction::Deny => (300, "drop"),
+                FirewallAction::Allow => (200, allow),
+            };
+            let families: Vec<bool> = match rule.cidr {
+                Some(IpNet::V4(_)) => vec![false],
+                Some(IpNet::V6(_)) => vec![true],
+                None => vec![false, true],
+            };
+            for v6 in families {
+                let proto = match (rule.proto, v6) {
+                    (Protocol::Any, false) => "ip",
+                    (Protocol::Any, true) => "ipv6",
+                    (Protocol::Tcp, false) => "tcp",
+                    (Protocol::Tcp, true) => "tcp6",
+                    (Protocol::Udp, false) => "udp",
+                    (Protocol::Udp, true) => "udp6",
+                    (Protocol::Icmp, false) => "icmp",
+                    (Protocol::Icmp, true) => "icmp6",
+                };
+                let mut matches = format!("{matcher},{proto}");
+                if let Some(cidr) = &rule.cidr {
+                    let key = if v6 { cidr_key.1 } else { cidr_key.0 };
+                    matches += &format!(",{key}={}", cidr.trunc());
+                }
+                let ports = match &rule.port {
+                    Some(range) => port_masks(range.start, range.end)
+                        .into_iter()
+                        .map(|(value, mask)| match mask {
+                            0xffff => format!(",tp_dst={value}"),
+                            0 => "".to_owned(),
+                            _ => format!(",tp_dst={value:#x}/{mask:#x}"),
+                        })
+                        .collect(),
+                    None => vec!["".to_owned()],
+                };
+                for port in ports {
+                    flows.push(format!(
+                        "{head},priority={priority},{matches}{port},actions={actions}"
+                    ));
+                }
+            }
+        }
+
+        // Default policy
+        match rules.iter().any(|e| e.action == FirewallAction::Allow) {
+            true => flows.push(format!("{head},priority=1,{matcher},actions=drop")),
+            false => {
+                for proto in ["ip", "ipv6"] {
+                    flows.push(format!(
+                        "{head},priority=1,{matcher},{proto},actions={allow}"
+                    ));
+                }
+            }
+        };
+        flows
+    }
+
+    /*
+     * Add the vm port flows to the switch.
+     * Flows are written to a file, for ovs-ofctl to read them at once.
+     */
+    pub fn create(&self, path: &str) -> Result<(), VirshleError> {
+        fs::write(path, self.to_flows().join("\n") + "\n")?;
+
+        #[cfg(debug_assertions)]
+        let cmd = format!("sudo ovs-ofctl add-flows {} {}", self.bridge, path);
+        #[cfg(not(debug_assertions))]
+        let cmd = format!("ovs-ofctl add-flows {} {}", self.bridge, path);
+
+        trace!("adding firewall flows for port {:#?}", self.port);
+        Self::exec(&cmd)
+    }
+    /*
+     * Whether the flows written to the file are up to date.
+     */
+    pub fn is_applied(&self, path: &str) -> bool {
+        fs::read_to_string(path).is_ok_and(|e| e == self.to_flows().join("\n") + "\n")
+    }
+    /*
+     * Remove the vm port flows from the switch.
+     */
+    pub fn delete(bridge: &str, cookie: u64) -> Result<(), VirshleError> {
+        #[cfg(debug_assertions)]
+        let cmd = format!("sudo ovs-ofctl del-flows {bridge} cookie={cookie:#x}/-1");
+        #[cfg(not(debug_assertions))]
+        let cmd = format!("ovs-ofctl del-flows {bridge} cookie={cookie:#x}/-1");
+
+        Self::exec(&cmd)
+    }
+    fn exec(cmd: &str) -> Result<(), VirshleError> {
+        let mut proc = Process::new();
+        let res = proc.stdin(cmd).run()?;
+
+        if let Some(stderr) = res.io.stderr {
+            let message = "Ovs command failed.";
+            let help = format!("{}\n{} ", stderr, &res.io.stdin.unwrap());
+            return Err(LibError::builder().msg(message).help(&help).build().into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::PortRange;
+    use pretty_assertions::assert_eq;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    fn testing_firewall(rules: Vec<FirewallRule>) -> OvsFirewall {
+        OvsFirewall {
+            bridge: "br0".to_owned(),
+            port: "vm-test--main".to_owned(),
+            cookie: 0x2a,
+            zone: 7,
+            mac: MacAddr6::from_str("4e:3b:ae:5f:6a:aa").unwrap(),
+            ips: Some(vec![IpNet::from(IpAddr::from_str("10.0.0.2").unwrap())]),
+            rules,
+        }
+    }
+
+    #[test]
+    fn split_port_range_into_masks() -> Result<()> {
+        assert_eq!(port_masks(22, 22), vec![(22, 0xffff)]);
+        assert_eq!(port_masks(8000, 8015), vec![(8000, 0xfff0)]);
+        assert_eq!(
+            port_masks(1000, 1100),
+            vec![
+                (1000, 0xfff8),
+                (1008, 0xfff0),
+                (1024, 0xffc0),
+                (1088, 0xfff8),
+                (1096, 0xfffc),
+                (1100, 0xffff)
+            ]
+        );
+        assert_eq!(port_masks(0, 65535), vec![(0, 0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn compile_anti_spoofing_flows() -> Result<()> {
+        let flows = testing_firewall(vec![]).to_flows();
+        let expected = [
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,ip,dl_src=4e:3b:ae:5f:6a:aa,nw_src=10.0.0.2,actions=ct(zone=7,table=10)",
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,arp,dl_src=4e:3b:ae:5f:6a:aa,arp_sha=4e:3b:ae:5f:6a:aa,arp_spa=10.0.0.2,actions=resubmit(,10)",
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,ipv6,dl_src=4e:3b:ae:5f:6a:aa,ipv6_src=fe80::/10,actions=ct(zone=7,table=10)",
+            "cookie=0x2a,table=0,priority=200,in_port=vm-test--main,actions=drop",
+            // No rules: allow both ways.
+            "cookie=0x2a,table=10,priority=1,in_port=vm-test--main,ip,actions=ct(commit,zone=7),resubmit(,20)",
+            "cookie=0x2a,table=21,priority=1,dl_dst=4e:3b:ae:5f:6a:aa,ipv6,actions=ct(commit,zone=7),NORMAL",
+        ];
+        for flow in expected {
+            assert!(flows.contains(&flow.to_owned()), "missing flow: {flow}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compile_anti_spoofing_flows_without_lease() -> Result<()> {
+        let mut firewall = testing_firewall(vec![]);
+        firewall.ips = Some(vec![]);
+        let flows = firewall.to_flows();
+
+        // Only dhcp requests and arp probes.
+        let expected = [
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,udp,dl_src=4e:3b:ae:5f:6a:aa,nw_src=0.0.0.0,tp_src=68,tp_dst=67,actions=ct(zone=7,table=10)",
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,arp,dl_src=4e:3b:ae:5f:6a:aa,arp_sha=4e:3b:ae:5f:6a:aa,arp_spa=0.0.0.0,actions=resubmit(,10)",
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,ipv6,dl_src=4e:3b:ae:5f:6a:aa,ipv6_src=::,actions=ct(zone=7,table=10)",
+        ];
+        for flow in expected {
+            assert!(flows.contains(&flow.to_owned()), "missing flow: {flow}");
+        }
+        let spoof = "cookie=0x2a,table=0,priority=210,in_port=vm-test--main";
+        let forbidden = [
+            format!("{spoof},ip,dl_src=4e:3b:ae:5f:6a:aa,actions=ct(zone=7,table=10)"),
+            format!("{spoof},ipv6,dl_src=4e:3b:ae:5f:6a:aa,actions=ct(zone=7,table=10)"),
+            format!("{spoof},arp,dl_src=4e:3b:ae:5f:6a:aa,arp_sha=4e:3b:ae:5f:6a:aa,actions=resubmit(,10)"),
+        ];
+        for flow in forbidden {
+            assert!(!flows.contains(&flow), "unexpected flow: {flow}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compile_anti_spoofing_flows_with_subnet() -> Result<()> {
+        let mut firewall = testing_firewall(vec![]);
+        firewall.ips = Some(vec![IpNet::from_str("10.0.0.0/24").unwrap()]);
+        let flows = firewall.to_flows();
+        let expected = [
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,ip,dl_src=4e:3b:ae:5f:6a:aa,nw_src=10.0.0.0/24,actions=ct(zone=7,table=10)",
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,arp,dl_src=4e:3b:ae:5f:6a:aa,arp_sha=4e:3b:ae:5f:6a:aa,arp_spa=10.0.0.0/24,actions=resubmit(,10)",
+        ];
+        for flow in expected {
+            assert!(flows.contains(&flow.to_owned()), "missing flow: {flow}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compile_anti_spoofing_flows_without_lease_source() -> Result<()> {
+        let mut firewall = testing_firewall(vec![]);
+        firewall.ips = None;
+        let flows = firewall.to_flows();
+
+        // Any address, from the vm mac only.
+        let expected = [
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,ip,dl_src=4e:3b:ae:5f:6a:aa,actions=ct(zone=7,table=10)",
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,ipv6,dl_src=4e:3b:ae:5f:6a:aa,actions=ct(zone=7,table=10)",
+            "cookie=0x2a,table=0,priority=210,in_port=vm-test--main,arp,dl_src=4e:3b:ae:5f:6a:aa,arp_sha=4e:3b:ae:5f:6a:aa,actions=resubmit(,10)",
+            "cookie=0x2a,table=0,priority=200,in_port=vm-test--main,actions=drop",
+        ];
+        for flow in expected {
+            assert!(flows.contains(&flow.to_owned()), "missing flow: {flow}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compile_firewall_rules() -> Result<()> {
+        let rules = vec![
+            FirewallRule {
+                direction: Direction::Ingress,
+                action: FirewallAction::Allow,
+                proto: Protocol::Tcp,
+                port: Some(PortRange { start: 22, end: 22 }),
+                cidr: Some(IpNet::from_str("192.168.1.0/24").unwrap()),
+            },
+            FirewallRule {
+                direction: Direction::Egress,
+                action: FirewallAction::Deny,
+                proto: Protocol::Udp,
+                port: Some(PortRange {
+                    start: 8000,
+                    end: 8015,
+                }),
+                cidr: None,
+            },
+        ];
+        let flows = testing_firewall(rules).to_flows();
+        let expected = [
+            "cookie=0x2a,table=21,priority=200,dl_dst=4e:3b:ae:5f:6a:aa,tcp,nw_src=192.168.1.0/24,tp_dst=22,actions=ct(commit,zone=7),NORMAL",
+            // Some allow rules: deny the rest.
+            "cookie=0x2a,table=21,priority=1,dl_dst=4e:3b:ae:5f:6a:aa,actions=drop",
+            "cookie=0x2a,table=10,priority=300,in_port=vm-test--main,udp,tp_dst=0x1f40/0xfff0,actions=drop",
+            "cookie=0x2a,table=10,priority=300,in_port=vm-test--main,udp6,tp_dst=0x1f40/0xfff0,actions=drop",
+            // Only deny rules: allow the rest.
+            "cookie=0x2a,table=10,priority=1,in_port=vm-test--main,ip,actions=ct(commit,zone=7),resubmit(,20)",
+        ];
+        for flow in expected {
+            assert!(flows.contains(&flow.to_owned()), "missing flow: {flow}");
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 pub mod convert;
+mod flow;
 mod getters;
 mod request;
 mod translate;
@@ -7,6 +8,7 @@ mod translate;
 use super::interface::Bridge;
 
 // Reexport
+pub use flow::OvsFirewall;
 pub use translate::{OvsBridge, OvsInterface, OvsInterfaceType, OvsPort};
 
 use serde::{Deserialize, Serialize};
@@ -26,6 +28,7 @@ use tracing::{error, info};
 use virshle_error::{LibError, VirshleError, WrapError};
 
 // Cloud-hypervisor
+use crate::config::{Bandwidth, Config, NetworkConfig, SwitchConfig, SwitchType, DEFAULT_SWITCH};
 use crate::hypervisor::Vm;
 use crate::network::utils;
 use crate::network::InterfaceState;
@@ -36,39 +39,139 @@ use std::path::Path;
 
 impl OvsBridge {
     /*
-     * Add vm port into ovs config.
+     * Add vm port into ovs config, or update its settings.
+     * With an optional vlan access tag or vlan trunks,
+     * and optional rate limits.
      */
-    pub fn create_tap_port(&self, name: &str) -> Result<(), VirshleError> {
+    pub fn create_tap_port(
+        &self,
+        name: &str,
+        vlan: Option<u16>,
+        trunks: Option<Vec<u16>>,
+        bandwidth: Option<Bandwidth>,
+    ) -> Result<(), VirshleError> {
         let vm_bridge_name = &self.name;
         let ifname = utils::unix_name(&name);
 
-        request::OvsRequest::interface(&ifname)
-            ._type(request::OvsInterfaceType::System)
+        let mut req = request::OvsRequest::interface(&ifname);
+        req._type(request::OvsInterfaceType::System)
             .bridge(vm_bridge_name)
-            .create()
-            .build()
-            .exec()?;
+            .create();
+        if let Some(vlan) = vlan {
+            req.tag(vlan);
+        }
+        if let Some(trunks) = &trunks {
+            req.trunks(trunks);
+        }
+        clear_qos(&ifname)?;
+        set_bandwidth(&mut req, &bandwidth)?;
+        req.build().exec()?;
 
         Ok(())
     }
     /*
-     * Add vm port into ovs config.
+     * Add vm port into ovs config, or update its settings.
+     * With an optional vlan access tag or vlan trunks,
+     * and optional rate limits.
      */
-    pub fn create_dpdk_port(&self, name: &str, socket_path: &str) -> Result<(), VirshleError> {
+    pub fn create_dpdk_port(
+        &self,
+        name: &str,
+        socket_path: &str,
+        vlan: Option<u16>,
+        trunks: Option<Vec<u16>>,
+        bandwidth: Option<Bandwidth>,
+    ) -> Result<(), VirshleError> {
         let vm_bridge_name = &self.name;
 
-        request::OvsRequest::interface(name)
-            ._type(request::OvsInterfaceType::DpdkVhostUserClient)
+        let mut req = request::OvsRequest::interface(name);
+        req._type(request::OvsInterfaceType::DpdkVhostUserClient)
             .socket_path(socket_path)
             .bridge(vm_bridge_name)
-            .create()
-            .build()
-            .exec()?;
+            .create();
+        if let Some(vlan) = vlan {
+            req.tag(vlan);
+        }
+        if let Some(trunks) = &trunks {
+            req.trunks(trunks);
+        }
+        clear_qos(name)?;
+        set_bandwidth(&mut req, &bandwidth)?;
+        req.build().exec()?;
 
         Ok(())
     }
 }
 
+/*
+* Apply a vm network rate limits to its port request.
+* Traffic from the vm is received by ovs and policed,
+* traffic to the vm is sent by ovs and shaped.
+*/
+fn set_bandwidth(
+    req: &mut request::OvsInterfaceBuilder,
+    bandwidth: &Option<Bandwidth>,
+) -> Result<(), VirshleError> {
+    let bandwidth = bandwidth.clone().unwrap_or_default();
+    let burst = bandwidth.get_burst()?;
+    // Always set, to remove a previous limit.
+    req.policing(
+        bandwidth.get_egress()?.unwrap_or(0) / 1000,
+        burst.unwrap_or(0) / 1000,
+    );
+    if let Some(rate) = bandwidth.get_ingress()? {
+        req.qos(rate, burst);
+    }
+    Ok(())
+}
+
+/*
+* Unlink and destroy the qos rows of a port (if any).
+* Qos and queue rows are not removed along with the port they are used by.
+*/
+pub fn clear_qos(iface: &str) -> Result<(), VirshleError> {
+    let mut rows: Vec<(&str, String)> = vec![];
+    for table in ["qos", "queue"] {
+        #[cfg(debug_assertions)]
+        let cmd = format!(
+            "sudo ovs-vsctl --bare --columns=_uuid find {table} external_ids:virshle-port={iface}"
+        );
+        #[cfg(not(debug_assertions))]
+        let cmd = format!(
+            "ovs-vsctl --bare --columns=_uuid find {table} external_ids:virshle-port={iface}"
+        );
+        let mut proc = Process::new();
+        let res = proc.stdin(&cmd).run()?;
+        if let Some(stdout) = res.io.stdout {
+            for uuid in stdout.split_whitespace() {
+                rows.push((table, uuid.to_owned()));
+            }
+        }
+    }
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    #[cfg(debug_assertions)]
+    let mut cmd = vec!["sudo ovs-vsctl".to_owned()];
+    #[cfg(not(debug_assertions))]
+    let mut cmd = vec!["ovs-vsctl".to_owned()];
+    cmd.push(format!("--if-exists clear port {iface} qos"));
+    for (table, uuid) in rows {
+        cmd.push(format!("--if-exists destroy {table} {uuid}"));
+    }
+    let cmd = cmd.join(" -- ");
+
+    let mut proc = Process::new();
+    let res = proc.stdin(&cmd).run()?;
+    if let Some(stderr) = res.io.stderr {
+        let message = "Ovs command failed.";
+        let help = format!("{}\n{} ", stderr, &res.io.stdin.unwrap());
+        return Err(LibError::builder().msg(message).help(&help).build().into());
+    }
+    Ok(())
+}
+
 impl OvsPort {
     pub fn is_virshle_port(&self) -> bool {
         self.name.starts_with("vm-")
@@ -96,8 +199,6 @@ impl OvsPort {
      * Remove network port from the vm switch.
      */
     pub fn delete(&self) -> Result<(), VirshleError> {
-        let vm_bridge_name = OvsBridge::get_vm_switch()?.name;
-
         #[cfg(debug_assertions)]
         let mut cmd = format!("sudo ovs-vsctl");
         #[cfg(not(debug_assertions))]
@@ -145,38 +246,61 @@ impl OvsBridge {
         }
     }
     /*
-     * Return the virshle managed switch.
-     * This is the switch where the vm are plugged in.
+     * Return a virshle managed switch by name.
      */
-    pub fn get_vm_switch() -> Result<OvsBridge, VirshleError> {
-        let vm_bridge_name = "br0";
+    pub fn get_switch(name: &str) -> Result<OvsBridge, VirshleError> {
         let bridges: Vec<OvsBridge> = Self::get_all()?;
 
-        let bridge = bridges.iter().find(|e| {
-            e.ports
-                .iter()
-                .find(|e| e.interface.name == vm_bridge_name)
-                .is_some()
-        });
+        let bridge = bridges
+            .iter()
+            .find(|e| e.ports.iter().find(|e| e.interface.name == name).is_some());
 
         match bridge {
             Some(v) => Ok(v.to_owned()),
             None => {
-                let message = "Couldn't identify the vm dedicated bridge";
-                let help = "Did you set up a vm virtual switch correctly?";
-                return Err(LibError::builder().msg(message).help(help).build().into());
+                let message = format!("Couldn't identify the vm switch {:#?}", name);
+                let help = "Did you set up the vm virtual switches with `v node init --net`?";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
             }
         }
     }
     /*
-     * Creates vm dedicated switch to plug vm port in.
+     * Return the default virshle managed switch.
+     * This is the switch where the vm are plugged in,
+     * unless their network says otherwise.
+     */
+    pub fn get_vm_switch() -> Result<OvsBridge, VirshleError> {
+        let switch = Config::get()?.network.get_switch(None)?;
+        Self::get_switch(&switch.name)
+    }
+    /*
+     * Creates a vm dedicated switch to plug vm port in.
      */
-    pub fn set_vm_switch() -> Result<(), VirshleError> {
-        info!("Create a virtual switch for virtual machines.");
-        let vm_bridge_name = "br0";
+    pub fn set_switch(switch: &SwitchConfig) -> Result<(), VirshleError> {
+        info!(
+            "Create a virtual switch {:#?} for virtual machines.",
+            switch.name
+        );
+        let _type = match switch._type {
+            SwitchType::System => request::OvsBridgeType::System,
+            SwitchType::Netdev => request::OvsBridgeType::Netdev,
+        };
+
+        request::OvsRequest::bridge(&switch.name)
+            ._type(_type)
+            .create()
+            .build()
+            .exec()?;
 
-        request::OvsRequest::bridge(vm_bridge_name)
-            ._type(request::OvsBridgeType::System)
+        Ok(())
+    }
+    /*
+     * Plug a host interface into the switch for external connectivity.
+     */
+    pub fn set_uplink(&self, interface: &str) -> Result<(), VirshleError> {
+        request::OvsRequest::interface(interface)
+            ._type(request::OvsInterfaceType::System)
+            .bridge(&self.name)
             .create()
             .build()
             .exec()?;
@@ -214,31 +338,73 @@ impl OvsBridge {
  * Split host main network interface to provide connectivity to vms.
  * see: ./README.md
  */
-pub async fn ensure_switches() -> Result<(), VirshleError> {
-    // Not fully implemented.
-    // Consider there is already a main ovs switch on host
-    // and link it to vm switch.
+pub async fn ensure_switches(network: &NetworkConfig) -> Result<(), VirshleError> {
+    // Create every switch first, so patches can target any of them.
+    for switch in network.switches() {
+        OvsBridge::set_switch(&switch)?;
+    }
+    for switch in network.switches() {
+        let bridge = OvsBridge::get_switch(&switch.name)?;
+        bridge.remove_orphan_ports().await?;
 
-    // Will do the job for now.
-    OvsBridge::set_vm_switch()?;
-    OvsBridge::get_vm_switch()?.remove_orphan_ports().await?;
+        if let Some(uplink) = &switch.uplink {
+            bridge.set_uplink(uplink)?;
+        }
+        if let Some(target) = &switch.patch {
+            patch_switches(&switch.name, target)?;
+        }
+    }
 
-    match patch_vm_and_main_switches() {
-        Err(e) => {
-            error!("{}", e);
+    // No switch declared:
+    // Consider there is already a main ovs switch on host
+    // and link it to vm switch.
+    if network.switch.is_empty() {
+        match patch_vm_and_main_switches() {
+            Err(e) => {
+                error!("{}", e);
+            }
+            Ok(()) => {}
         }
-        Ok(()) => {}
     }
 
     info!("Created virshle ovs switches.");
     Ok(())
 }
 
+/*
+ * Link two switches with a patch cable.
+ */
+pub fn patch_switches(switch: &str, target: &str) -> Result<(), VirshleError> {
+    // Ovs interface names are global, so name them after both ends.
+    let patch_target = format!("patch_{target}_{switch}");
+    let patch_switch = format!("patch_{switch}_{target}");
+
+    // - add patch cable to target switch (1/2)
+    request::OvsRequest::interface(&patch_target)
+        ._type(request::OvsInterfaceType::Patch)
+        .bridge(target)
+        .peer(&patch_switch)
+        .create()
+        .build()
+        .exec()?;
+
+    // - add patch cable to switch (2/2)
+    request::OvsRequest::interface(&patch_switch)
+        ._type(request::OvsInterfaceType::Patch)
+        .bridge(switch)
+        .peer(&patch_target)
+        .create()
+        .build()
+        .exec()?;
+
+    Ok(())
+}
+
 /*
  * Link the vm switch to a main switch (if any) for internet connectivity.
  */
 pub fn patch_vm_and_main_switches() -> Result<(), VirshleError> {
-    let vm_bridge_name = "br0";
+    let vm_bridge_name = DEFAULT_SWITCH;
     let main_bridge_name = OvsBridge::get_main_switch()?.name;
 
     let patch_main = format!("patch_{vm_bridge_name}");
@@ -285,7 +451,7 @@ mod test {
     // Create main switch.
     #[tokio::test]
     async fn test_ovs_config_host() -> Result<()> {
-        ensure_switches().await?;
+        ensure_switches(&Config::get()?.network).await?;
         Ok(())
     }
 
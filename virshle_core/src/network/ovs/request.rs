@@ -18,6 +18,9 @@ pub enum OvsAction {
     Delete,
     Get,
 }
+/// Default burst size (in bits) of shaped ports, the ovs policing one.
+pub const DEFAULT_QOS_BURST: u64 = 8_000_000;
+
 /*
 * The different type of network interface in ovs.
 */
@@ -93,6 +96,9 @@ impl OvsBridgeBuilder {
             }
             OvsAction::Create => {
                 cmd.push(format!("--may-exist add-br {}", self.bridge));
+                if let OvsBridgeType::Netdev = self._type {
+                    cmd.push(format!("set bridge {} datapath_type=netdev", self.bridge));
+                }
             }
             OvsAction::Delete => {
                 cmd.push(format!("--if-exists del-br {}", self.bridge));
@@ -129,6 +135,16 @@ pub struct OvsInterfaceBuilder {
     // For dpdkvhostuser* type interfaces
     socket_path: Option<String>,
 
+    // Vlan access tag
+    tag: Option<u16>,
+    // Vlan trunks
+    trunks: Option<Vec<u16>>,
+
+    // Rate limit of traffic received from the interface (kbps, kb)
+    policing: Option<(u64, u64)>,
+    // Rate limit of traffic sent to the interface (bps, bits)
+    qos: Option<(u64, Option<u64>)>,
+
     // Final command
     stdin: String,
 }
@@ -167,6 +183,35 @@ impl OvsInterfaceBuilder {
         self.socket_path = Some(path.to_string());
         self
     }
+    /*
+     * Make the port an access port of a vlan.
+     */
+    pub fn tag(&mut self, tag: u16) -> &mut Self {
+        self.tag = Some(tag);
+        self
+    }
+    /*
+     * Make the port a trunk port of vlans.
+     */
+    pub fn trunks(&mut self, trunks: &[u16]) -> &mut Self {
+        self.trunks = Some(trunks.to_vec());
+        self
+    }
+    /*
+     * Drop traffic received from the port above a rate (in kbps),
+     * 0 to remove the limit.
+     */
+    pub fn policing(&mut self, rate: u64, burst: u64) -> &mut Self {
+        self.policing = Some((rate, burst));
+        self
+    }
+    /*
+     * Shape traffic sent to the port to a rate (in bps).
+     */
+    pub fn qos(&mut self, rate: u64, burst: Option<u64>) -> &mut Self {
+        self.qos = Some((rate, burst));
+        self
+    }
 
     pub fn build(&mut self) -> Self {
         let mut cmd: Vec<String> = vec![];
@@ -201,6 +246,46 @@ impl OvsInterfaceBuilder {
                     } else {
                         cmd.push(format!("set interface {iface} type={_type}"));
                     }
+
+                    if let Some(tag) = &self.tag {
+                        cmd.push(format!("set port {iface} tag={tag}"));
+                    }
+                    if let Some(trunks) = &self.trunks {
+                        let trunks = trunks
+                            .iter()
+                            .map(|e| e.to_string())
+                            .collect::<Vec<String>>()
+                            .join(",");
+                        cmd.push(format!("set port {iface} trunks={trunks}"));
+                    }
+                    if let Some((rate, burst)) = &self.policing {
+                        cmd.push(format!(
+                            "set interface {iface} ingress_policing_rate={rate} ingress_policing_burst={burst}"
+                        ));
+                    }
+                    if let Some((rate, burst)) = &self.qos {
+                        // Tag rows to find and destroy them along with the port.
+                        let tag = format!("external_ids:virshle-port={iface}");
+                        cmd.push(format!("set port {iface} qos=@qos"));
+                        if self._type == OvsInterfaceType::DpdkVhostUserClient {
+                            // Userspace switches only police, in bytes.
+                            let cbs = burst.unwrap_or(DEFAULT_QOS_BURST) / 8;
+                            cmd.push(format!(
+                                "--id=@qos create qos type=egress-policer {tag} other-config:cir={} other-config:cbs={cbs}",
+                                rate / 8
+                            ));
+                        } else {
+                            cmd.push(format!(
+                                "--id=@qos create qos type=linux-htb {tag} other-config:max-rate={rate} queues:0=@queue"
+                            ));
+                            let burst = burst
+                                .map(|e| format!(" other-config:burst={e}"))
+                                .unwrap_or_default();
+                            cmd.push(format!(
+                                "--id=@queue create queue {tag} other-config:max-rate={rate}{burst}"
+                            ));
+                        }
+                    }
                 }
             }
             OvsAction::Delete => {
@@ -255,6 +340,10 @@ impl OvsRequest {
             stdin: "".to_string(),
             peer: None,
             socket_path: None,
+            tag: None,
+            trunks: None,
+            policing: None,
+            qos: None,
         }
     }
 }
@@ -284,6 +373,72 @@ mod tests {
         Ok(())
     }
     #[test]
+    fn create_ovs_vlan_ports() -> Result<()> {
+        let req = OvsRequest::interface("vm-tap1")
+            ._type(OvsInterfaceType::System)
+            .bridge("br0")
+            .tag(42)
+            .create()
+            .build();
+        assert_str_eq!(
+            "sudo ovs-vsctl -- --may-exist add-port br0 vm-tap1 \
+            -- set interface vm-tap1 type=system \
+            -- set port vm-tap1 tag=42",
+            req.stdin,
+        );
+        let req = OvsRequest::interface("vm-tap2")
+            ._type(OvsInterfaceType::System)
+            .bridge("br0")
+            .trunks(&[10, 20])
+            .create()
+            .build();
+        assert_str_eq!(
+            "sudo ovs-vsctl -- --may-exist add-port br0 vm-tap2 \
+            -- set interface vm-tap2 type=system \
+            -- set port vm-tap2 trunks=10,20",
+            req.stdin,
+        );
+        Ok(())
+    }
+    #[test]
+    fn create_ovs_rate_limited_ports() -> Result<()> {
+        let req = OvsRequest::interface("vm-tap1")
+            ._type(OvsInterfaceType::System)
+            .bridge("br0")
+            .policing(50_000, 0)
+            .qos(100_000_000, None)
+            .create()
+            .build();
+        assert_str_eq!(
+            "sudo ovs-vsctl -- --may-exist add-port br0 vm-tap1 \
+            -- set interface vm-tap1 type=system \
+            -- set interface vm-tap1 ingress_policing_rate=50000 ingress_policing_burst=0 \
+            -- set port vm-tap1 qos=@qos \
+            -- --id=@qos create qos type=linux-htb external_ids:virshle-port=vm-tap1 \
+            other-config:max-rate=100000000 queues:0=@queue \
+            -- --id=@queue create queue external_ids:virshle-port=vm-tap1 \
+            other-config:max-rate=100000000",
+            req.stdin,
+        );
+        let req = OvsRequest::interface("vm-dpdk1")
+            ._type(OvsInterfaceType::DpdkVhostUserClient)
+            .socket_path("/tmp/vm.sock")
+            .bridge("br1")
+            .qos(100_000_000, Some(8_000))
+            .create()
+            .build();
+        assert_str_eq!(
+            "sudo ovs-vsctl -- --may-exist add-port br1 vm-dpdk1 \
+            -- set interface vm-dpdk1 type=dpdkvhostuserclient \
+            options:vhost-server-path=/tmp/vm.sock \
+            -- set port vm-dpdk1 qos=@qos \
+            -- --id=@qos create qos type=egress-policer external_ids:virshle-port=vm-dpdk1 \
+            other-config:cir=12500000 other-config:cbs=1000",
+            req.stdin,
+        );
+        Ok(())
+    }
+    #[test]
     fn delete_ovs_port() -> Result<()> {
         let req = OvsRequest::interface("br0p1").delete().build();
         assert_str_eq!("sudo ovs-vsctl -- --if-exists del-port br0p1", req.stdin,);
@@ -303,6 +458,19 @@ mod tests {
         Ok(())
     }
     #[test]
+    fn create_ovs_netdev_bridge() -> Result<()> {
+        let req = OvsRequest::bridge("br1")
+            ._type(OvsBridgeType::Netdev)
+            .create()
+            .build();
+        assert_str_eq!(
+            "sudo ovs-vsctl -- --may-exist add-br br1 \
+            -- set bridge br1 datapath_type=netdev",
+            req.stdin,
+        );
+        Ok(())
+    }
+    #[test]
     fn delete_ovs_bridge() -> Result<()> {
         let req = OvsRequest::bridge("br0").delete().build();
         assert_str_eq!("sudo ovs-vsctl -- --if-exists del-br br0", req.stdin,);
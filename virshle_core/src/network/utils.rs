@@ -1,4 +1,5 @@
 use macaddr::MacAddr6;
+use sha2::{Digest, Sha256};
 use std::str::FromStr;
 use uuid::Uuid;
 
@@ -55,6 +56,60 @@ pub fn uuid_to_mac(uuid: &Uuid) -> MacAddr6 {
     mac
 }
 
+/// Convert Vm uuid and network name to a predictable mac address,
+/// so every Vm network interface gets its own.
+pub fn uuid_to_net_mac(uuid: &Uuid, net_name: &str) -> MacAddr6 {
+    let digest = Sha256::new()
+        .chain_update(uuid.as_bytes())
+        .chain_update(net_name.as_bytes())
+        .finalize();
+    let mut bytes = [0u8; 6];
+    bytes.copy_from_slice(&digest[..6]);
+    // Same rfc complient hardware address prefix as uuid_to_mac
+    // (locally administered unicast).
+    bytes[0] = (bytes[0] & 0xf0) | 0x0e;
+    let mac = MacAddr6::from(bytes);
+
+    trace!(
+        "converted uuid: {:#?} and network: {:#?} to mac: {:#?}",
+        uuid.to_string(),
+        net_name,
+        mac.to_string()
+    );
+    mac
+}
+
+/// Convert a human readable bit rate or size (ex: 100Mbit) to bits.
+/// Units are decimal, like tc ones.
+pub fn reverse_human_bits(string: &str) -> Result<u64, VirshleError> {
+    let string = string.replace(" ", "").replace("_", "");
+    let units: [(&str, u64); 5] = [
+        ("Tbit", u64::pow(1000, 4)),
+        ("Gbit", u64::pow(1000, 3)),
+        ("Mbit", u64::pow(1000, 2)),
+        ("kbit", 1000),
+        ("bit", 1),
+    ];
+    for (unit, factor) in units {
+        if let Some(num) = string.strip_suffix(unit) {
+            let int: u64 = num.parse()?;
+            return match int.checked_mul(factor) {
+                Some(v) => Ok(v),
+                None => {
+                    let message = format!("Bit rate or size {:#?} is too large.", string);
+                    let help = "Must be below 18446744Tbit.";
+                    Err(LibError::builder().msg(&message).help(help).build().into())
+                }
+            };
+        }
+    }
+    Err(LibError::builder()
+        .msg("Couldn't convert human readable string to bits")
+        .help("Must be of the form 1Gbit, 100Mbit, 500kbit or 1bit")
+        .build()
+        .into())
+}
+
 /// Convert Vm uuid to predictable dhcp duid-uuid.
 pub fn uuid_to_duid(uuid: &Uuid) -> String {
     let uuid_origin = uuid.to_string();
@@ -103,6 +158,25 @@ mod test {
         Ok(())
     }
     #[test]
+    fn test_uuid_to_net_mac() -> Result<()> {
+        let uuid = Uuid::parse_str("c37b3266-9c59-42bb-8ecf-bdd643236a78").unwrap();
+        let main = uuid_to_net_mac(&uuid, "main");
+        let storage = uuid_to_net_mac(&uuid, "storage");
+        assert_eq!(main.to_string(), "7E:BE:97:80:0F:0A");
+        assert_eq!(storage.to_string(), "DE:CB:BB:92:95:A5");
+        assert_eq!(main, uuid_to_net_mac(&uuid, "main"));
+        Ok(())
+    }
+    #[test]
+    fn test_reverse_human_bits() -> Result<()> {
+        assert_eq!(reverse_human_bits("100Mbit")?, 100_000_000);
+        assert_eq!(reverse_human_bits("1 Gbit")?, 1_000_000_000);
+        assert_eq!(reverse_human_bits("500kbit")?, 500_000);
+        assert!(reverse_human_bits("100MiB").is_err());
+        assert!(reverse_human_bits("20000000Tbit").is_err());
+        Ok(())
+    }
+    #[test]
     fn test_uuid_to_duid() -> Result<()> {
         let uuid = Uuid::parse_str("c37b3266-9c59-42bb-8ecf-bdd643236a78").unwrap();
         let duid = uuid_to_duid(&uuid);
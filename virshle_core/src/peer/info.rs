@@ -6,6 +6,7 @@ use crate::config::{MAX_CPU_RESERVATION, MAX_DISK_RESERVATION, MAX_RAM_RESERVATI
 use crate::config::init::MANAGED_DIR;
 
 use crate::hypervisor::disk::utils;
+use crate::hypervisor::Disk;
 
 use std::path::Path;
 
@@ -79,6 +80,113 @@ impl NodeInfo {
             Ok(())
         }
     }
+    /// Check the node can afford a copy of an existing vm.
+    pub async fn can_clone_vm(&self, vm: &Vm) -> Result<(), VirshleError> {
+        // Check saturation
+        if self.host_info.disk.is_saturated().await?
+            || self.host_info.ram.is_saturated().await?
+            || self.host_info.cpu.is_saturated().await?
+        {
+            return Err(LibError::builder()
+                .msg("Not allowed to clone VM: node is saturated.")
+                .help("Try deleting unused VMs or change saturation indexes in config.")
+                .build()
+                .into());
+        }
+        // Check remaining disk space
+        let disks_total_size: u64 = vm
+            .disk
+            .iter()
+            .map(|e| e.get_reserved_size().unwrap_or(0))
+            .sum();
+        if disks_total_size < self.host_info.disk.available {
+            Ok(())
+        } else {
+            let help = format!("Not enough disk space for a copy of vm {:#?}", vm.name);
+            warn!("{}", help);
+            Err(LibError::builder()
+                .msg("Couldn't clone Vm")
+                .help(&help)
+                .build()
+                .into())
+        }
+    }
+    /// Check the node can afford to grow a vm to the requested vcpu and ram (in bytes).
+    /// Shrinking a vm is always allowed.
+    pub async fn can_resize_vm(
+        &self,
+        vm: &Vm,
+        vcpu: Option<u64>,
+        vram: Option<u64>,
+    ) -> Result<(), VirshleError> {
+        let cpu = &self.host_info.cpu;
+        if let Some(vcpu) = vcpu {
+            if vcpu > vm.vcpu {
+                let reserved = cpu.reserved + vcpu - vm.vcpu;
+                if reserved as f64 / cpu.number as f64 * 100.0 >= MAX_CPU_RESERVATION {
+                    let help = format!(
+                        "Not enough cpu left to grow vm {:#?} to {} vcpu.",
+                        vm.name, vcpu
+                    );
+                    warn!("{}", help);
+                    return Err(LibError::builder()
+                        .msg("Not allowed to resize VM: node would be saturated.")
+                        .help(&help)
+                        .build()
+                        .into());
+                }
+            }
+        }
+        let ram = &self.host_info.ram;
+        if let Some(vram) = vram {
+            let current = utils::reverse_human_bytes(&vm.vram)?;
+            if vram > current {
+                let reserved = ram.reserved + vram - current;
+                if reserved as f64 / ram.total as f64 * 100.0 >= MAX_RAM_RESERVATION {
+                    let help = format!(
+                        "Not enough ram left to grow vm {:#?} to {}.",
+                        vm.name,
+                        utils::human_bytes(&vram)?
+                    );
+                    warn!("{}", help);
+                    return Err(LibError::builder()
+                        .msg("Not allowed to resize VM: node would be saturated.")
+                        .help(&help)
+                        .build()
+                        .into());
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Check the node can afford to grow a vm disk to the requested size (in bytes).
+    pub async fn can_resize_disk(
+        &self,
+        vm: &Vm,
+        disk: &Disk,
+        size: u64,
+    ) -> Result<(), VirshleError> {
+        let host_disk = &self.host_info.disk;
+        let current = disk.get_reserved_size()?;
+        if size > current {
+            let reserved = host_disk.reserved + size - current;
+            if reserved as f64 / host_disk.size as f64 * 100.0 >= MAX_DISK_RESERVATION {
+                let help = format!(
+                    "Not enough disk space left to grow disk {:#?} of vm {:#?} to {}.",
+                    disk.name,
+                    vm.name,
+                    utils::human_bytes(&size)?
+                );
+                warn!("{}", help);
+                return Err(LibError::builder()
+                    .msg("Not allowed to resize disk: node would be saturated.")
+                    .help(&help)
+                    .build()
+                    .into());
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -132,8 +240,8 @@ impl HostRam {
         let vms = Vm::database().await?.many().get().await?;
         let total_ram: u64 = vms
             .iter()
-            .map(|e| utils::reverse_human_bytes(&e.vram).unwrap())
-            .sum();
+            .map(|e| utils::reverse_human_bytes(&e.vram))
+            .sum::<Result<u64, VirshleError>>()?;
         Ok(total_ram)
     }
     pub async fn get() -> Result<Self, VirshleError> {
@@ -256,16 +364,20 @@ impl HostDisk {
     }
     pub async fn get_reserved() -> Result<u64, VirshleError> {
         let vms = Vm::database().await?.many().get().await?;
-        let n_cpus: u64 = vms
-            .iter()
-            .map(|e| {
-                e.disk
-                    .iter()
-                    .map(|d| d.get_size().unwrap_or(0))
-                    .sum::<u64>()
-            })
-            .sum();
-        Ok(n_cpus)
+        let mut total_size: u64 = 0;
+        for vm in &vms {
+            for disk in &vm.disk {
+                // A single broken disk must not block the whole node.
+                match disk.get_reserved_size() {
+                    Ok(size) => total_size += size,
+                    Err(e) => warn!(
+                        "couldn't get size of disk {:#?} of vm {:#?}: {}",
+                        disk.name, vm.name, e
+                    ),
+                };
+            }
+        }
+        Ok(total_size)
     }
     pub async fn get_percentage_reserved(&self) -> Result<f64, VirshleError> {
         let res = self.reserved as f64 / self.size as f64 * 100.0;
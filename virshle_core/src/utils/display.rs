@@ -78,6 +78,13 @@ pub fn display_some_num(num: &Option<u64>) -> String {
         format!("")
     }
 }
+pub fn display_some_string(string: &Option<String>) -> String {
+    if let Some(string) = string {
+        string.to_owned()
+    } else {
+        format!("")
+    }
+}
 pub fn display_some_bool(b: &Option<bool>) -> String {
     if let Some(b) = b {
         format!("{}", b)
@@ -110,6 +110,11 @@ pub enum VirshleError {
     #[serde(skip)]
     HyprHttpError(#[from] hyper::http::Error),
 
+    #[error(transparent)]
+    #[diagnostic(code(reqwest::error))]
+    #[serde(skip)]
+    ReqwestError(#[from] reqwest::Error),
+
     // Env var error
     // Mainly use to get ssh_auth_agent socket.
     #[error(transparent)]
@@ -0,0 +1,41 @@
+//!
+//! Remember whether a vm is meant to be running,
+//! to boot autostart vms back after a host reboot.
+//!
+
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Vm::Table)
+                    .add_column(boolean(Vm::Running).default(false))
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Vm::Table)
+                    .drop_column(Vm::Running)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden, Debug)]
+enum Vm {
+    Table,
+    Running,
+}
@@ -0,0 +1,90 @@
+//!
+//! Add the image cache index,
+//! and the junction table that tracks which vm disk uses which image.
+//!
+
+use crate::create_table::Vm;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Cached base images
+        manager
+            .create_table(
+                Table::create()
+                    .table(Image::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Image::Id))
+                    .col(string_uniq(Image::Name))
+                    .col(string(Image::Source))
+                    .col(string(Image::Sha256))
+                    .col(string(Image::Path))
+                    .col(big_integer(Image::Size))
+                    .col(date_time(Image::CreatedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Junction table Vm_Image
+        // An image ref-count is its number of rows in this table.
+        manager
+            .create_table(
+                Table::create()
+                    .table(VmImage::Table)
+                    .if_not_exists()
+                    .primary_key(Index::create().col(VmImage::VmId).col(VmImage::DiskName))
+                    .col(integer(VmImage::VmId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("vm_id")
+                            .from(VmImage::Table, VmImage::VmId)
+                            .to(Vm::Table, Vm::Id),
+                    )
+                    .col(integer(VmImage::ImageId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("image_id")
+                            .from(VmImage::Table, VmImage::ImageId)
+                            .to(Image::Table, Image::Id),
+                    )
+                    .col(string(VmImage::DiskName))
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(VmImage::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Image::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden, Debug)]
+pub enum Image {
+    Table,
+    Id,
+    Name,
+    Source,
+    Sha256,
+    Path,
+    Size,
+    CreatedAt,
+}
+
+/// Junction table
+#[derive(DeriveIden, Debug)]
+pub enum VmImage {
+    Table,
+    VmId,
+    ImageId,
+    DiskName,
+}
@@ -0,0 +1,66 @@
+//!
+//! Add the vm snapshot table.
+//!
+
+use crate::create_table::Vm;
+use sea_orm_migration::{prelude::*, schema::*};
+use sea_query::Index;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Vm snapshots list
+        manager
+            .create_table(
+                Table::create()
+                    .table(Snapshot::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Snapshot::Id))
+                    .col(integer(Snapshot::VmId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("vm_id")
+                            .from(Snapshot::Table, Snapshot::VmId)
+                            .to(Vm::Table, Vm::Id),
+                    )
+                    .col(string(Snapshot::Tag))
+                    .col(string(Snapshot::Path))
+                    .col(date_time(Snapshot::CreatedAt))
+                    .to_owned(),
+            )
+            .await?;
+        // A tag is unique per vm.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_snapshot_vm_id_tag")
+                    .table(Snapshot::Table)
+                    .col(Snapshot::VmId)
+                    .col(Snapshot::Tag)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Snapshot::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden, Debug)]
+pub enum Snapshot {
+    Table,
+    Id,
+    VmId,
+    Tag,
+    Path,
+    CreatedAt,
+}
@@ -1,5 +1,8 @@
 pub use sea_orm_migration::prelude::*;
 
+mod add_vm_running_column;
+mod create_image_table;
+mod create_snapshot_table;
 mod create_table;
 pub use create_table::*;
 
@@ -8,6 +11,11 @@ pub struct Migrator;
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(create_table::Migration)]
+        vec![
+            Box::new(create_table::Migration),
+            Box::new(create_snapshot_table::Migration),
+            Box::new(create_image_table::Migration),
+            Box::new(add_vm_running_column::Migration),
+        ]
     }
 }
@@ -5,9 +5,12 @@ use crate::connection::{Connection, ConnectionHandle};
 pub use response::Response;
 
 // Http
-use http_body_util::Full;
-use hyper::body::{Bytes, Incoming};
+use futures::{Stream, TryStreamExt};
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
 use hyper::client::conn::http1;
+use hyper::header::{CONNECTION, UPGRADE};
+use hyper::upgrade::Upgraded;
 use hyper::{Request, Response as HyperResponse, StatusCode};
 use hyper_util::rt::TokioIo;
 
@@ -60,10 +63,57 @@ impl RestClient {
             endpoint.to_owned()
         }
     }
+    /// Send the http request, whatever its body.
+    /// The request is consumed as streamed bodies can't be cloned.
+    #[tracing::instrument(skip_all)]
+    async fn send_body(
+        &mut self,
+        endpoint: &str,
+        request: Request<Body>,
+    ) -> Result<Response, VirshleError> {
+        // Ensure connection is open and has a stream handle.
+        self.open().await?;
+
+        if let Some(handle) = &mut self.handle {
+            let response: Result<HyperResponse<Incoming>, _> =
+                handle.sender.send_request(request).await;
+            match response {
+                Ok(response) => {
+                    let status: StatusCode = response.status();
+                    let response: Response = Response::new(&endpoint, response);
+                    trace!("{:#?}", response);
+
+                    if !status.is_success() {
+                        let status = status.to_string();
+                        error!("{}", status);
+                    }
+
+                    Ok(response)
+                }
+                Err(e) => {
+                    error!("{:#?}", e);
+                    Err(e.into())
+                }
+            }
+        } else {
+            let err = LibError::builder()
+                .msg("Connection has no handler.")
+                .help("open connection first.")
+                .build();
+            return Err(err.into());
+        }
+    }
 }
 
+/// A raw bidirectional stream,
+/// obtained after an http connection upgrade.
+pub type UpgradedStream = TokioIo<Upgraded>;
+
+/// Request body, either sent at once or streamed.
+pub type Body = UnsyncBoxBody<Bytes, std::io::Error>;
+
 pub struct StreamHandle {
-    sender: http1::SendRequest<Full<Bytes>>,
+    sender: http1::SendRequest<Body>,
     connection: JoinHandle<Result<(), hyper::Error>>,
 }
 
@@ -117,6 +167,28 @@ pub trait Rest {
     ) -> impl Future<Output = Result<Response, VirshleError>> + Send
     where
         T: Serialize + Send;
+    /// Send an http PUT request to socket,
+    /// with a body streamed chunk by chunk (ex: a file upload).
+    /// # Arguments:
+    /// - path: the url enpoint (ex:"/vm/import")
+    fn put_stream<S>(
+        &mut self,
+        enpoint: &str,
+        body: S,
+    ) -> impl Future<Output = Result<Response, VirshleError>> + Send
+    where
+        S: Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static;
+    /// Send an http upgrade request to socket,
+    /// and return the raw stream once the protocol is switched.
+    /// The connection can't be used for further requests.
+    /// # Arguments:
+    /// - path: the url enpoint (ex:"/vm/console")
+    /// - protocol: the protocol to switch to (ex: "virshle-console")
+    fn upgrade(
+        &mut self,
+        enpoint: &str,
+        protocol: &str,
+    ) -> impl Future<Output = Result<UpgradedStream, VirshleError>> + Send;
 }
 
 impl Rest for RestClient {
@@ -141,38 +213,10 @@ impl Rest for RestClient {
         request: &Request<Full<Bytes>>,
     ) -> Result<Response, VirshleError> {
         trace!("{:#?}", request);
-
-        // Ensure connection is open and has a stream handle.
-        self.open().await?;
-
-        if let Some(handle) = &mut self.handle {
-            let response: Result<HyperResponse<Incoming>, _> =
-                handle.sender.send_request(request.to_owned()).await;
-            match response {
-                Ok(response) => {
-                    let status: StatusCode = response.status();
-                    let response: Response = Response::new(&endpoint, response);
-                    trace!("{:#?}", response);
-
-                    if !status.is_success() {
-                        let status = status.to_string();
-                        error!("{}", status);
-                    }
-
-                    Ok(response)
-                }
-                Err(e) => {
-                    error!("{:#?}", e);
-                    Err(e.into())
-                }
-            }
-        } else {
-            let err = LibError::builder()
-                .msg("Connection has no handler.")
-                .help("open connection first.")
-                .build();
-            return Err(err.into());
-        }
+        let request = request
+            .to_owned()
+            .map(|body| body.map_err(|e| match e {}).boxed_unsync());
+        self.send_body(endpoint, request).await
     }
 
     /// Test http enpoint responsiveness after connection is open.
@@ -189,14 +233,18 @@ impl Rest for RestClient {
             .method("GET")
             // .header("Host", "localhost")
             .header("server", "Virshle API")
-            .body(Full::new(Bytes::new()))?;
+            .body(
+                Full::new(Bytes::new())
+                    .map_err(|e| match e {})
+                    .boxed_unsync(),
+            )?;
 
         if let Some(handle) = &mut self.handle {
             // Timeout reponse and return succesfully if a response is sent,
             // Wether it is a succesful response or an error message.
             let time: u64 = 1000;
 
-            let response = handle.sender.send_request(request.to_owned());
+            let response = handle.sender.send_request(request);
             let _response = timeout(time::Duration::from_millis(time), response).await;
             match _response {
                 Ok(_) => Ok(()),
@@ -270,6 +318,52 @@ impl Rest for RestClient {
         };
         self.send(&endpoint, &request?).await
     }
+
+    async fn put_stream<S>(&mut self, endpoint: &str, body: S) -> Result<Response, VirshleError>
+    where
+        S: Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static,
+    {
+        let endpoint = self.make_endpoint(endpoint);
+        let body = StreamBody::new(body.map_ok(Frame::data)).boxed_unsync();
+        let request = Request::builder()
+            .uri(&endpoint)
+            .method("PUT")
+            .header("server", "Virshle API")
+            .header("Content-Type", "application/octet-stream")
+            .body(body)?;
+        self.send_body(&endpoint, request).await
+    }
+
+    async fn upgrade(
+        &mut self,
+        endpoint: &str,
+        protocol: &str,
+    ) -> Result<UpgradedStream, VirshleError> {
+        let endpoint = self.make_endpoint(endpoint);
+        let request = Request::builder()
+            .uri(&endpoint)
+            .method("GET")
+            .header("server", "Virshle API")
+            .header(CONNECTION, "upgrade")
+            .header(UPGRADE, protocol)
+            .body(Full::new(Bytes::new()));
+
+        let response = self.send(&endpoint, &request?).await?;
+        // Upgraded connection is handed over to the caller.
+        self.handle = None;
+
+        if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+            // Surface the api error if any.
+            response.to_value::<serde_json::Value>().await?;
+            let message = format!("Couldn't switch to protocol {:#?}.", protocol);
+            let help = format!("Does endpoint {:#?} support upgrades?", endpoint);
+            let err = LibError::builder().msg(&message).help(&help).build();
+            return Err(err.into());
+        }
+        let upgraded = hyper::upgrade::on(response.inner).await?;
+        trace!("upgraded connection to {}", protocol);
+        Ok(TokioIo::new(upgraded))
+    }
 }
 
 pub async fn handshake(stream: Stream) -> Result<StreamHandle, VirshleError> {
@@ -280,7 +374,7 @@ pub async fn handshake(stream: Stream) -> Result<StreamHandle, VirshleError> {
                 Ok((sender, connection)) => {
                     let handle = StreamHandle {
                         sender,
-                        connection: spawn(async move { connection.await }),
+                        connection: spawn(async move { connection.with_upgrades().await }),
                     };
                     trace!("http1 handshake succeeded");
                     Ok(handle)
@@ -303,7 +397,7 @@ pub async fn handshake(stream: Stream) -> Result<StreamHandle, VirshleError> {
                 Ok((sender, connection)) => {
                     let handle = StreamHandle {
                         sender,
-                        connection: spawn(async move { connection.await }),
+                        connection: spawn(async move { connection.with_upgrades().await }),
                     };
                     trace!("http1 handshake succeeded");
                     Ok(handle)
@@ -326,7 +420,7 @@ pub async fn handshake(stream: Stream) -> Result<StreamHandle, VirshleError> {
                 Ok((sender, connection)) => {
                     let handle = StreamHandle {
                         sender,
-                        connection: spawn(async move { connection.await }),
+                        connection: spawn(async move { connection.with_upgrades().await }),
                     };
                     trace!("http1 handshake succeeded");
                     Ok(handle)
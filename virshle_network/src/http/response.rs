@@ -1,6 +1,7 @@
 use http_body_util::BodyExt;
 use hyper::body::{Bytes, Incoming};
 use hyper::{Request, Response as HyperResponse, StatusCode};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 // Serde
 use convert_case::{Case, Casing};
@@ -35,6 +36,24 @@ impl Response {
         let data = data.to_bytes();
         Ok(data)
     }
+    /// Stream the body into a writer (ex: a file download),
+    /// without holding it whole in memory.
+    /// Return the number of bytes written.
+    pub async fn write_to<W>(self, writer: &mut W) -> Result<u64, VirshleError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut body = self.inner.into_body();
+        let mut size: u64 = 0;
+        while let Some(frame) = body.frame().await {
+            if let Ok(data) = frame?.into_data() {
+                writer.write_all(&data).await?;
+                size += data.len() as u64;
+            }
+        }
+        writer.flush().await?;
+        Ok(size)
+    }
     pub async fn to_string(self) -> Result<String, VirshleError> {
         let data: Bytes = self.into_bytes().await?;
         let value: String = String::from_utf8(data.to_vec())?;
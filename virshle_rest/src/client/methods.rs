@@ -3,17 +3,19 @@ use crate::client::Client;
 use crate::commons::*;
 use virshle_core::{
     config::{ UserData, VmTemplate},
-    hypervisor::{Vm, VmInfo, VmInfoResponse, VmState, VmTable},
+    hypervisor::{PartitionReport, ShutdownMode, Snapshot, Vm, VmInfo, VmInfoResponse, VmState, VmTable},
     peer::{HostInfo, NodeInfo, Peer},
+    Image,
 };
 
 // Connections and Http
-use virshle_network::connection::{Connection, ConnectionHandle, ConnectionState};
-use virshle_network::http::{Rest, RestClient};
+use virshle_network::connection::{Connection, ConnectionHandle, ConnectionState, Uri};
+use virshle_network::http::{Rest, RestClient, UpgradedStream};
 
 use bon::bon;
 use pipelight_exec::Status;
 use rand::seq::IndexedRandom;
+use tokio_util::io::ReaderStream;
 use std::cmp::Ordering;
 use indexmap::IndexMap;
 use uuid::Uuid;
@@ -84,6 +86,9 @@ impl Methods {
     pub fn template(&mut self) -> TemplateMethods<'_> {
         TemplateMethods { api: self }
     }
+    pub fn image(&mut self) -> ImageMethods<'_> {
+        ImageMethods { api: self }
+    }
     pub fn vm(&mut self) -> VmMethods<'_> {
         VmMethods { api: self }
     }
@@ -97,6 +102,9 @@ pub struct PeerMethods<'a> {
 pub struct TemplateMethods<'a> {
     api: &'a mut Methods,
 }
+pub struct ImageMethods<'a> {
+    api: &'a mut Methods,
+}
 pub struct VmMethods<'a> {
     api: &'a mut Methods,
 }
@@ -555,6 +563,111 @@ impl TemplateMethods<'_> {
     }
 }
 
+#[bon]
+impl ImageMethods<'_> {
+    /// List cached images on peers.
+    #[builder(finish_fn = exec)]
+    pub async fn get(
+        &mut self,
+        alias: Option<String>,
+    ) -> Result<IndexMap<Peer, Vec<Image>>, VirshleError> {
+        let mut res: IndexMap<Peer, Vec<Image>> = IndexMap::new();
+        match alias {
+            None => {
+                for (peer, rest) in self.api.peers.values_mut() {
+                    let images = Self::_get(peer, rest).await?;
+                    res.insert(peer.clone(), images);
+                }
+            }
+            Some(alias) => {
+                if let Some((peer, rest)) = self.api.peers.get_mut(&alias) {
+                    let images = Self::_get(peer, rest).await?;
+                    res.insert(peer.clone(), images);
+                }
+            }
+        };
+        Ok(res)
+    }
+    async fn _get(peer: &Peer, rest: &mut RestClient) -> Result<Vec<Image>, VirshleError> {
+        rest.open().await?;
+        rest.ping().await?;
+        let images: Vec<Image> = rest.get("/image/all").await?.to_value().await?;
+        Ok(images)
+    }
+    /// Fetch an image into a peer cache.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn pull(
+        &mut self,
+        source: String,
+        sha256: Option<String>,
+
+        alias: Option<String>,
+    ) -> Result<Image, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = PullImageArgs { source, sha256 };
+        let res: Image = rest
+            .put("/image/pull", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+    /// Remove an unused image from a peer cache.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn rm(
+        &mut self,
+        name: String,
+
+        alias: Option<String>,
+    ) -> Result<Image, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = GetImageArgs { name };
+        let res: Image = rest
+            .put("/image/rm", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+    /// Remove every unused image from a peer cache.
+    #[builder(
+        finish_fn = exec, 
+        on(Option<String>,into)
+    )]
+    pub async fn prune(
+        &mut self,
+        alias: Option<String>,
+    ) -> Result<Vec<Image>, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let res: Vec<Image> = rest
+            .put::<()>("/image/prune", None)
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+}
+
 pub struct VmVmmMethods<'a> {
     api: &'a mut Methods,
 }
@@ -620,6 +733,172 @@ impl VmMethods<'_> {
             .await?;
         Ok(path)
     }
+    /// Attach to a virtual machine serial console.
+    /// Return a raw stream, forwarded to the vm serial port.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn console(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+
+        alias: Option<String>,
+    ) -> Result<UpgradedStream, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(id) = id {
+            query.append_pair("id", &id.to_string());
+        }
+        if let Some(uuid) = uuid {
+            query.append_pair("uuid", &uuid.to_string());
+        }
+        if let Some(name) = name {
+            query.append_pair("name", &name);
+        }
+        let endpoint = format!("/vm/console?{}", query.finish());
+
+        let stream = rest.upgrade(&endpoint, CONSOLE_PROTOCOL).await?;
+        Ok(stream)
+    }
+    /// Create copies of an existing virtual machine.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn clone_as(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        clone_name: Option<String>,
+        n: Option<u8>,
+        user_data: Option<UserData>,
+
+        alias: Option<String>,
+    ) -> Result<IndexMap<Peer, IndexMap<Status, Vec<VmTable>>>, VirshleError> {
+        let mut res: IndexMap<Peer, IndexMap<Status, Vec<VmTable>>> = IndexMap::new();
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+
+        let args = CloneVmArgs {
+            id,
+            uuid,
+            name,
+            clone_name,
+            ntimes: n,
+            user_data,
+        };
+        let vms: IndexMap<Status, Vec<VmTable>> = rest
+            .put("/vm/clone", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        res.insert(peer.clone(), vms);
+        Ok(res)
+    }
+    /// Download a stopped vm as a tar.zst archive into `output`.
+    /// Return the archive size.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn export(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        snapshot: Option<String>,
+        output: String,
+
+        alias: Option<String>,
+    ) -> Result<u64, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(id) = id {
+            query.append_pair("id", &id.to_string());
+        }
+        if let Some(uuid) = uuid {
+            query.append_pair("uuid", &uuid.to_string());
+        }
+        if let Some(name) = name {
+            query.append_pair("name", &name);
+        }
+        if let Some(snapshot) = snapshot {
+            query.append_pair("snapshot", &snapshot);
+        }
+        let endpoint = format!("/vm/export?{}", query.finish());
+
+        let response = rest.get(&endpoint).await?;
+        if !response.status().is_success() {
+            // Surface the api error.
+            response.to_value::<serde_json::Value>().await?;
+        }
+        let mut file = tokio::fs::File::create_new(&output).await?;
+        match response.write_to(&mut file).await {
+            Ok(size) => Ok(size),
+            Err(e) => {
+                // Do not leave a truncated archive behind.
+                tokio::fs::remove_file(&output).await.ok();
+                Err(e)
+            }
+        }
+    }
+    /// Upload a tar.zst archive and recreate the vm it holds.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn import(
+        &mut self,
+        input: String,
+        keep_uuid: Option<bool>,
+        name: Option<String>,
+
+        alias: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(keep_uuid) = keep_uuid {
+            query.append_pair("keep_uuid", &keep_uuid.to_string());
+        }
+        if let Some(name) = name {
+            query.append_pair("name", &name);
+        }
+        let endpoint = format!("/vm/import?{}", query.finish());
+
+        let file = tokio::fs::File::open(&input).await?;
+        let vm: VmTable = rest
+            .put_stream(&endpoint, ReaderStream::new(file))
+            .await?
+            .to_value()
+            .await?;
+        Ok(vm)
+    }
 }
 
 pub struct VmEnsureMethods<'a> {
@@ -1192,19 +1471,25 @@ impl VmShutdownMethods<'_> {
         id: Option<u64>,
         uuid: Option<Uuid>,
         name: Option<String>,
+        /// Seconds given to the guest to power itself off.
+        timeout: Option<u64>,
+        /// Force the vm down if the guest didn't power off in time.
+        force: Option<bool>,
 
         alias: Option<String>,
-    ) -> Result<VmTable, VirshleError> {
+    ) -> Result<(VmTable, ShutdownMode), VirshleError> {
         let mut method = self.api.peer();
         let mut getter = method.get();
         let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
-        let res: VmTable = Self::_one(
+        let res: (VmTable, ShutdownMode) = Self::_one(
             peer,
             rest,
-            Some(GetVmArgs {
+            Some(ShutdownVmArgs {
                 id,
                 uuid,
                 name,
+                timeout,
+                force,
             }),
         )
         .await?;
@@ -1213,36 +1498,46 @@ impl VmShutdownMethods<'_> {
     async fn _one(
         peer: &Peer,
         rest: &mut RestClient,
-        args: Option<GetVmArgs>,
-    ) -> Result<VmTable, VirshleError> {
+        args: Option<ShutdownVmArgs>,
+    ) -> Result<(VmTable, ShutdownMode), VirshleError> {
         rest.open().await?;
         rest.ping().await?;
-        let vm: VmTable = rest
+        let res: (VmTable, ShutdownMode) = rest
             .put("/vm/shutdown", args.clone())
             .await?
             .to_value()
             .await?;
-        Ok(vm)
+        Ok(res)
     }
     /// Bulk operation
-    /// Delete many virtual machine on a node.
+    /// Shutdown many virtual machine on a node.
     #[builder(finish_fn = exec)]
     pub async fn many(
         &mut self,
         state: Option<VmState>,
         account: Option<Uuid>,
+        timeout: Option<u64>,
+        force: Option<bool>,
         alias: Option<String>,
-    ) -> Result<IndexMap<Peer, IndexMap<Status, Vec<VmTable>>>, VirshleError> {
-        let mut res: IndexMap<Peer, IndexMap<Status, Vec<VmTable>>> = IndexMap::new();
+    ) -> Result<
+        IndexMap<Peer, (IndexMap<Status, Vec<VmTable>>, IndexMap<Uuid, ShutdownMode>)>,
+        VirshleError,
+    > {
+        let mut res: IndexMap<
+            Peer,
+            (IndexMap<Status, Vec<VmTable>>, IndexMap<Uuid, ShutdownMode>),
+        > = IndexMap::new();
         let mut method = self.api.peer();
         let mut getter = method.get();
         let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
         let vms = Self::_many(
             peer,
             rest,
-            Some(GetManyVmArgs {
+            Some(ShutdownManyVmArgs {
                 vm_state: state,
                 account_uuid: account,
+                timeout,
+                force,
             }),
         )
         .await?;
@@ -1252,11 +1547,11 @@ impl VmShutdownMethods<'_> {
     async fn _many(
         peer: &Peer,
         rest: &mut RestClient,
-        args: Option<GetManyVmArgs>,
-    ) -> Result<IndexMap<Status, Vec<VmTable>>, VirshleError> {
+        args: Option<ShutdownManyVmArgs>,
+    ) -> Result<(IndexMap<Status, Vec<VmTable>>, IndexMap<Uuid, ShutdownMode>), VirshleError> {
         rest.open().await?;
         rest.ping().await?;
-        let vms: IndexMap<Status, Vec<VmTable>> = rest
+        let vms: (IndexMap<Status, Vec<VmTable>>, IndexMap<Uuid, ShutdownMode>) = rest
             .put("/vm/shutdown.many", args.clone())
             .await?
             .to_value()
@@ -1264,3 +1559,840 @@ impl VmShutdownMethods<'_> {
         Ok(vms)
     }
 }
+pub struct VmRebootMethods<'a> {
+    api: &'a mut Methods,
+}
+impl VmMethods<'_> {
+    pub fn reboot(&mut self) -> VmRebootMethods<'_> {
+        VmRebootMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmRebootMethods<'_> {
+    /// Reboot a virtual machine on a node.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+
+        alias: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        let res: VmTable = Self::_one(
+            peer,
+            rest,
+            Some(GetVmArgs {
+                id,
+                uuid,
+                name,
+            }),
+        )
+        .await?;
+        Ok(res)
+    }
+    async fn _one(
+        peer: &Peer,
+        rest: &mut RestClient,
+        args: Option<GetVmArgs>,
+    ) -> Result<VmTable, VirshleError> {
+        rest.open().await?;
+        rest.ping().await?;
+        let vm: VmTable = rest
+            .put("/vm/reboot", args.clone())
+            .await?
+            .to_value()
+            .await?;
+        Ok(vm)
+    }
+    /// Bulk operation
+    /// Reboot many virtual machine on a node.
+    #[builder(finish_fn = exec)]
+    pub async fn many(
+        &mut self,
+        state: Option<VmState>,
+        account: Option<Uuid>,
+        alias: Option<String>,
+    ) -> Result<IndexMap<Peer, IndexMap<Status, Vec<VmTable>>>, VirshleError> {
+        let mut res: IndexMap<Peer, IndexMap<Status, Vec<VmTable>>> = IndexMap::new();
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        let vms = Self::_many(
+            peer,
+            rest,
+            Some(GetManyVmArgs {
+                vm_state: state,
+                account_uuid: account,
+            }),
+        )
+        .await?;
+        res.insert(peer.clone(), vms);
+        Ok(res)
+    }
+    async fn _many(
+        peer: &Peer,
+        rest: &mut RestClient,
+        args: Option<GetManyVmArgs>,
+    ) -> Result<IndexMap<Status, Vec<VmTable>>, VirshleError> {
+        rest.open().await?;
+        rest.ping().await?;
+        let vms: IndexMap<Status, Vec<VmTable>> = rest
+            .put("/vm/reboot.many", args.clone())
+            .await?
+            .to_value()
+            .await?;
+        Ok(vms)
+    }
+}
+pub struct VmPauseMethods<'a> {
+    api: &'a mut Methods,
+}
+impl VmMethods<'_> {
+    pub fn pause(&mut self) -> VmPauseMethods<'_> {
+        VmPauseMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmPauseMethods<'_> {
+    /// Pause a virtual machine on a node.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+
+        alias: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        let res: VmTable = Self::_one(
+            peer,
+            rest,
+            Some(GetVmArgs {
+                id,
+                uuid,
+                name,
+            }),
+        )
+        .await?;
+        Ok(res)
+    }
+    async fn _one(
+        peer: &Peer,
+        rest: &mut RestClient,
+        args: Option<GetVmArgs>,
+    ) -> Result<VmTable, VirshleError> {
+        rest.open().await?;
+        rest.ping().await?;
+        let vm: VmTable = rest
+            .put("/vm/pause", args.clone())
+            .await?
+            .to_value()
+            .await?;
+        Ok(vm)
+    }
+    /// Bulk operation
+    /// Pause many virtual machine on a node.
+    #[builder(finish_fn = exec)]
+    pub async fn many(
+        &mut self,
+        state: Option<VmState>,
+        account: Option<Uuid>,
+        alias: Option<String>,
+    ) -> Result<IndexMap<Peer, IndexMap<Status, Vec<VmTable>>>, VirshleError> {
+        let mut res: IndexMap<Peer, IndexMap<Status, Vec<VmTable>>> = IndexMap::new();
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        let vms = Self::_many(
+            peer,
+            rest,
+            Some(GetManyVmArgs {
+                vm_state: state,
+                account_uuid: account,
+            }),
+        )
+        .await?;
+        res.insert(peer.clone(), vms);
+        Ok(res)
+    }
+    async fn _many(
+        peer: &Peer,
+        rest: &mut RestClient,
+        args: Option<GetManyVmArgs>,
+    ) -> Result<IndexMap<Status, Vec<VmTable>>, VirshleError> {
+        rest.open().await?;
+        rest.ping().await?;
+        let vms: IndexMap<Status, Vec<VmTable>> = rest
+            .put("/vm/pause.many", args.clone())
+            .await?
+            .to_value()
+            .await?;
+        Ok(vms)
+    }
+}
+pub struct VmResumeMethods<'a> {
+    api: &'a mut Methods,
+}
+impl VmMethods<'_> {
+    pub fn resume(&mut self) -> VmResumeMethods<'_> {
+        VmResumeMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmResumeMethods<'_> {
+    /// Resume a virtual machine on a node.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+
+        alias: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        let res: VmTable = Self::_one(
+            peer,
+            rest,
+            Some(GetVmArgs {
+                id,
+                uuid,
+                name,
+            }),
+        )
+        .await?;
+        Ok(res)
+    }
+    async fn _one(
+        peer: &Peer,
+        rest: &mut RestClient,
+        args: Option<GetVmArgs>,
+    ) -> Result<VmTable, VirshleError> {
+        rest.open().await?;
+        rest.ping().await?;
+        let vm: VmTable = rest
+            .put("/vm/resume", args.clone())
+            .await?
+            .to_value()
+            .await?;
+        Ok(vm)
+    }
+    /// Bulk operation
+    /// Resume many virtual machine on a node.
+    #[builder(finish_fn = exec)]
+    pub async fn many(
+        &mut self,
+        state: Option<VmState>,
+        account: Option<Uuid>,
+        alias: Option<String>,
+    ) -> Result<IndexMap<Peer, IndexMap<Status, Vec<VmTable>>>, VirshleError> {
+        let mut res: IndexMap<Peer, IndexMap<Status, Vec<VmTable>>> = IndexMap::new();
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        let vms = Self::_many(
+            peer,
+            rest,
+            Some(GetManyVmArgs {
+                vm_state: state,
+                account_uuid: account,
+            }),
+        )
+        .await?;
+        res.insert(peer.clone(), vms);
+        Ok(res)
+    }
+    async fn _many(
+        peer: &Peer,
+        rest: &mut RestClient,
+        args: Option<GetManyVmArgs>,
+    ) -> Result<IndexMap<Status, Vec<VmTable>>, VirshleError> {
+        rest.open().await?;
+        rest.ping().await?;
+        let vms: IndexMap<Status, Vec<VmTable>> = rest
+            .put("/vm/resume.many", args.clone())
+            .await?
+            .to_value()
+            .await?;
+        Ok(vms)
+    }
+}
+
+pub struct VmSnapshotMethods<'a> {
+    api: &'a mut Methods,
+}
+impl VmMethods<'_> {
+    pub fn snapshot(&mut self) -> VmSnapshotMethods<'_> {
+        VmSnapshotMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmSnapshotMethods<'_> {
+    /// Take a snapshot of a virtual machine under the given tag.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        tag: String,
+
+        alias: Option<String>,
+    ) -> Result<Snapshot, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = SnapshotVmArgs {
+            id,
+            uuid,
+            name,
+            tag,
+        };
+        let res: Snapshot = rest
+            .put("/vm/snapshot", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+    /// List a virtual machine snapshots.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn get(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+
+        alias: Option<String>,
+    ) -> Result<Vec<Snapshot>, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = GetVmArgs { id, uuid, name };
+        let res: Vec<Snapshot> = rest
+            .put("/vm/snapshot.list", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+}
+
+pub struct VmRestoreMethods<'a> {
+    api: &'a mut Methods,
+}
+impl VmMethods<'_> {
+    pub fn restore(&mut self) -> VmRestoreMethods<'_> {
+        VmRestoreMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmRestoreMethods<'_> {
+    /// Roll a virtual machine back to the snapshot with the given tag.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        tag: String,
+
+        alias: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = SnapshotVmArgs {
+            id,
+            uuid,
+            name,
+            tag,
+        };
+        let vm: VmTable = rest
+            .put("/vm/restore", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(vm)
+    }
+}
+
+pub struct VmMigrateMethods<'a> {
+    api: &'a mut Methods,
+}
+impl VmMethods<'_> {
+    pub fn migrate(&mut self) -> VmMigrateMethods<'_> {
+        VmMigrateMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmMigrateMethods<'_> {
+    /// Live migrate a virtual machine from a peer to another.
+    /// - the destination peer recreates the vm and waits for it,
+    /// - the source peer streams the vm and cleans up.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        port: Option<u16>,
+
+        /// Source peer alias.
+        alias: Option<String>,
+        /// Destination peer alias.
+        to: String,
+    ) -> Result<VmTable, VirshleError> {
+        let args = GetVmArgs { id, uuid, name };
+
+        // Retrieve vm from source.
+        let (vm, vm_table) = {
+            let mut method = self.api.peer();
+            let mut getter = method.get();
+            let (peer, rest) = getter.alias_or_default().maybe_alias(alias.clone()).exec()?;
+            if peer.alias == to {
+                let message = format!("Vm already lives on peer {:#?}.", to);
+                let help = "Choose another destination peer.";
+                return Err(LibError::builder().msg(&message).help(help).build().into());
+            }
+            rest.open().await?;
+            rest.ping().await?;
+            let vm: Vm = rest
+                .post("/vm/get_definition", Some(args.clone()))
+                .await?
+                .to_value()
+                .await?;
+            let vm_table: VmTable = rest
+                .post("/vm/info", Some(args.clone()))
+                .await?
+                .to_value()
+                .await?;
+            (vm, vm_table)
+        };
+
+        // Make destination wait for the vm.
+        let destination_url = {
+            let mut method = self.api.peer();
+            let mut getter = method.get();
+            let (peer, rest) = getter.alias(&to)?;
+            rest.open().await?;
+            rest.ping().await?;
+            // The destination only listens on the address the source reaches it at.
+            let host = Uri::new(&peer.url)?.get_host()?;
+            let destination_url: String = rest
+                .put(
+                    "/vm/receive_migration",
+                    Some(ReceiveMigrationArgs {
+                        vm: vm.clone(),
+                        account_uuid: vm_table.account_uuid,
+                        host,
+                        port,
+                        disk: vm_table.disk.clone(),
+                    }),
+                )
+                .await?
+                .to_value()
+                .await?;
+            destination_url
+        };
+
+        // Stream vm from source.
+        let sent: Result<VmTable, VirshleError> = async {
+            let mut method = self.api.peer();
+            let mut getter = method.get();
+            let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+            rest.put(
+                "/vm/send_migration",
+                Some(SendMigrationArgs {
+                    id: None,
+                    uuid: Some(vm.uuid),
+                    name: None,
+                    destination_url,
+                }),
+            )
+            .await?
+            .to_value()
+            .await
+        }
+        .await;
+        if let Err(e) = sent {
+            // Don't leave the destination waiting for a vm that won't come.
+            let mut method = self.api.peer();
+            let mut getter = method.get();
+            let (_, rest) = getter.alias(&to)?;
+            let canceled: Result<VmTable, VirshleError> = async {
+                rest.put(
+                    "/vm/cancel_migration",
+                    Some(GetVmArgs {
+                        id: None,
+                        uuid: Some(vm.uuid),
+                        name: None,
+                    }),
+                )
+                .await?
+                .to_value()
+                .await
+            }
+            .await;
+            if let Err(cancel_err) = canceled {
+                error!("{}", cancel_err);
+            }
+            return Err(e);
+        }
+
+        // Return vm as seen from destination.
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias(&to)?;
+        let res: VmTable = rest
+            .post(
+                "/vm/info",
+                Some(GetVmArgs {
+                    id: None,
+                    uuid: Some(vm.uuid),
+                    name: None,
+                }),
+            )
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+}
+
+pub struct VmUpdateMethods<'a> {
+    api: &'a mut Methods,
+}
+impl VmMethods<'_> {
+    pub fn update(&mut self) -> VmUpdateMethods<'_> {
+        VmUpdateMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmUpdateMethods<'_> {
+    /// Update a virtual machine settings.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        autostart: Option<bool>,
+        security_groups: Option<Vec<String>>,
+        net_name: Option<String>,
+        ingress: Option<String>,
+        egress: Option<String>,
+        burst: Option<String>,
+
+        alias: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = UpdateVmArgs {
+            id,
+            uuid,
+            name,
+            autostart,
+            security_groups,
+            net_name,
+            ingress,
+            egress,
+            burst,
+        };
+        let res: VmTable = rest
+            .put("/vm/update", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+}
+pub struct VmResizeMethods<'a> {
+    api: &'a mut Methods,
+}
+impl VmMethods<'_> {
+    pub fn resize(&mut self) -> VmResizeMethods<'_> {
+        VmResizeMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmResizeMethods<'_> {
+    /// Resize a virtual machine vcpu and ram.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        vcpu: Option<u64>,
+        vram: Option<String>,
+
+        alias: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = ResizeVmArgs {
+            id,
+            uuid,
+            name,
+            vcpu,
+            vram,
+        };
+        let res: VmTable = rest
+            .put("/vm/resize", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+}
+pub struct VmDiskMethods<'a> {
+    api: &'a mut Methods,
+}
+impl VmMethods<'_> {
+    pub fn disk(&mut self) -> VmDiskMethods<'_> {
+        VmDiskMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmDiskMethods<'_> {
+    /// Create a data disk and attach it to a virtual machine.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn add(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        disk_name: String,
+        disk_size: String,
+
+        alias: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = AddDiskArgs {
+            id,
+            uuid,
+            name,
+            disk_name,
+            disk_size,
+        };
+        let res: VmTable = rest
+            .put("/vm/disk/add", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+    /// Grow a virtual machine disk.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn resize(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        disk_name: String,
+        disk_size: String,
+
+        alias: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = ResizeDiskArgs {
+            id,
+            uuid,
+            name,
+            disk_name,
+            disk_size,
+        };
+        let res: VmTable = rest
+            .put("/vm/disk/resize", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+    /// Change a virtual machine disk I/O tuning,
+    /// and tell whether the vm must be restarted for it to apply.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn update(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        disk_name: String,
+        direct: Option<bool>,
+        num_queues: Option<u64>,
+        queue_size: Option<u64>,
+        bandwidth: Option<String>,
+        iops: Option<u64>,
+
+        alias: Option<String>,
+    ) -> Result<(VmTable, bool), VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = UpdateDiskArgs {
+            id,
+            uuid,
+            name,
+            disk_name,
+            direct,
+            num_queues,
+            queue_size,
+            bandwidth,
+            iops,
+        };
+        let res: (VmTable, bool) = rest
+            .put("/vm/disk/update", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+    /// Check a virtual machine disks filesystems.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn check(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        disk_name: Option<String>,
+        repair: Option<bool>,
+
+        alias: Option<String>,
+    ) -> Result<Vec<PartitionReport>, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = CheckDiskArgs {
+            id,
+            uuid,
+            name,
+            disk_name,
+            repair,
+        };
+        let res: Vec<PartitionReport> = rest
+            .put("/vm/disk/check", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+    /// Detach a data disk from a virtual machine and delete it.
+    #[builder(
+        finish_fn = exec, 
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn remove(
+        &mut self,
+        id: Option<u64>,
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        disk_name: String,
+
+        alias: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let mut method = self.api.peer();
+        let mut getter = method.get();
+        let (peer, rest) = getter.alias_or_default().maybe_alias(alias).exec()?;
+        rest.open().await?;
+        rest.ping().await?;
+        let args = RemoveDiskArgs {
+            id,
+            uuid,
+            name,
+            disk_name,
+        };
+        let res: VmTable = rest
+            .put("/vm/disk/rm", Some(args))
+            .await?
+            .to_value()
+            .await?;
+        Ok(res)
+    }
+}
@@ -1,7 +1,7 @@
 use crate::Client;
 use virshle_core::{
     config::{Config, UserData},
-    hypervisor::{Vm, VmTable},
+    hypervisor::{ShutdownMode, Snapshot, Vm, VmTable},
     peer::{NodeInfo, Peer},
 };
 use virshle_network::connection::ConnectionState;
@@ -105,14 +105,60 @@ async fn crud_vm() -> Result<()> {
         .alias("Self")
         .exec()
         .await?;
-    // Shutdown one
+    // Pause one
+    let _: VmTable = client
+        .api()
+        .await?
+        .vm()
+        .pause()
+        .one()
+        .uuid(vm.uuid)
+        .alias("Self")
+        .exec()
+        .await?;
+    // Resume one
+    let _: VmTable = client
+        .api()
+        .await?
+        .vm()
+        .resume()
+        .one()
+        .uuid(vm.uuid)
+        .alias("Self")
+        .exec()
+        .await?;
+    // Snapshot and restore one
+    let _: Snapshot = client
+        .api()
+        .await?
+        .vm()
+        .snapshot()
+        .one()
+        .uuid(vm.uuid)
+        .tag("test")
+        .alias("Self")
+        .exec()
+        .await?;
     let _: VmTable = client
+        .api()
+        .await?
+        .vm()
+        .restore()
+        .one()
+        .uuid(vm.uuid)
+        .tag("test")
+        .alias("Self")
+        .exec()
+        .await?;
+    // Shutdown one
+    let _: (VmTable, ShutdownMode) = client
         .api()
         .await?
         .vm()
         .shutdown()
         .one()
         .uuid(vm.uuid)
+        .force(true)
         .alias("Self")
         .exec()
         .await?;
@@ -1,8 +1,9 @@
 use super::Status;
 
 use bon::bon;
+use human_bytes::human_bytes;
 use owo_colors::OwoColorize;
-use virshle_core::{Peer, VmTable};
+use virshle_core::{Peer, Snapshot, VmTable};
 // use spinoff::{spinners, Color, Spinner};
 
 use indexmap::IndexMap;
@@ -45,6 +46,66 @@ impl Printer {
         }
         Ok(message.to_owned())
     }
+    /// Print the result of a snapshot of a single vm.
+    #[tracing::instrument(skip_all)]
+    #[builder(
+        finish_fn = print,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub fn res_snapshot(
+        &self,
+        tag: &str,
+        peer: &str,
+        content: &Result<Snapshot, VirshleError>,
+    ) -> Result<String, VirshleError> {
+        let tag = format!("[{tag}]");
+        let message;
+        match content {
+            Ok(snapshot) => {
+                let tag = tag.green();
+                let snapshot_tag = format!("snapshot/{}", snapshot.tag.bold().blue());
+                message = format!(
+                    "✅ {tag} succedded for {snapshot_tag} on node {}",
+                    peer.green()
+                );
+            }
+            Err(e) => {
+                let tag = tag.red();
+                message = format!("⛔️ {tag} failed on node {}", peer.green());
+            }
+        }
+        Ok(message.to_owned())
+    }
+    /// Print the result of a vm export into an archive file.
+    #[tracing::instrument(skip_all)]
+    #[builder(
+        finish_fn = print,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub fn res_archive(
+        &self,
+        tag: &str,
+        peer: &str,
+        path: &str,
+        content: &Result<u64, VirshleError>,
+    ) -> Result<String, VirshleError> {
+        let tag = format!("[{tag}]");
+        let message;
+        match content {
+            Ok(size) => {
+                let tag = tag.green();
+                let archive = format!("{} ({})", path.bold().blue(), human_bytes(*size as f64));
+                message = format!("✅ {tag} succedded into {archive} from node {}", peer.green());
+            }
+            Err(e) => {
+                let tag = tag.red();
+                message = format!("⛔️ {tag} failed on node {}", peer.green());
+            }
+        }
+        Ok(message.to_owned())
+    }
     /// Print the result of an operation on a single vm.
     #[tracing::instrument(skip_all)]
     #[builder(
@@ -1,10 +1,18 @@
-use virshle_core::hypervisor::{vm::UserData, vmm::types::VmState};
+use virshle_core::hypervisor::{
+    disk::DiskInfo,
+    vm::{UserData, Vm},
+    vmm::types::VmState,
+};
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 pub use pipelight_exec::Status;
 
+/// Protocol a connection is upgraded to,
+/// to attach to a vm serial console.
+pub const CONSOLE_PROTOCOL: &str = "virshle-console";
+
 /// A strutc to query a VM from a node.
 #[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct GetVmArgs {
@@ -44,6 +52,146 @@ pub struct StartManyVmArgs {
     pub user_data: Option<UserData>,
 }
 #[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ShutdownVmArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    /// Seconds given to the guest to power itself off.
+    pub timeout: Option<u64>,
+    /// Force the vm down if the guest didn't power off in time.
+    pub force: Option<bool>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ShutdownManyVmArgs {
+    pub vm_state: Option<VmState>,
+    pub account_uuid: Option<Uuid>,
+    pub timeout: Option<u64>,
+    pub force: Option<bool>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotVmArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    pub tag: String,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UpdateVmArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    pub autostart: Option<bool>,
+    /// Replace the vm security groups, an empty vec removes them.
+    pub security_groups: Option<Vec<String>>,
+    /// Network to rate limit, the only vm network if none.
+    pub net_name: Option<String>,
+    /// Human readable rates per second (ex: "100Mbit"), "0bit" removes them.
+    pub ingress: Option<String>,
+    pub egress: Option<String>,
+    pub burst: Option<String>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ResizeVmArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    pub vcpu: Option<u64>,
+    /// Human readable ram size (ex: "8GiB").
+    pub vram: Option<String>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AddDiskArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    pub disk_name: String,
+    /// Human readable disk size (ex: "20GiB").
+    pub disk_size: String,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ResizeDiskArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    pub disk_name: String,
+    /// Human readable disk size (ex: "50GiB").
+    pub disk_size: String,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UpdateDiskArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    pub disk_name: String,
+    pub direct: Option<bool>,
+    pub num_queues: Option<u64>,
+    pub queue_size: Option<u64>,
+    /// Human readable bandwidth per second (ex: "100MiB").
+    pub bandwidth: Option<String>,
+    pub iops: Option<u64>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CheckDiskArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    /// Check every vm disk if none.
+    pub disk_name: Option<String>,
+    pub repair: Option<bool>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RemoveDiskArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    pub disk_name: String,
+}
+/// A struct to hand a VM over to a node.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ReceiveMigrationArgs {
+    pub vm: Vm,
+    pub account_uuid: Option<Uuid>,
+    /// Address the sending node reaches the receiving node at.
+    pub host: String,
+    /// Listening port, defaults to a free ephemeral port.
+    pub port: Option<u16>,
+    /// Vm disks as seen from the sending node.
+    pub disk: Option<Vec<DiskInfo>>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SendMigrationArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    pub destination_url: String,
+}
+/// Passed as query parameters,
+/// the request and response bodies are the archive itself.
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ExportVmArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    /// Tag of a snapshot to bundle.
+    pub snapshot: Option<String>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CloneVmArgs {
+    pub id: Option<u64>,
+    pub uuid: Option<Uuid>,
+    pub name: Option<String>,
+    /// Name of the clone, suffixed with an index when cloning many times.
+    pub clone_name: Option<String>,
+    pub ntimes: Option<u8>,
+    /// Defaults to the source vm user data.
+    pub user_data: Option<UserData>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ImportVmArgs {
+    pub keep_uuid: Option<bool>,
+    pub name: Option<String>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct EnsureVmArgs {
     pub id: Option<u64>,
     pub uuid: Option<Uuid>,
@@ -60,3 +208,12 @@ pub struct EnsureManyVmArgs {
     pub user_data: Option<UserData>,
     pub net: Option<bool>,
 }
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PullImageArgs {
+    pub source: String,
+    pub sha256: Option<String>,
+}
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GetImageArgs {
+    pub name: String,
+}
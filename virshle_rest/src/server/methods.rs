@@ -1,28 +1,41 @@
 use crate::commons::vm_bulk_results_to_hashmap;
 use crate::commons::{
-    CreateManyVmArgs, CreateVmArgs, GetManyVmArgs, GetVmArgs, StartManyVmArgs, StartVmArgs,
+    AddDiskArgs, CheckDiskArgs, CloneVmArgs, CreateManyVmArgs, CreateVmArgs, ExportVmArgs,
+    GetImageArgs, GetManyVmArgs, GetVmArgs, ImportVmArgs, PullImageArgs, ReceiveMigrationArgs,
+    RemoveDiskArgs, ResizeDiskArgs, ResizeVmArgs, SendMigrationArgs, ShutdownManyVmArgs,
+    ShutdownVmArgs, SnapshotVmArgs, StartManyVmArgs, StartVmArgs, UpdateDiskArgs, UpdateVmArgs,
 };
 use crate::server::Server;
 
 use http_body_util::BodyExt;
 use indexmap::IndexMap;
 
-use pipelight_exec::{Finder, Status};
+use pipelight_exec::Status;
+use std::time::Duration;
 use uuid::Uuid;
 
 // Hypervisor
 use virshle_core::{
     config::{Config, DhcpType, Node, UserData, VmTemplate, VmTemplateTable},
     hypervisor::{
-        vm::{Vm, VmTable},
+        vm::{ShutdownMode, Snapshot, Vm, VmTable},
         vmm::types::{VmInfoResponse, VmState},
+        PartitionReport,
     },
     network::dhcp::KeaDhcp,
     peer::{HostInfo, NodeInfo, Peer},
+    Image,
 };
 
 // Connections and Http
+use axum::body::Body;
 use bon::bon;
+use bytes::Bytes;
+use futures::{Stream, TryStreamExt};
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
+use tokio::net::UnixStream;
+use tokio_util::io::StreamReader;
 use virshle_network::{
     connection::{Connection, TcpConnection},
     http::{Rest, RestClient},
@@ -32,7 +45,7 @@ use virshle_network::{
 use miette::{Diagnostic, Result};
 use tokio::task::JoinError;
 use tracing::{error, info, warn};
-use virshle_error::{LibError, VirshleError};
+use virshle_error::{LibError, VirshleError, WrapError};
 
 impl Server {
     pub fn api(&self) -> Result<Methods, VirshleError> {
@@ -61,6 +74,9 @@ impl Methods {
     pub fn template(&self) -> TemplateMethods<'_> {
         TemplateMethods { api: self }
     }
+    pub fn image(&self) -> ImageMethods<'_> {
+        ImageMethods { api: self }
+    }
     pub fn vm(&self) -> VmMethods<'_> {
         VmMethods { api: self }
     }
@@ -83,6 +99,10 @@ pub struct TemplateMethods<'a> {
     api: &'a Methods,
 }
 #[derive(Clone)]
+pub struct ImageMethods<'a> {
+    api: &'a Methods,
+}
+#[derive(Clone)]
 pub struct VmMethods<'a> {
     api: &'a Methods,
 }
@@ -117,6 +137,29 @@ impl PeerMethods<'_> {
     }
 }
 
+impl ImageMethods<'_> {
+    /// List images in node cache.
+    pub async fn get_many(&self) -> Result<Vec<Image>, VirshleError> {
+        Image::get_all().await
+    }
+    /// Fetch an image into node cache.
+    pub async fn pull(&self, args: PullImageArgs) -> Result<Image, VirshleError> {
+        Image::pull()
+            .source(args.source)
+            .maybe_sha256(args.sha256)
+            .exec()
+            .await
+    }
+    /// Remove an unused image from node cache.
+    pub async fn rm(&self, args: GetImageArgs) -> Result<Image, VirshleError> {
+        Image::rm(&args.name).await
+    }
+    /// Remove every unused image from node cache.
+    pub async fn prune(&self) -> Result<Vec<Image>, VirshleError> {
+        Image::prune().await
+    }
+}
+
 impl TemplateMethods<'_> {
     pub async fn reclaim(&self, args: CreateVmArgs) -> Result<bool, VirshleError> {
         if let Some(name) = &args.template_name {
@@ -234,7 +277,8 @@ impl VmCreateMethods<'_> {
     async fn _one(&self, args: CreateVmArgs) -> Result<Vm, VirshleError> {
         match args.template_name {
             Some(name) => {
-                let template = self.api.config.template(&name)?;
+                let mut template = self.api.config.template(&name)?;
+                template.pull_images().await?;
                 let mut vm: Vm = template.try_into()?;
 
                 // Safeguard before creating.
@@ -292,7 +336,8 @@ impl VmCreateMethods<'_> {
     }
     async fn _many(&self, args: CreateManyVmArgs) -> Result<Vec<Vm>, VirshleError> {
         if args.template_name.is_some() && args.ntimes.is_some() {
-            let template = self.api.config.template(&args.template_name.unwrap())?;
+            let mut template = self.api.config.template(&args.template_name.unwrap())?;
+            template.pull_images().await?;
 
             let mut tasks = vec![];
             for i in 0..args.ntimes.unwrap() {
@@ -589,6 +634,106 @@ impl VmMethods<'_> {
 }
 #[bon]
 impl VmShutdownMethods<'_> {
+    #[builder(finish_fn = exec)]
+    pub async fn one(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        timeout: Option<u64>,
+        force: Option<bool>,
+    ) -> Result<(VmTable, ShutdownMode), VirshleError> {
+        let (vm, mode) = Self::_one(ShutdownVmArgs {
+            id,
+            name,
+            uuid,
+            timeout,
+            force,
+        })
+        .await?;
+        let res = VmTable::from(&vm).await?;
+        Ok((res, mode))
+    }
+    async fn _one(args: ShutdownVmArgs) -> Result<(Vm, ShutdownMode), VirshleError> {
+        let vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        let mode = vm
+            .shutdown()
+            .maybe_timeout(args.timeout.map(Duration::from_secs))
+            .maybe_force(args.force)
+            .exec()
+            .await?;
+        Ok((vm, mode))
+    }
+
+    #[builder(finish_fn = exec)]
+    pub async fn many(
+        &self,
+        state: Option<VmState>,
+        account: Option<Uuid>,
+        timeout: Option<u64>,
+        force: Option<bool>,
+    ) -> Result<(IndexMap<Status, Vec<VmTable>>, IndexMap<Uuid, ShutdownMode>), VirshleError> {
+        let vms = Vm::database()
+            .await?
+            .many()
+            .maybe_account_uuid(account)
+            .maybe_vm_state(state)
+            .get()
+            .await?;
+
+        let mut tasks = vec![];
+        for vm in vms.clone() {
+            tasks.push(tokio::spawn({
+                async move {
+                    let vm = vm.clone();
+                    let mode = vm
+                        .shutdown()
+                        .maybe_timeout(timeout.map(Duration::from_secs))
+                        .maybe_force(force)
+                        .exec()
+                        .await?;
+                    Ok::<(Vm, ShutdownMode), VirshleError>((vm, mode))
+                }
+            }));
+        }
+        let results: Vec<Result<Result<(Vm, ShutdownMode), VirshleError>, JoinError>> =
+            futures::future::join_all(tasks).await;
+
+        // Keep track of which path each vm went through.
+        let mut modes: IndexMap<Uuid, ShutdownMode> = IndexMap::new();
+        let results: Vec<Result<Result<Vm, VirshleError>, JoinError>> = results
+            .into_iter()
+            .map(|res| {
+                res.map(|res| {
+                    res.map(|(vm, mode)| {
+                        modes.insert(vm.uuid, mode);
+                        vm
+                    })
+                })
+            })
+            .collect();
+        let res: IndexMap<Status, Vec<VmTable>> = vm_bulk_results_to_hashmap(vms, results).await?;
+        Ok((res, modes))
+    }
+}
+
+pub struct VmRebootMethods<'a> {
+    api: &'a Methods,
+}
+impl VmMethods<'_> {
+    pub fn reboot(&self) -> VmRebootMethods<'_> {
+        VmRebootMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmRebootMethods<'_> {
     #[builder(finish_fn = exec)]
     pub async fn one(
         &self,
@@ -609,7 +754,7 @@ impl VmShutdownMethods<'_> {
             .maybe_uuid(args.uuid)
             .get()
             .await?;
-        vm.shutdown().await.ok();
+        vm.reboot().await?;
         Ok(vm)
     }
 
@@ -632,7 +777,7 @@ impl VmShutdownMethods<'_> {
             tasks.push(tokio::spawn({
                 async move {
                     let vm = vm.clone();
-                    vm.shutdown().await
+                    vm.reboot().await
                 }
             }));
         }
@@ -643,16 +788,29 @@ impl VmShutdownMethods<'_> {
     }
 }
 
+pub struct VmPauseMethods<'a> {
+    api: &'a Methods,
+}
 impl VmMethods<'_> {
-    /*
-     * TODO:
-     * It should forward vm tty to user tty or ssh session!
-     */
-    pub async fn _start_attach(
-        args: GetVmArgs,
-        user_data: Option<UserData>,
-    ) -> Result<Vm, VirshleError> {
-        let mut vm = Vm::database()
+    pub fn pause(&self) -> VmPauseMethods<'_> {
+        VmPauseMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmPauseMethods<'_> {
+    #[builder(finish_fn = exec)]
+    pub async fn one(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+    ) -> Result<VmTable, VirshleError> {
+        let vm = Self::_one(GetVmArgs { id, name, uuid }).await?;
+        let res = VmTable::from(&vm).await?;
+        Ok(res)
+    }
+    async fn _one(args: GetVmArgs) -> Result<Vm, VirshleError> {
+        let vm = Vm::database()
             .await?
             .one()
             .maybe_id(args.id)
@@ -660,11 +818,62 @@ impl VmMethods<'_> {
             .maybe_uuid(args.uuid)
             .get()
             .await?;
+        vm.pause().await?;
         Ok(vm)
     }
 
-    /// Attach a virtual machine on a node.
-    pub async fn _attach(args: GetVmArgs, node_name: Option<String>) -> Result<(), VirshleError> {
+    #[builder(finish_fn = exec)]
+    pub async fn many(
+        &self,
+        state: Option<VmState>,
+        account: Option<Uuid>,
+    ) -> Result<IndexMap<Status, Vec<VmTable>>, VirshleError> {
+        let vms = Vm::database()
+            .await?
+            .many()
+            .maybe_account_uuid(account)
+            .maybe_vm_state(state)
+            .get()
+            .await?;
+
+        let mut tasks = vec![];
+        for vm in vms.clone() {
+            tasks.push(tokio::spawn({
+                async move {
+                    let vm = vm.clone();
+                    vm.pause().await
+                }
+            }));
+        }
+        let results: Vec<Result<Result<Vm, VirshleError>, JoinError>> =
+            futures::future::join_all(tasks).await;
+        let res: IndexMap<Status, Vec<VmTable>> = vm_bulk_results_to_hashmap(vms, results).await?;
+        Ok(res)
+    }
+}
+
+pub struct VmResumeMethods<'a> {
+    api: &'a Methods,
+}
+impl VmMethods<'_> {
+    pub fn resume(&self) -> VmResumeMethods<'_> {
+        VmResumeMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmResumeMethods<'_> {
+    #[builder(finish_fn = exec)]
+    pub async fn one(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+    ) -> Result<VmTable, VirshleError> {
+        let vm = Self::_one(GetVmArgs { id, name, uuid }).await?;
+        let res = VmTable::from(&vm).await?;
+        Ok(res)
+    }
+    async fn _one(args: GetVmArgs) -> Result<Vm, VirshleError> {
         let vm = Vm::database()
             .await?
             .one()
@@ -673,24 +882,69 @@ impl VmMethods<'_> {
             .maybe_uuid(args.uuid)
             .get()
             .await?;
+        vm.resume().await?;
+        Ok(vm)
+    }
 
-        let finder = Finder::new()
-            .seed("cloud-hypervisor")
-            .seed(&vm.uuid.to_string())
-            .search_no_parents()?;
+    #[builder(finish_fn = exec)]
+    pub async fn many(
+        &self,
+        state: Option<VmState>,
+        account: Option<Uuid>,
+    ) -> Result<IndexMap<Status, Vec<VmTable>>, VirshleError> {
+        let vms = Vm::database()
+            .await?
+            .many()
+            .maybe_account_uuid(account)
+            .maybe_vm_state(state)
+            .get()
+            .await?;
 
-        #[cfg(debug_assertions)]
-        if let Some(matches) = finder.matches {
-            if let Some(_match) = matches.first() {
-                if let Some(pid) = _match.pid {}
-            }
+        let mut tasks = vec![];
+        for vm in vms.clone() {
+            tasks.push(tokio::spawn({
+                async move {
+                    let vm = vm.clone();
+                    vm.resume().await
+                }
+            }));
         }
+        let results: Vec<Result<Result<Vm, VirshleError>, JoinError>> =
+            futures::future::join_all(tasks).await;
+        let res: IndexMap<Status, Vec<VmTable>> = vm_bulk_results_to_hashmap(vms, results).await?;
+        Ok(res)
+    }
+}
 
-        Ok(())
+pub struct VmSnapshotMethods<'a> {
+    api: &'a Methods,
+}
+impl VmMethods<'_> {
+    pub fn snapshot(&self) -> VmSnapshotMethods<'_> {
+        VmSnapshotMethods { api: self.api }
     }
-    /// Get detailed information about a VM,
-    /// from the underlying cloud-hypervisor process.
-    pub async fn get_ch_info(&self, args: GetVmArgs) -> Result<VmInfoResponse, VirshleError> {
+}
+#[bon]
+impl VmSnapshotMethods<'_> {
+    /// Take a snapshot of the vm under the given tag.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        tag: String,
+    ) -> Result<Snapshot, VirshleError> {
+        let args = SnapshotVmArgs {
+            id,
+            name,
+            uuid,
+            tag,
+        };
         let vm = Vm::database()
             .await?
             .one()
@@ -699,23 +953,68 @@ impl VmMethods<'_> {
             .maybe_uuid(args.uuid)
             .get()
             .await?;
-        let info = vm.vmm().api()?.info().await?;
-        Ok(info.into())
+        let res = vm.snapshot(&args.tag).await?;
+        Ok(res)
     }
-    pub async fn get_raw_ch_info(&self, args: GetVmArgs) -> Result<String, VirshleError> {
+    /// Return the vm snapshots.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn get(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+    ) -> Result<Vec<Snapshot>, VirshleError> {
         let vm = Vm::database()
             .await?
             .one()
-            .maybe_id(args.id)
-            .maybe_name(args.name)
-            .maybe_uuid(args.uuid)
+            .maybe_id(id)
+            .maybe_name(name)
+            .maybe_uuid(uuid)
             .get()
             .await?;
-        let info = vm.vmm().api()?._info().await?;
-        Ok(info.into())
+        let res = vm.snapshots().get_all().await?;
+        Ok(res)
     }
+}
 
-    pub async fn ping_ch(&self, args: GetVmArgs) -> Result<(), VirshleError> {
+pub struct VmRestoreMethods<'a> {
+    api: &'a Methods,
+}
+impl VmMethods<'_> {
+    pub fn restore(&self) -> VmRestoreMethods<'_> {
+        VmRestoreMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmRestoreMethods<'_> {
+    /// Roll the vm back to the snapshot with the given tag.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        tag: String,
+    ) -> Result<VmTable, VirshleError> {
+        let vm = Self::_one(SnapshotVmArgs {
+            id,
+            name,
+            uuid,
+            tag,
+        })
+        .await?;
+        let res = VmTable::from(&vm).await?;
+        Ok(res)
+    }
+    async fn _one(args: SnapshotVmArgs) -> Result<Vm, VirshleError> {
         let vm = Vm::database()
             .await?
             .one()
@@ -724,7 +1023,584 @@ impl VmMethods<'_> {
             .maybe_uuid(args.uuid)
             .get()
             .await?;
-        vm.vmm().api()?.ping().await
+        vm.restore(&args.tag).await?;
+        Ok(vm)
+    }
+}
+
+pub struct VmUpdateMethods<'a> {
+    api: &'a Methods,
+}
+impl VmMethods<'_> {
+    pub fn update(&self) -> VmUpdateMethods<'_> {
+        VmUpdateMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmUpdateMethods<'_> {
+    /// Update vm settings.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        autostart: Option<bool>,
+        security_groups: Option<Vec<String>>,
+        net_name: Option<String>,
+        ingress: Option<String>,
+        egress: Option<String>,
+        burst: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let vm = Self::_one(UpdateVmArgs {
+            id,
+            name,
+            uuid,
+            autostart,
+            security_groups,
+            net_name,
+            ingress,
+            egress,
+            burst,
+        })
+        .await?;
+        let res = VmTable::from(&vm).await?;
+        Ok(res)
+    }
+    async fn _one(args: UpdateVmArgs) -> Result<Vm, VirshleError> {
+        let mut vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        vm.update()
+            .maybe_autostart(args.autostart)
+            .maybe_security_groups(args.security_groups)
+            .exec()
+            .await?;
+        if args.ingress.is_some() || args.egress.is_some() || args.burst.is_some() {
+            vm.update_net()
+                .maybe_name(args.net_name)
+                .maybe_ingress(args.ingress)
+                .maybe_egress(args.egress)
+                .maybe_burst(args.burst)
+                .exec()
+                .await?;
+        }
+        Ok(vm)
+    }
+}
+
+pub struct VmResizeMethods<'a> {
+    api: &'a Methods,
+}
+impl VmMethods<'_> {
+    pub fn resize(&self) -> VmResizeMethods<'_> {
+        VmResizeMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmResizeMethods<'_> {
+    /// Resize vm vcpu and ram.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn one(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        vcpu: Option<u64>,
+        vram: Option<String>,
+    ) -> Result<VmTable, VirshleError> {
+        let vm = Self::_one(ResizeVmArgs {
+            id,
+            name,
+            uuid,
+            vcpu,
+            vram,
+        })
+        .await?;
+        let res = VmTable::from(&vm).await?;
+        Ok(res)
+    }
+    async fn _one(args: ResizeVmArgs) -> Result<Vm, VirshleError> {
+        let mut vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        vm.resize()
+            .maybe_vcpu(args.vcpu)
+            .maybe_vram(args.vram)
+            .exec()
+            .await?;
+        Ok(vm)
+    }
+}
+
+pub struct VmDiskMethods<'a> {
+    api: &'a Methods,
+}
+impl VmMethods<'_> {
+    pub fn disk(&self) -> VmDiskMethods<'_> {
+        VmDiskMethods { api: self.api }
+    }
+}
+#[bon]
+impl VmDiskMethods<'_> {
+    /// Create a data disk and attach it to vm.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn add(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        disk_name: String,
+        disk_size: String,
+    ) -> Result<VmTable, VirshleError> {
+        let vm = Self::_add(AddDiskArgs {
+            id,
+            name,
+            uuid,
+            disk_name,
+            disk_size,
+        })
+        .await?;
+        let res = VmTable::from(&vm).await?;
+        Ok(res)
+    }
+    async fn _add(args: AddDiskArgs) -> Result<Vm, VirshleError> {
+        let mut vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        vm.add_disk()
+            .name(args.disk_name)
+            .size(args.disk_size)
+            .exec()
+            .await?;
+        Ok(vm)
+    }
+    /// Grow a vm disk.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn resize(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        disk_name: String,
+        disk_size: String,
+    ) -> Result<VmTable, VirshleError> {
+        let vm = Self::_resize(ResizeDiskArgs {
+            id,
+            name,
+            uuid,
+            disk_name,
+            disk_size,
+        })
+        .await?;
+        let res = VmTable::from(&vm).await?;
+        Ok(res)
+    }
+    async fn _resize(args: ResizeDiskArgs) -> Result<Vm, VirshleError> {
+        let mut vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        vm.resize_disk()
+            .name(args.disk_name)
+            .size(args.disk_size)
+            .exec()
+            .await?;
+        Ok(vm)
+    }
+    /// Change vm disk I/O tuning.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn update(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        disk_name: String,
+        direct: Option<bool>,
+        num_queues: Option<u64>,
+        queue_size: Option<u64>,
+        bandwidth: Option<String>,
+        iops: Option<u64>,
+    ) -> Result<(VmTable, bool), VirshleError> {
+        let (vm, restart) = Self::_update(UpdateDiskArgs {
+            id,
+            name,
+            uuid,
+            disk_name,
+            direct,
+            num_queues,
+            queue_size,
+            bandwidth,
+            iops,
+        })
+        .await?;
+        let res = VmTable::from(&vm).await?;
+        Ok((res, restart))
+    }
+    async fn _update(args: UpdateDiskArgs) -> Result<(Vm, bool), VirshleError> {
+        let mut vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        let (_, restart) = vm
+            .update_disk()
+            .name(args.disk_name)
+            .maybe_direct(args.direct)
+            .maybe_num_queues(args.num_queues)
+            .maybe_queue_size(args.queue_size)
+            .maybe_bandwidth(args.bandwidth)
+            .maybe_iops(args.iops)
+            .exec()
+            .await?;
+        Ok((vm, restart))
+    }
+    /// Check vm disks filesystems.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn check(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        disk_name: Option<String>,
+        repair: Option<bool>,
+    ) -> Result<Vec<PartitionReport>, VirshleError> {
+        Self::_check(CheckDiskArgs {
+            id,
+            name,
+            uuid,
+            disk_name,
+            repair,
+        })
+        .await
+    }
+    async fn _check(args: CheckDiskArgs) -> Result<Vec<PartitionReport>, VirshleError> {
+        let vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        vm.check_disks()
+            .maybe_name(args.disk_name)
+            .maybe_repair(args.repair)
+            .exec()
+            .await
+    }
+    /// Detach a data disk from vm and delete it.
+    #[builder(
+        finish_fn = exec,
+        on(String,into),
+        on(Option<String>,into)
+    )]
+    pub async fn remove(
+        &self,
+        id: Option<u64>,
+        name: Option<String>,
+        uuid: Option<Uuid>,
+        disk_name: String,
+    ) -> Result<VmTable, VirshleError> {
+        let vm = Self::_remove(RemoveDiskArgs {
+            id,
+            name,
+            uuid,
+            disk_name,
+        })
+        .await?;
+        let res = VmTable::from(&vm).await?;
+        Ok(res)
+    }
+    async fn _remove(args: RemoveDiskArgs) -> Result<Vm, VirshleError> {
+        let mut vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        vm.remove_disk().name(args.disk_name).exec().await?;
+        Ok(vm)
+    }
+}
+
+pub struct VmMigrateMethods<'a> {
+    api: &'a Methods,
+}
+impl VmMethods<'_> {
+    pub fn migrate(&self) -> VmMigrateMethods<'_> {
+        VmMigrateMethods { api: self.api }
+    }
+}
+impl VmMigrateMethods<'_> {
+    /// Make this node wait for an incoming vm.
+    /// Return the url to send the vm to.
+    pub async fn receive(&self, args: ReceiveMigrationArgs) -> Result<String, VirshleError> {
+        let mut vm = args.vm;
+        let receiver_url = vm
+            .receive_migration(args.account_uuid, &args.host, args.port, args.disk)
+            .await?;
+        Ok(receiver_url)
+    }
+    /// Remove a vm that awaits a migration.
+    pub async fn cancel(&self, args: GetVmArgs) -> Result<VmTable, VirshleError> {
+        let mut vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        let res = VmTable::from(&vm).await?;
+        vm.cancel_migration().await?;
+        Ok(res)
+    }
+    /// Send a vm to a node that awaits it.
+    pub async fn send(&self, args: SendMigrationArgs) -> Result<VmTable, VirshleError> {
+        let mut vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        let res = VmTable::from(&vm).await?;
+        vm.send_migration(&args.destination_url).await?;
+        Ok(res)
+    }
+}
+
+impl VmMethods<'_> {
+    /// Create copies of an existing vm.
+    /// Clones are created one after the other, as they read the same disks.
+    pub async fn clone_as(
+        &self,
+        args: CloneVmArgs,
+    ) -> Result<IndexMap<Status, Vec<VmTable>>, VirshleError> {
+        let vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        let ntimes = args.ntimes.unwrap_or(1);
+
+        // Clones are made one after another,
+        // as each one is checked against the node remaining capacity.
+        let mut clones: Vec<Vm> = vec![];
+        let mut results: Vec<Result<Result<Vm, VirshleError>, JoinError>> = vec![];
+        for i in 1..=ntimes {
+            // Expected clone, reported as failed if it couldn't be created.
+            let mut clone = Vm {
+                vcpu: vm.vcpu,
+                vram: vm.vram.clone(),
+                ..Default::default()
+            };
+            if let Some(name) = &args.clone_name {
+                clone.name = match ntimes {
+                    1 => name.to_owned(),
+                    _ => format!("{name}-{i}"),
+                };
+            }
+            let res = vm
+                .clone_as()
+                .name(&clone.name)
+                .uuid(clone.uuid)
+                .maybe_user_data(args.user_data.clone())
+                .exec()
+                .await;
+            if let Err(e) = &res {
+                error!("{}", e);
+            }
+            clones.push(clone);
+            results.push(Ok(res));
+        }
+        let res: IndexMap<Status, Vec<VmTable>> =
+            vm_bulk_results_to_hashmap(clones, results).await?;
+        Ok(res)
+    }
+
+    /// Bundle a stopped vm into a tar.zst archive,
+    /// to be streamed as the response body.
+    pub async fn export(
+        &self,
+        args: ExportVmArgs,
+    ) -> Result<impl Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static, VirshleError>
+    {
+        let vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        vm.export(args.snapshot).await
+    }
+    /// Recreate a vm from a tar.zst archive,
+    /// streamed as the request body.
+    pub async fn import(&self, args: ImportVmArgs, body: Body) -> Result<VmTable, VirshleError> {
+        let mut reader = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+        let vm = Vm::import(&mut reader, args.keep_uuid.unwrap_or_default(), args.name).await?;
+        let res = VmTable::from(&vm).await?;
+        Ok(res)
+    }
+}
+
+impl VmMethods<'_> {
+    /// Attach to a virtual machine serial console.
+    ///
+    /// Once the http connection is upgraded,
+    /// bytes are forwarded both ways between the client and the vm console socket,
+    /// until one of them hangs up.
+    pub async fn console(
+        &self,
+        args: GetVmArgs,
+        on_upgrade: OnUpgrade,
+    ) -> Result<(), VirshleError> {
+        let vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+
+        // Connect before switching protocols,
+        // so that the client gets a proper error if the vm is down.
+        let socket = vm.get_console_socket()?;
+        let mut console = match UnixStream::connect(&socket).await {
+            Ok(v) => v,
+            Err(e) => {
+                let message = format!("Couldn't reach vm {:#?} serial console.", vm.name);
+                let help = "Is the vm running?";
+                let err = WrapError::builder()
+                    .msg(&message)
+                    .help(help)
+                    .origin(miette::Error::from_err(e))
+                    .build();
+                return Err(err.into());
+            }
+        };
+
+        tokio::spawn(async move {
+            match on_upgrade.await {
+                Ok(upgraded) => {
+                    info!("attached to vm {} console", vm.name);
+                    let mut upgraded = TokioIo::new(upgraded);
+                    if let Err(e) = tokio::io::copy_bidirectional(&mut upgraded, &mut console).await
+                    {
+                        warn!("{}", e);
+                    }
+                    info!("detached from vm {} console", vm.name);
+                }
+                Err(e) => error!("{}", e),
+            }
+        });
+        Ok(())
+    }
+    /// Get detailed information about a VM,
+    /// from the underlying cloud-hypervisor process.
+    pub async fn get_ch_info(&self, args: GetVmArgs) -> Result<VmInfoResponse, VirshleError> {
+        let vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        let mut info = vm.vmm().api()?.info().await?;
+        info.net = vm.net.clone();
+        Ok(info)
+    }
+    pub async fn get_raw_ch_info(&self, args: GetVmArgs) -> Result<String, VirshleError> {
+        let vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        let info = vm.vmm().api()?._info().await?;
+        Ok(info.into())
+    }
+
+    pub async fn ping_ch(&self, args: GetVmArgs) -> Result<(), VirshleError> {
+        let vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        vm.vmm().api()?.ping().await
+    }
+    pub async fn get_definition(&self, args: GetVmArgs) -> Result<Vm, VirshleError> {
+        let vm = Vm::database()
+            .await?
+            .one()
+            .maybe_id(args.id)
+            .maybe_name(args.name)
+            .maybe_uuid(args.uuid)
+            .get()
+            .await?;
+        Ok(vm)
     }
     pub async fn get_vsock_path(&self, args: GetVmArgs) -> Result<String, VirshleError> {
         let vm = Vm::database()
@@ -15,11 +15,11 @@ use std::path::PathBuf;
 use tokio::net::UnixListener;
 
 use bon::bon;
-use virshle_core::config::Config;
+use virshle_core::{config::Config, database, Vm};
 
 // Error Handling
 use miette::Result;
-use tracing::info;
+use tracing::{error, info};
 use virshle_error::VirshleError;
 
 #[derive(Clone)]
@@ -47,8 +47,19 @@ impl Server {
     /// Run REST api.
     pub async fn serve(&mut self) -> Result<(), VirshleError> {
         let socket_path = Server::get_socket()?;
+        // Bring an older database schema up to date before serving requests.
+        database::migrate_db().await?;
         self.make_router().await?;
 
+        // Boot back autostart vms without delaying the api.
+        tokio::spawn(async {
+            if let Err(e) = Vm::autostart_all().await {
+                error!("[autostart] {}", e);
+            }
+        });
+        // Re-apply vms flows as they get their leases.
+        tokio::spawn(Vm::watch_leases());
+
         info!("Server listening on socket {}", &socket_path);
         tokio_scoped::scope(|s| {
             s.spawn(async {
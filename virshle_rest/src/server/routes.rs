@@ -1,8 +1,12 @@
 use crate::commons::*;
 use crate::server::Server;
 use axum::{
-    extract::{Extension, Path, Query, State},
-    http::Request,
+    body::Body,
+    extract::{Extension, Path, Query, Request, State},
+    http::{
+        header::{CONNECTION, CONTENT_TYPE, UPGRADE},
+        StatusCode,
+    },
     middleware::map_response,
     response::{IntoResponse, Response},
     routing::{get, post, put},
@@ -16,13 +20,16 @@ use tokio::sync::RwLock;
 use indexmap::IndexMap;
 use pipelight_exec::Status;
 use tower_http::trace::TraceLayer;
+use uuid::Uuid;
 use virshle_core::{
     config::{Config, UserData, VmTemplate, VmTemplateTable},
     hypervisor::{
-        vm::{Vm, VmInfo, VmTable},
+        vm::{ShutdownMode, Snapshot, Vm, VmInfo, VmTable},
         vmm::types::{VmInfoResponse, VmState},
+        PartitionReport,
     },
     peer::{HostInfo, NodeInfo, Peer},
+    Image,
 };
 // Error handling
 use miette::Result;
@@ -92,6 +99,43 @@ impl Server {
                     },
                 ),
             )
+            // Image
+            .route(
+                "/image/all",
+                get(async |State(server): State<Server>| {
+                    Result::<Json<Vec<Image>>, VirshleError>::Ok(Json(
+                        server.api()?.image().get_many().await?,
+                    ))
+                }),
+            )
+            .route(
+                "/image/pull",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<PullImageArgs>| {
+                        Result::<Json<Image>, VirshleError>::Ok(Json(
+                            server.api()?.image().pull(params).await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/image/rm",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<GetImageArgs>| {
+                        Result::<Json<Image>, VirshleError>::Ok(Json(
+                            server.api()?.image().rm(params).await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/image/prune",
+                put(async |State(server): State<Server>| {
+                    Result::<Json<Vec<Image>>, VirshleError>::Ok(Json(
+                        server.api()?.image().prune().await?,
+                    ))
+                }),
+            )
             // Vm
             .route(
                 "/vm/info",
@@ -275,8 +319,9 @@ impl Server {
             .route(
                 "/vm/shutdown",
                 put(
-                    async move |State(server): State<Server>, Json(params): Json<GetVmArgs>| {
-                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                    async move |State(server): State<Server>,
+                                Json(params): Json<ShutdownVmArgs>| {
+                        Result::<Json<(VmTable, ShutdownMode)>, VirshleError>::Ok(Json(
                             server
                                 .api()?
                                 .vm()
@@ -285,6 +330,8 @@ impl Server {
                                 .maybe_id(params.id)
                                 .maybe_name(params.name)
                                 .maybe_uuid(params.uuid)
+                                .maybe_timeout(params.timeout)
+                                .maybe_force(params.force)
                                 .exec()
                                 .await?,
                         ))
@@ -293,13 +340,147 @@ impl Server {
             )
             .route(
                 "/vm/shutdown.many",
+                put(
+                    async move |State(server): State<Server>,
+                                Json(params): Json<ShutdownManyVmArgs>| {
+                        Result::<
+                            Json<(IndexMap<Status, Vec<VmTable>>, IndexMap<Uuid, ShutdownMode>)>,
+                            VirshleError,
+                        >::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .shutdown()
+                                .many()
+                                .maybe_state(params.vm_state)
+                                .maybe_account(params.account_uuid)
+                                .maybe_timeout(params.timeout)
+                                .maybe_force(params.force)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/reboot",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<GetVmArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .reboot()
+                                .one()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/reboot.many",
                 put(
                     async move |State(server): State<Server>, Json(params): Json<GetManyVmArgs>| {
                         Result::<Json<IndexMap<Status, Vec<VmTable>>>, VirshleError>::Ok(Json(
                             server
                                 .api()?
                                 .vm()
-                                .shutdown()
+                                .reboot()
+                                .many()
+                                .maybe_state(params.vm_state)
+                                .maybe_account(params.account_uuid)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/console",
+                get(
+                    async move |State(server): State<Server>,
+                                Query(params): Query<GetVmArgs>,
+                                mut request: Request| {
+                        let on_upgrade = hyper::upgrade::on(&mut request);
+                        server.api()?.vm().console(params, on_upgrade).await?;
+                        Result::<Response, VirshleError>::Ok(
+                            Response::builder()
+                                .status(StatusCode::SWITCHING_PROTOCOLS)
+                                .header(CONNECTION, "upgrade")
+                                .header(UPGRADE, CONSOLE_PROTOCOL)
+                                .body(Body::empty())?,
+                        )
+                    },
+                ),
+            )
+            .route(
+                "/vm/clone",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<CloneVmArgs>| {
+                        Result::<Json<IndexMap<Status, Vec<VmTable>>>, VirshleError>::Ok(Json(
+                            server.api()?.vm().clone_as(params).await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/export",
+                get(
+                    async move |State(server): State<Server>,
+                                Query(params): Query<ExportVmArgs>| {
+                        let stream = server.api()?.vm().export(params).await?;
+                        Result::<Response, VirshleError>::Ok(
+                            Response::builder()
+                                .header(CONTENT_TYPE, "application/zstd")
+                                .body(Body::from_stream(stream))?,
+                        )
+                    },
+                ),
+            )
+            .route(
+                "/vm/import",
+                put(
+                    async move |State(server): State<Server>,
+                                Query(params): Query<ImportVmArgs>,
+                                body: Body| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server.api()?.vm().import(params, body).await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/pause",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<GetVmArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .pause()
+                                .one()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/pause.many",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<GetManyVmArgs>| {
+                        Result::<Json<IndexMap<Status, Vec<VmTable>>>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .pause()
                                 .many()
                                 .maybe_state(params.vm_state)
                                 .maybe_account(params.account_uuid)
@@ -309,6 +490,261 @@ impl Server {
                     },
                 ),
             )
+            .route(
+                "/vm/resume",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<GetVmArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .resume()
+                                .one()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/resume.many",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<GetManyVmArgs>| {
+                        Result::<Json<IndexMap<Status, Vec<VmTable>>>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .resume()
+                                .many()
+                                .maybe_state(params.vm_state)
+                                .maybe_account(params.account_uuid)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/snapshot",
+                put(
+                    async move |State(server): State<Server>,
+                                Json(params): Json<SnapshotVmArgs>| {
+                        Result::<Json<Snapshot>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .snapshot()
+                                .one()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .tag(params.tag)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/snapshot.list",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<GetVmArgs>| {
+                        Result::<Json<Vec<Snapshot>>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .snapshot()
+                                .get()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/restore",
+                put(
+                    async move |State(server): State<Server>,
+                                Json(params): Json<SnapshotVmArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .restore()
+                                .one()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .tag(params.tag)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/update",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<UpdateVmArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .update()
+                                .one()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .maybe_autostart(params.autostart)
+                                .maybe_security_groups(params.security_groups)
+                                .maybe_net_name(params.net_name)
+                                .maybe_ingress(params.ingress)
+                                .maybe_egress(params.egress)
+                                .maybe_burst(params.burst)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/resize",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<ResizeVmArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .resize()
+                                .one()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .maybe_vcpu(params.vcpu)
+                                .maybe_vram(params.vram)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/disk/add",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<AddDiskArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .disk()
+                                .add()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .disk_name(params.disk_name)
+                                .disk_size(params.disk_size)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/disk/resize",
+                put(
+                    async move |State(server): State<Server>,
+                                Json(params): Json<ResizeDiskArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .disk()
+                                .resize()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .disk_name(params.disk_name)
+                                .disk_size(params.disk_size)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/disk/update",
+                put(
+                    async move |State(server): State<Server>,
+                                Json(params): Json<UpdateDiskArgs>| {
+                        Result::<Json<(VmTable, bool)>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .disk()
+                                .update()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .disk_name(params.disk_name)
+                                .maybe_direct(params.direct)
+                                .maybe_num_queues(params.num_queues)
+                                .maybe_queue_size(params.queue_size)
+                                .maybe_bandwidth(params.bandwidth)
+                                .maybe_iops(params.iops)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/disk/check",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<CheckDiskArgs>| {
+                        Result::<Json<Vec<PartitionReport>>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .disk()
+                                .check()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .maybe_disk_name(params.disk_name)
+                                .maybe_repair(params.repair)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/disk/rm",
+                put(
+                    async move |State(server): State<Server>,
+                                Json(params): Json<RemoveDiskArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server
+                                .api()?
+                                .vm()
+                                .disk()
+                                .remove()
+                                .maybe_id(params.id)
+                                .maybe_name(params.name)
+                                .maybe_uuid(params.uuid)
+                                .disk_name(params.disk_name)
+                                .exec()
+                                .await?,
+                        ))
+                    },
+                ),
+            )
             .route(
                 "/vm/delete",
                 put(
@@ -346,6 +782,48 @@ impl Server {
                     },
                 ),
             )
+            .route(
+                "/vm/get_definition",
+                post(
+                    async move |State(server): State<Server>, Json(params): Json<GetVmArgs>| {
+                        Result::<Json<Vm>, VirshleError>::Ok(Json(
+                            server.api()?.vm().get_definition(params).await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/receive_migration",
+                put(
+                    async move |State(server): State<Server>,
+                                Json(params): Json<ReceiveMigrationArgs>| {
+                        Result::<Json<String>, VirshleError>::Ok(Json(
+                            server.api()?.vm().migrate().receive(params).await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/cancel_migration",
+                put(
+                    async move |State(server): State<Server>, Json(params): Json<GetVmArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server.api()?.vm().migrate().cancel(params).await?,
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/vm/send_migration",
+                put(
+                    async move |State(server): State<Server>,
+                                Json(params): Json<SendMigrationArgs>| {
+                        Result::<Json<VmTable>, VirshleError>::Ok(Json(
+                            server.api()?.vm().migrate().send(params).await?,
+                        ))
+                    },
+                ),
+            )
             .route(
                 "/vm/get_vsock_path",
                 post(